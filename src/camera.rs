@@ -1,14 +1,81 @@
-use crate::na::{Vector3, Perspective3, Isometry3, Rotation, Rotation3};
-use crate::ray::Ray;
+use crate::na::{Vector3, Vector2, Matrix3, Perspective3, Isometry3, Rotation, Rotation3, UnitQuaternion};
+use crate::ray::{Ray, RayDifferential};
 use std::f64;
 
-use crate::geometry::random_point_on_disc;
+use crate::geometry::{random_point_on_disc, random_point_on_polygon, rand, cumulative, sample_cumulative};
+use crate::color::Color;
 
 
 pub trait Camera {
     // Given a point (x=0-1, y=0-1) as a proportion of the way into the previously sized image
     // and (sx=0-1, sy=0-1), subsamples within that pixel, generate a ray for that pixel 
     fn get_ray(&self, x: f64, y: f64, sx: f64, sy: f64) -> Ray;
+
+    /// As get_ray, for a ray cast at a time (0 - 1) during the motion of the scene
+    fn get_ray_at(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64) -> Ray {
+        Ray { time, ..self.get_ray(x, y, sx, sy) }
+    }
+
+    /// As get_ray_at, along with how much of each colour the ray carries. Cameras that bend
+    /// colours differently (eg. with chromatic aberration) trace each ray for a single colour.
+    fn get_sample(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64) -> (Ray, Color) {
+        (self.get_ray_at(x, y, sx, sy, time), Color::white())
+    }
+
+    /// As get_sample, along with the ray differentials: rays through the image a step of
+    /// `pixel` (width, height, as a proportion of the image) across and up.
+    fn get_sample_differential(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64, pixel: (f64, f64)) -> (Ray, RayDifferential, Color) {
+        let (r, weight) = self.get_sample(x, y, sx, sy, time);
+        let d = RayDifferential {
+            rx: self.get_ray_at(x + pixel.0, y, sx, sy, time),
+            ry: self.get_ray_at(x, y + pixel.1, sx, sy, time),
+        };
+        (r, d, weight)
+    }
+
+    /// Scale applied to the radiance reaching the image
+    fn exposure(&self) -> f64 {
+        1.
+    }
+
+    /// Inverse of get_ray: where on the image (x=0-1, y=0-1) a point in the scene appears, or
+    /// None if the camera can't see that way. Points out of frame may be outside 0-1.
+    fn get_coord_for_point(&self, point: Vector3<f64>) -> Option<(f64, f64)>;
+}
+
+/// Camera axes (x, y, z) for a camera at location looking at lookat. z points into the scene.
+fn camera_basis(lookat: Vector3<f64>, location: Vector3<f64>, up: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>, Vector3<f64>) {
+    let camz = (lookat - location).normalize();
+    let mut camx = up.cross(&camz).normalize();
+    if camx.y.is_nan() {
+        // Looking vertical. In this situation we arbitrarily pick an axis.
+        camx = Vector3::new(1., 0., 0.);
+    }
+    let camy = camx.cross(
+            &(Vector3::new(0f64,0f64,0f64) - camz)
+            ).normalize();
+    (camx, camy, camz)
+}
+
+/// Inverse of a pinhole's view: the offset from the centre of the image (-0.5 - 0.5) of the
+/// ray from location through point, for a ray direction camz + camx * u * tax + camy * v * tay.
+fn pinhole_coord(
+        location: &Vector3<f64>,
+        camx: &Vector3<f64>,
+        camy: &Vector3<f64>,
+        camz: &Vector3<f64>,
+        tax: f64,
+        tay: f64,
+        point: Vector3<f64>,
+    ) -> Option<(f64, f64)> {
+    let d = point - location;
+    let depth = d.dot(camz);
+    if depth <= 0. {
+        return None;
+    }
+    let u = d.dot(camx) / (camx.norm_squared() * depth * tax);
+    let v = d.dot(camy) / (camy.norm_squared() * depth * tay);
+    Some((u, v))
 }
 
 pub struct SimpleCamera {
@@ -23,15 +90,10 @@ pub struct SimpleCamera {
 }
 
 impl SimpleCamera {
-    pub fn new(lookat: Vector3<f64>, location:Vector3<f64>, up:Vector3<f64>, angle: f64, height: u32, width: u32) -> SimpleCamera {
-        let camz = (lookat - location).normalize();
-        let camx = up.cross(&camz).normalize();
-        let camy = camx.cross(
-                &(Vector3::new(0f64,0f64,0f64) - camz)
-                ).normalize();
-
+    pub fn new(lookat: Vector3<f64>, location:Vector3<f64>, up:Vector3<f64>, angle: f64, width: u32, height: u32) -> SimpleCamera {
+        let (camx, camy, camz) = camera_basis(lookat, location, up);
 
-        let aspect_ratio = (height as f64) / (width as f64);
+        let aspect_ratio = (width as f64) / (height as f64);
 
         //let viewPlaneHalfWidth= (fieldOfView / 2.).tan()
         //let viewPlaneHalfHeight = aspectRatio*viewPlaneHalfWidth
@@ -56,10 +118,12 @@ impl Camera for SimpleCamera {
         let ydir = self.camy * (y + sy - 0.5) * self.tay;
         let dest = self.camz + xdir + ydir;
 
-        Ray {
-            ro: self.location,
-            rd: dest
-        }
+        Ray::new(self.location, dest)
+    }
+
+    fn get_coord_for_point(&self, point: Vector3<f64>) -> Option<(f64, f64)> {
+        let (u, v) = pinhole_coord(&self.location, &self.camx, &self.camy, &self.camz, self.tax, self.tay, point)?;
+        Some((u + 0.5, v + 0.5))
     }
 }
 
@@ -164,8 +228,20 @@ pub struct FlatLensCamera {
     tax: f64,
     tay: f64,
 
+    /// Radius of the lens
     aperture: f64,
+    aperture_shape: Aperture,
+    /// Distance to the plane in focus
     focus: f64,
+    exposure: f64,
+
+    aspect_ratio: f64,
+    /// Radial distortion coefficients (k1, k2). Positive for barrel, negative for pincushion.
+    distortion: (f64, f64),
+    /// Difference in magnification of red and blue from green
+    chromatic_aberration: f64,
+    /// How far the rear of the lens cuts into the aperture off axis (0 - 1)
+    cats_eye: f64,
 }
 
 
@@ -175,22 +251,13 @@ impl FlatLensCamera {
             location:Vector3<f64>,
             up:Vector3<f64>,
             angle: f64,
-            height: u32,
             width: u32,
+            height: u32,
             aperture: f64
          ) -> FlatLensCamera {
-        let camz = (lookat - location).normalize();
-        let mut camx = up.cross(&camz).normalize();
-        if camx.y.is_nan() {
-            // Looking vertical. In this situation we arbitrarily pick an axis.
-            camx = Vector3::new(1., 0., 0.);
-        }
-        let camy = camx.cross(
-                &(Vector3::new(0f64,0f64,0f64) - camz)
-                ).normalize();
-
+        let (camx, camy, camz) = camera_basis(lookat, location, up);
 
-        let aspect_ratio = (height as f64) / (width as f64);
+        let aspect_ratio = (width as f64) / (height as f64);
         let focus = (lookat - location).norm();
 
         //let viewPlaneHalfWidth= (fieldOfView / 2.).tan()
@@ -207,32 +274,673 @@ impl FlatLensCamera {
             tay: angle.tan(),
         
             aperture,
+            aperture_shape: Aperture::Circle,
             focus,
+            exposure: 1.,
+
+            aspect_ratio,
+            distortion: (0., 0.),
+            chromatic_aberration: 0.,
+            cats_eye: 0.,
         }
     }
+
+    /// A camera described the way a photographer would. The field of view comes from the
+    /// focal length and sensor width (in mm), cropped to the image's aspect ratio, and the
+    /// lens opening from the focal length and f-number. `meters_per_unit` gives the scale of
+    /// the scene, so that depth of field is right for its size.
+    #[allow(clippy::too_many_arguments)]
+    pub fn physical(
+            lookat: Vector3<f64>,
+            location: Vector3<f64>,
+            up: Vector3<f64>,
+            focal_length: f64,
+            sensor_width: f64,
+            f_number: f64,
+            meters_per_unit: f64,
+            width: u32,
+            height: u32,
+        ) -> FlatLensCamera {
+        let sensor_height = sensor_width * height as f64 / width as f64;
+        // The view is tan(angle) high one unit in front of the camera
+        let angle = (sensor_height / focal_length).atan();
+        let aperture = focal_length / (2. * f_number) / 1000. / meters_per_unit;
+        FlatLensCamera::new(lookat, location, up, angle, width, height, aperture)
+    }
+
+    /// Focus at a distance other than lookat
+    pub fn with_focus(mut self, focus: f64) -> FlatLensCamera {
+        self.focus = focus;
+        self
+    }
+
+    pub fn with_exposure(mut self, exposure: f64) -> FlatLensCamera {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn with_aperture_shape(mut self, shape: Aperture) -> FlatLensCamera {
+        self.aperture_shape = shape;
+        self
+    }
+
+    pub fn with_distortion(mut self, k1: f64, k2: f64) -> FlatLensCamera {
+        self.distortion = (k1, k2);
+        self
+    }
+
+    pub fn with_chromatic_aberration(mut self, amount: f64) -> FlatLensCamera {
+        self.chromatic_aberration = amount;
+        self
+    }
+
+    pub fn with_cats_eye(mut self, amount: f64) -> FlatLensCamera {
+        self.cats_eye = amount;
+        self
+    }
+
+    /// Scale from the centre of the image that radial distortion applies at (u, v)
+    fn distortion_scale(&self, u: f64, v: f64) -> f64 {
+        let r2 = 4. * ((u * self.aspect_ratio).powi(2) + v * v);
+        1. + self.distortion.0 * r2 + self.distortion.1 * r2 * r2
+    }
+
+    /// The point in focus seen (u, v) from the centre of the image
+    fn focal_point(&self, u: f64, v: f64) -> Vector3<f64> {
+        let pinhole_dest = self.camz + self.camx * u * self.tax + self.camy * v * self.tay;
+        self.location + pinhole_dest * self.focus
+    }
+
+    /// The ray through the centre of the lens, which is in focus at every distance
+    pub fn centre_ray(&self, x: f64, y: f64) -> Ray {
+        let xdir = self.camx * (x - 0.5) * self.tax;
+        let ydir = self.camy * (y - 0.5) * self.tay;
+        Ray::new(self.location, (self.camz + xdir + ydir).normalize())
+    }
 }
 
 impl Camera for FlatLensCamera {
     fn get_ray(&self, x: f64, y: f64, sx: f64, sy: f64) -> Ray {
+        self.get_sample(x, y, sx, sy, 0.).0
+    }
 
-        let xdir = self.camx * (x + sx - 0.5) * self.tax;
-        let ydir = self.camy * (y + sy - 0.5) * self.tay;
-        let pinhole_dest = self.camz + xdir + ydir;
+    fn get_sample(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64) -> (Ray, Color) {
+        // Position on the image from the centre, and its distance from it (1 at the top edge)
+        let mut u = x + sx - 0.5;
+        let mut v = y + sy - 0.5;
 
-        let focal_point = self.location + pinhole_dest * self.focus;
-        let point_lens = random_point_on_disc(self.aperture); 
-        let ro = self.location + Vector3::new(point_lens[0], point_lens[1], 0.0);
+        let mut scale = self.distortion_scale(u, v);
+        let mut weight = Color::white();
+        if self.chromatic_aberration != 0. {
+            let channel = ((rand() * 3.) as usize).min(2);
+            scale *= 1. + self.chromatic_aberration * (1. - channel as f64);
+            weight = match channel {
+                0 => Color::new(3., 0., 0.),
+                1 => Color::new(0., 3., 0.),
+                _ => Color::new(0., 0., 3.),
+            };
+        }
+        u *= scale;
+        v *= scale;
 
-        Ray {
+        let focal_point = self.focal_point(u, v);
+        let point_lens = self.aperture_shape.sample(self.aperture);
+        if self.cats_eye > 0. {
+            // Off axis we look through the lens at an angle, and the back of the barrel hides
+            // the side of the aperture away from the centre.
+            let shift = Vector2::new(u * self.aspect_ratio, v) * 2. * self.cats_eye * self.aperture;
+            if (point_lens + shift).norm() > self.aperture {
+                return (Ray { ro: self.location, rd: Vector3::zeros(), time }, weight);
+            }
+        }
+        // The lens lies in the camera's image plane
+        let ro = self.location + self.camx.normalize() * point_lens[0] + self.camy * point_lens[1];
+
+        (Ray {
             ro,
-            rd: (focal_point - ro).normalize()
+            rd: (focal_point - ro).normalize(),
+            time,
+        }, weight)
+    }
+
+    fn get_sample_differential(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64, pixel: (f64, f64)) -> (Ray, RayDifferential, Color) {
+        let (r, weight) = self.get_sample(x, y, sx, sy, time);
+        // Through the same point on the lens, to where the neighbouring pixels are in focus
+        let aim = |u: f64, v: f64| {
+            let scale = self.distortion_scale(u, v);
+            Ray { ro: r.ro, rd: (self.focal_point(u * scale, v * scale) - r.ro).normalize(), time }
+        };
+        let (u, v) = (x + sx - 0.5, y + sy - 0.5);
+        (r, RayDifferential { rx: aim(u + pixel.0, v), ry: aim(u, v + pixel.1) }, weight)
+    }
+
+    fn exposure(&self) -> f64 {
+        self.exposure
+    }
+
+    fn get_coord_for_point(&self, point: Vector3<f64>) -> Option<(f64, f64)> {
+        let (du, dv) = pinhole_coord(&self.location, &self.camx, &self.camy, &self.camz, self.tax, self.tay, point)?;
+        // Undo the distortion, which scales the radius r to r (1 + k1 r^2 + k2 r^4). This has
+        // no closed form inverse, so solve for r with Newton's method.
+        let (k1, k2) = self.distortion;
+        let distorted = 2. * ((du * self.aspect_ratio).powi(2) + dv * dv).sqrt();
+        let mut r = distorted;
+        for _ in 0 .. 20 {
+            let r2 = r * r;
+            r -= (r * (1. + k1 * r2 + k2 * r2 * r2) - distorted) / (1. + 3. * k1 * r2 + 5. * k2 * r2 * r2);
+        }
+        let scale = if distorted > 0. { r / distorted } else { 1. };
+        Some((du * scale + 0.5, dv * scale + 0.5))
+    }
+}
+
+/// Shape of the lens opening, which out of focus highlights (bokeh) take on.
+pub enum Aperture {
+    Circle,
+    /// An iris of straight blades, with a corner at `rotation` radians from the camera's x axis
+    Polygon { blades: usize, rotation: f64 },
+    /// An image the width of the aperture, which light passes through in proportion to its
+    /// brightness
+    Mask { width: usize, height: usize, cdf: Vec<f64> },
+}
+
+impl Aperture {
+    pub fn mask(width: usize, height: usize, pixels: impl Iterator<Item=f64>) -> Aperture {
+        Aperture::Mask { width, height, cdf: cumulative(pixels.map(|p| p.max(0.))) }
+    }
+
+    pub fn from_image(pth: &str) -> Aperture {
+        let img = match image::open(pth) {
+            Ok(i) => i.to_luma32f(),
+            Err(e) => panic!("Could not load {}: {:?}", pth, e),
+        };
+        Aperture::mask(img.width() as usize, img.height() as usize, img.pixels().map(|p| p[0] as f64))
+    }
+
+    /// A point on the aperture of a lens with the given radius
+    pub fn sample(&self, radius: f64) -> Vector2<f64> {
+        match self {
+            Aperture::Circle => random_point_on_disc(radius),
+            Aperture::Polygon { blades, rotation } => random_point_on_polygon(radius, *blades, *rotation),
+            Aperture::Mask { width, height, cdf } => match sample_cumulative(cdf) {
+                Some(i) => {
+                    let px = ((i % width) as f64 + rand()) / *width as f64;
+                    let py = ((i / width) as f64 + rand()) / *height as f64;
+                    Vector2::new(px * 2. - 1., 1. - py * 2.) * radius
+                },
+                None => Vector2::zeros(),
+            },
+        }
+    }
+}
+
+/// Exposure relative to the "sunny 16" rule, under which a sunlit scene (which our scenes are
+/// lit like) is correctly exposed at f/16 with a shutter speed of 1 / ISO seconds.
+pub fn photographic_exposure(iso: f64, shutter: f64, f_number: f64) -> f64 {
+    shutter * iso * 256. / (f_number * f_number)
+}
+
+/// A parallel projection: every ray points the same way, from a point on the view plane, so
+/// objects don't get smaller with distance and parallel lines stay parallel.
+pub struct OrthographicCamera {
+    location: Vector3<f64>,
+
+    camx: Vector3<f64>,
+    camy: Vector3<f64>,
+    camz: Vector3<f64>,
+}
+
+impl OrthographicCamera {
+    /// `size` is the height of the view in world units
+    pub fn new(
+            lookat: Vector3<f64>,
+            location: Vector3<f64>,
+            up: Vector3<f64>,
+            size: f64,
+            width: u32,
+            height: u32,
+        ) -> OrthographicCamera {
+        let (camx, camy, camz) = camera_basis(lookat, location, up);
+        let aspect_ratio = (width as f64) / (height as f64);
+        OrthographicCamera {
+            location,
+            camx: camx * size * aspect_ratio,
+            camy: camy * size,
+            camz,
+        }
+    }
+
+    /// The classic isometric view: looking down at lookat from above and to the front right,
+    /// so that the x, y and z axes are drawn 120 degrees apart and equally foreshortened.
+    pub fn isometric(lookat: Vector3<f64>, distance: f64, size: f64, width: u32, height: u32) -> OrthographicCamera {
+        let location = lookat + Vector3::new(1., 1., -1.).normalize() * distance;
+        OrthographicCamera::new(lookat, location, Vector3::new(0., 1., 0.), size, width, height)
+    }
+}
+
+impl Camera for OrthographicCamera {
+    fn get_ray(&self, x: f64, y: f64, sx: f64, sy: f64) -> Ray {
+        let xdir = self.camx * (x + sx - 0.5);
+        let ydir = self.camy * (y + sy - 0.5);
+        Ray::new(self.location + xdir + ydir, self.camz)
+    }
+
+    fn get_coord_for_point(&self, point: Vector3<f64>) -> Option<(f64, f64)> {
+        let d = point - self.location;
+        if d.dot(&self.camz) < 0. {
+            return None;
+        }
+        Some((d.dot(&self.camx) / self.camx.norm_squared() + 0.5, d.dot(&self.camy) / self.camy.norm_squared() + 0.5))
+    }
+}
+
+/// Map a point on the image (-0.5 - 0.5) centred on the view direction to a direction in the
+/// camera's space.
+fn camera_direction(camx: &Vector3<f64>, camy: &Vector3<f64>, camz: &Vector3<f64>, d: Vector3<f64>) -> Vector3<f64> {
+    camx * d.x + camy * d.y + camz * d.z
+}
+
+/// Inverse of camera_direction, for unit axes
+fn camera_space(camx: &Vector3<f64>, camy: &Vector3<f64>, camz: &Vector3<f64>, d: Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(d.dot(camx), d.dot(camy), d.dot(camz))
+}
+
+/// A full 360 x 180 degree view, with longitude along x and latitude along y (a lat-long or
+/// equirectangular image). The centre of the image looks at lookat. Rendered with a 2:1 aspect
+/// ratio this is the usual format for environment maps and VR panoramas.
+pub struct EquirectangularCamera {
+    location: Vector3<f64>,
+
+    camx: Vector3<f64>,
+    camy: Vector3<f64>,
+    camz: Vector3<f64>,
+}
+
+impl EquirectangularCamera {
+    pub fn new(lookat: Vector3<f64>, location: Vector3<f64>, up: Vector3<f64>) -> EquirectangularCamera {
+        let (camx, camy, camz) = camera_basis(lookat, location, up);
+        EquirectangularCamera { location, camx, camy, camz }
+    }
+}
+
+impl Camera for EquirectangularCamera {
+    fn get_ray(&self, x: f64, y: f64, sx: f64, sy: f64) -> Ray {
+        let longitude = (x + sx - 0.5) * 2. * f64::consts::PI;
+        let latitude = (y + sy - 0.5) * f64::consts::PI;
+        let d = Vector3::new(
+            latitude.cos() * longitude.sin(),
+            latitude.sin(),
+            latitude.cos() * longitude.cos());
+        Ray::new(self.location, camera_direction(&self.camx, &self.camy, &self.camz, d))
+    }
+
+    fn get_coord_for_point(&self, point: Vector3<f64>) -> Option<(f64, f64)> {
+        let d = camera_space(&self.camx, &self.camy, &self.camz, point - self.location).try_normalize(0.)?;
+        let longitude = d.x.atan2(d.z);
+        let latitude = d.y.asin();
+        Some((longitude / (2. * f64::consts::PI) + 0.5, latitude / f64::consts::PI + 0.5))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FisheyeProjection {
+    /// Distance from the centre of the image is proportional to the angle from the view direction
+    Equidistant,
+    /// Area on the image is proportional to solid angle, like most real fisheye lenses
+    Equisolid,
+}
+
+/// A fisheye lens, with a circular image that fits in the shorter side of the frame. Pixels
+/// outside the circle see nothing.
+pub struct FisheyeCamera {
+    location: Vector3<f64>,
+
+    camx: Vector3<f64>,
+    camy: Vector3<f64>,
+    camz: Vector3<f64>,
+
+    /// Field of view across the image circle, in radians (up to 2pi)
+    fov: f64,
+    projection: FisheyeProjection,
+    /// Scale from image coordinates to the radius of the circle
+    scalex: f64,
+    scaley: f64,
+}
+
+impl FisheyeCamera {
+    pub fn new(
+            lookat: Vector3<f64>,
+            location: Vector3<f64>,
+            up: Vector3<f64>,
+            fov: f64,
+            projection: FisheyeProjection,
+            width: u32,
+            height: u32,
+        ) -> FisheyeCamera {
+        let (camx, camy, camz) = camera_basis(lookat, location, up);
+        let shortest = width.min(height) as f64;
+        FisheyeCamera {
+            location,
+            camx, camy, camz,
+            fov: fov.min(2. * f64::consts::PI),
+            projection,
+            scalex: 2. * width as f64 / shortest,
+            scaley: 2. * height as f64 / shortest,
         }
+    }
+
+    /// Angle from the view direction for a radius on the image circle (0 - 1)
+    fn theta(&self, r: f64) -> f64 {
+        match self.projection {
+            FisheyeProjection::Equidistant => r * self.fov / 2.,
+            FisheyeProjection::Equisolid => 2. * (r * (self.fov / 4.).sin()).asin(),
+        }
+    }
+
+    /// Inverse of theta
+    fn radius(&self, theta: f64) -> f64 {
+        match self.projection {
+            FisheyeProjection::Equidistant => theta * 2. / self.fov,
+            FisheyeProjection::Equisolid => (theta / 2.).sin() / (self.fov / 4.).sin(),
+        }
+    }
+}
+
+impl Camera for FisheyeCamera {
+    fn get_ray(&self, x: f64, y: f64, sx: f64, sy: f64) -> Ray {
+        let u = (x + sx - 0.5) * self.scalex;
+        let v = (y + sy - 0.5) * self.scaley;
+        let r = (u * u + v * v).sqrt();
+        if r > 1. {
+            return Ray::new(self.location, Vector3::zeros());
+        }
+        let theta = self.theta(r);
+        let phi = v.atan2(u);
+        let d = Vector3::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+        Ray::new(self.location, camera_direction(&self.camx, &self.camy, &self.camz, d))
+    }
+
+    fn get_coord_for_point(&self, point: Vector3<f64>) -> Option<(f64, f64)> {
+        let d = camera_space(&self.camx, &self.camy, &self.camz, point - self.location).try_normalize(0.)?;
+        let theta = d.z.clamp(-1., 1.).acos();
+        if theta > self.fov / 2. {
+            return None;
+        }
+        let r = self.radius(theta);
+        let phi = d.y.atan2(d.x);
+        Some((r * phi.cos() / self.scalex + 0.5, r * phi.sin() / self.scaley + 0.5))
+    }
+}
+
+/// The six 90 degree views along the camera's axes, laid out in a 3 x 2 grid:
+///
+/// ```text
+/// +---------+---------+---------+
+/// |  right  |  left   |   up    |
+/// +---------+---------+---------+
+/// |  down   |  front  |  back   |
+/// +---------+---------+---------+
+/// ```
+///
+/// The front face looks at lookat, and the side faces share its up. Render with a 3:2 aspect
+/// ratio so the faces are square.
+pub struct CubemapCamera {
+    location: Vector3<f64>,
+
+    camx: Vector3<f64>,
+    camy: Vector3<f64>,
+    camz: Vector3<f64>,
+}
+
+impl CubemapCamera {
+    pub fn new(lookat: Vector3<f64>, location: Vector3<f64>, up: Vector3<f64>) -> CubemapCamera {
+        let (camx, camy, camz) = camera_basis(lookat, location, up);
+        CubemapCamera { location, camx, camy, camz }
+    }
+
+    /// Forward, right and up axes of each face, in camera space, in layout order.
+    fn face(i: usize) -> (Vector3<f64>, Vector3<f64>, Vector3<f64>) {
+        let x = Vector3::new(1., 0., 0.);
+        let y = Vector3::new(0., 1., 0.);
+        let z = Vector3::new(0., 0., 1.);
+        match i {
+            0 => (x, -z, y), // right
+            1 => (-x, z, y), // left
+            2 => (y, x, -z), // up
+            3 => (-y, x, z), // down
+            4 => (z, x, y),  // front
+            _ => (-z, -x, y), // back
+        }
+    }
+}
+
+impl Camera for CubemapCamera {
+    fn get_ray(&self, x: f64, y: f64, sx: f64, sy: f64) -> Ray {
+        let fx = ((x + sx) * 3.).clamp(0., 2.999_999);
+        let fy = ((1. - y - sy) * 2.).clamp(0., 1.999_999);
+        let (col, row) = (fx.floor(), fy.floor());
+        let (forward, right, up) = CubemapCamera::face(row as usize * 3 + col as usize);
+        // Position within the face, -1 - 1, with y up
+        let u = (fx - col) * 2. - 1.;
+        let v = 1. - (fy - row) * 2.;
+        let d = (forward + right * u + up * v).normalize();
+        Ray::new(self.location, camera_direction(&self.camx, &self.camy, &self.camz, d))
+    }
+
+    fn get_coord_for_point(&self, point: Vector3<f64>) -> Option<(f64, f64)> {
+        let d = camera_space(&self.camx, &self.camy, &self.camz, point - self.location);
+        // The face the point is most in front of
+        let (i, depth) = (0 .. 6)
+            .map(|i| (i, d.dot(&CubemapCamera::face(i).0)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+        if depth <= 0. {
+            return None;
+        }
+        let (_, right, up) = CubemapCamera::face(i);
+        let u = d.dot(&right) / depth;
+        let v = d.dot(&up) / depth;
+        let (col, row) = ((i % 3) as f64, (i / 3) as f64);
+        Some(((col + (u + 1.) / 2.) / 3., 1. - (row + (1. - v) / 2.) / 2.))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StereoLayout {
+    /// Left eye in the left half of the image
+    SideBySide,
+    /// Left eye in the top half of the image
+    TopBottom,
+}
+
+/// A pair of eyes, `interocular` apart, looking through the same camera. Each eye gets half
+/// of the image, so the wrapped camera should be sized for one eye.
+///
+/// Eyes converge by shifting their view rather than turning in (off-axis stereo), so objects
+/// `convergence` away appear at the depth of the screen without any vertical parallax.
+///
+/// For 360 degree cameras, each direction is seen from a point on a circle `interocular`
+/// across, so that every way you look has the eyes side by side (omni-directional stereo).
+/// Looking up or down the eyes close in, to avoid a seam at the poles.
+///
+/// ## References
+/// 1. Google - Rendering Omni-directional Stereo Content (2015)
+pub struct StereoCamera {
+    camera: Box<dyn Camera + Sync>,
+    layout: StereoLayout,
+    interocular: f64,
+    convergence: f64,
+    omnidirectional: bool,
 
+    camx: Vector3<f64>,
+    camy: Vector3<f64>,
+    camz: Vector3<f64>,
+}
 
+impl StereoCamera {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+            camera: Box<dyn Camera + Sync>,
+            lookat: Vector3<f64>,
+            location: Vector3<f64>,
+            up: Vector3<f64>,
+            interocular: f64,
+            convergence: f64,
+            omnidirectional: bool,
+            layout: StereoLayout,
+        ) -> StereoCamera {
+        let (camx, camy, camz) = camera_basis(lookat, location, up);
+        StereoCamera { camera, layout, interocular, convergence, omnidirectional, camx, camy, camz }
+    }
+
+    /// The eye (-1 for left, 1 for right) that sees a point on the image, and the point and
+    /// subsample offset within that eye's image
+    fn eye_image(&self, x: f64, y: f64, sx: f64, sy: f64) -> (f64, (f64, f64), (f64, f64)) {
+        match self.layout {
+            StereoLayout::SideBySide if x < 0.5 => (-1., (x * 2., y), (sx * 2., sy)),
+            StereoLayout::SideBySide => (1., (x * 2. - 1., y), (sx * 2., sy)),
+            StereoLayout::TopBottom if y >= 0.5 => (-1., (x, y * 2. - 1.), (sx, sy * 2.)),
+            StereoLayout::TopBottom => (1., (x, y * 2.), (sx, sy * 2.)),
+        }
+    }
+
+    /// Move a ray seen from the middle of the eyes to one eye (-1 for left, 1 for right)
+    fn eye_ray(&self, r: Ray, eye: f64) -> Ray {
+        let half = self.interocular / 2. * eye;
+        if self.omnidirectional {
+            // Perpendicular to the horizontal part of the view, which shrinks towards the poles
+            let d = r.rd.normalize();
+            let horizontal = d - self.camy * d.dot(&self.camy);
+            return Ray { ro: r.ro + self.camy.cross(&horizontal) * half, ..r };
+        }
+        let ro = r.ro + self.camx * half;
+        let along = r.rd.dot(&self.camz);
+        if along <= 0. {
+            return Ray { ro, ..r };
+        }
+        // Aim at the point the middle ray crosses the plane of convergence
+        let target = r.ro + r.rd * (self.convergence / along);
+        Ray { ro, rd: (target - ro).normalize(), ..r }
+    }
+}
+
+impl Camera for StereoCamera {
+    fn get_ray(&self, x: f64, y: f64, sx: f64, sy: f64) -> Ray {
+        self.get_sample(x, y, sx, sy, 0.).0
+    }
+
+    fn get_ray_at(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64) -> Ray {
+        self.get_sample(x, y, sx, sy, time).0
+    }
+
+    fn get_sample(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64) -> (Ray, Color) {
+        let (eye, (x, y), (sx, sy)) = self.eye_image(x, y, sx, sy);
+        let (r, weight) = self.camera.get_sample(x, y, sx, sy, time);
+        if r.rd == Vector3::zeros() {
+            return (r, weight);
+        }
+        (self.eye_ray(r, eye), weight)
+    }
+
+    fn get_sample_differential(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64, pixel: (f64, f64)) -> (Ray, RayDifferential, Color) {
+        let (eye, (x, y), (sx, sy)) = self.eye_image(x, y, sx, sy);
+        let (_, _, pixel) = self.eye_image(0., 0., pixel.0, pixel.1);
+        let (r, d, weight) = self.camera.get_sample_differential(x, y, sx, sy, time, pixel);
+        if r.rd == Vector3::zeros() {
+            return (r, d, weight);
+        }
+        let d = RayDifferential { rx: self.eye_ray(d.rx, eye), ry: self.eye_ray(d.ry, eye) };
+        (self.eye_ray(r, eye), d, weight)
+    }
+
+    fn exposure(&self) -> f64 {
+        self.camera.exposure()
+    }
+
+    /// Where the point is in the left eye's half of the image, as seen from between the eyes
+    fn get_coord_for_point(&self, point: Vector3<f64>) -> Option<(f64, f64)> {
+        let (x, y) = self.camera.get_coord_for_point(point)?;
+        match self.layout {
+            StereoLayout::SideBySide => Some((x / 2., y)),
+            StereoLayout::TopBottom => Some((x, y / 2. + 0.5)),
+        }
+    }
+}
+
+/// A camera that moves during the shutter interval. The wrapped camera is in its starting
+/// position, and its rays are carried along by the motion - a rigid move to the ending
+/// position, rotating about the camera's location.
+pub struct MovingCamera {
+    camera: Box<dyn Camera + Sync>,
+    location: Vector3<f64>,
+    translation: Vector3<f64>,
+    rotation: UnitQuaternion<f64>,
+}
+
+impl MovingCamera {
+    /// `start` and `end` are (lookat, location, up) at the start and end of the motion.
+    pub fn new(
+            camera: Box<dyn Camera + Sync>,
+            start: (Vector3<f64>, Vector3<f64>, Vector3<f64>),
+            end: (Vector3<f64>, Vector3<f64>, Vector3<f64>),
+        ) -> MovingCamera {
+        let basis = |(lookat, location, up)| {
+            let (x, y, z) = camera_basis(lookat, location, up);
+            Rotation3::from_matrix_unchecked(Matrix3::from_columns(&[x, y, z]))
+        };
+        let rotation = basis(end) * basis(start).inverse();
+        MovingCamera {
+            camera,
+            location: start.1,
+            translation: end.1 - start.1,
+            rotation: UnitQuaternion::from_rotation_matrix(&rotation),
+        }
+    }
+}
+
+impl MovingCamera {
+    /// Move a ray from the start of the motion to where it is at time
+    fn carry(&self, r: Ray, time: f64) -> Ray {
+        let rotation = UnitQuaternion::identity().slerp(&self.rotation, time);
+        Ray {
+            ro: self.location + self.translation * time + rotation * (r.ro - self.location),
+            rd: rotation * r.rd,
+            time,
+        }
+    }
+}
+
+impl Camera for MovingCamera {
+    fn get_ray(&self, x: f64, y: f64, sx: f64, sy: f64) -> Ray {
+        self.get_ray_at(x, y, sx, sy, 0.)
+    }
+
+    fn get_ray_at(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64) -> Ray {
+        self.get_sample(x, y, sx, sy, time).0
+    }
+
+    fn get_sample(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64) -> (Ray, Color) {
+        let (r, weight) = self.camera.get_sample(x, y, sx, sy, time);
+        (self.carry(r, time), weight)
+    }
+
+    fn get_sample_differential(&self, x: f64, y: f64, sx: f64, sy: f64, time: f64, pixel: (f64, f64)) -> (Ray, RayDifferential, Color) {
+        let (r, d, weight) = self.camera.get_sample_differential(x, y, sx, sy, time, pixel);
+        let d = RayDifferential { rx: self.carry(d.rx, time), ry: self.carry(d.ry, time) };
+        (self.carry(r, time), d, weight)
+    }
+
+    fn exposure(&self) -> f64 {
+        self.camera.exposure()
+    }
+
+    /// Where the point is at the start of the motion
+    fn get_coord_for_point(&self, point: Vector3<f64>) -> Option<(f64, f64)> {
+        self.camera.get_coord_for_point(point)
     }
 }
 
 #[cfg(test)]
+#[allow(unused_macros)]
 macro_rules! assert_approx_eq(
     ($a:expr, $b:expr) => ({
     let (a, b) = (&$a, &$b);
@@ -268,4 +976,244 @@ mod tests {
         assert_approx_eq!(c.get_ray(0.05, 0.1, 0., 0.).rd.z, 0.361484323405431);
         */
     }
+
+    #[test]
+    fn orthographic_rays_are_parallel() {
+        let c = OrthographicCamera::new(
+            Vector3::new(0., 0., 0.),
+            Vector3::new(0., 0., -10.),
+            Vector3::new(0., 1., 0.),
+            4.,
+            200, 100
+        );
+        let a = c.get_ray(0., 0., 0., 0.);
+        let b = c.get_ray(1., 1., 0., 0.);
+        assert_eq!(a.rd, Vector3::new(0., 0., 1.));
+        assert_eq!(a.rd, b.rd);
+        // The view is 4 high, and twice as wide
+        assert_approx_eq!((b.ro - a.ro).y.abs(), 4.);
+        assert_approx_eq!((b.ro - a.ro).x.abs(), 8.);
+        assert_approx_eq!(c.get_ray(0.5, 0.5, 0., 0.).ro.z, -10.);
+    }
+
+    #[test]
+    fn isometric_view_direction() {
+        let c = OrthographicCamera::isometric(Vector3::new(0., 0., 0.), 10., 4., 100, 100);
+        let r = c.get_ray(0.5, 0.5, 0., 0.);
+        // The axes are equally foreshortened
+        assert_approx_eq!(r.rd.x.abs(), r.rd.y.abs());
+        assert_approx_eq!(r.rd.y.abs(), r.rd.z.abs());
+        assert!(r.rd.y < 0.);
+    }
+
+    #[test]
+    fn equirectangular_covers_the_sphere() {
+        let c = EquirectangularCamera::new(Vector3::new(0., 0., 1.), Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.));
+        let centre = c.get_ray(0.5, 0.5, 0., 0.).rd;
+        assert_approx_eq!((centre - Vector3::new(0., 0., 1.)).norm(), 0.);
+        let behind = c.get_ray(0., 0.5, 0., 0.).rd;
+        assert_approx_eq!((behind - Vector3::new(0., 0., -1.)).norm(), 0.);
+        let zenith = c.get_ray(0.3, 1., 0., 0.).rd;
+        assert_approx_eq!(zenith.y, 1.);
+        // A quarter turn right
+        let right = c.get_ray(0.75, 0.5, 0., 0.).rd;
+        assert_approx_eq!(right.x.abs(), 1.);
+    }
+
+    #[test]
+    fn fisheye_projections() {
+        let eq = FisheyeCamera::new(
+            Vector3::new(0., 0., 1.), Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.),
+            f64::consts::PI, FisheyeProjection::Equidistant, 200, 100);
+        // The circle fits the height, so the top edge is 90 degrees off axis
+        assert_approx_eq!(eq.get_ray(0.5, 1., 0., 0.).rd.z, 0.);
+        assert_approx_eq!(eq.get_ray(0.5, 0.75, 0., 0.).rd.normalize().z, (f64::consts::PI / 4.).cos());
+        // Outside the circle
+        assert_eq!(eq.get_ray(0., 0., 0., 0.).rd, Vector3::zeros());
+
+        let es = FisheyeCamera::new(
+            Vector3::new(0., 0., 1.), Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.),
+            f64::consts::PI, FisheyeProjection::Equisolid, 100, 100);
+        assert_approx_eq!(es.get_ray(1., 0.5, 0., 0.).rd.z, 0.);
+        let half = es.get_ray(0.75, 0.5, 0., 0.).rd.normalize();
+        assert_approx_eq!(half.z.acos(), 2. * (0.5 * (f64::consts::PI / 4.).sin()).asin());
+    }
+
+    #[test]
+    fn cubemap_faces() {
+        let c = CubemapCamera::new(Vector3::new(0., 0., 1.), Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.));
+        let centre = |col: f64, row: f64| c.get_ray((col + 0.5) / 3., 1. - (row + 0.5) / 2., 0., 0.).rd;
+        assert_eq!(centre(1., 1.), Vector3::new(0., 0., 1.));
+        assert_eq!(centre(2., 1.), Vector3::new(0., 0., -1.));
+        assert_eq!(centre(2., 0.), Vector3::new(0., 1., 0.));
+        assert_eq!(centre(0., 1.), Vector3::new(0., -1., 0.));
+        assert_eq!(centre(0., 0.).x, -centre(1., 0.).x);
+    }
+
+    #[test]
+    fn physical_camera_field_of_view() {
+        // A 36mm wide sensor behind a 18mm lens sees 90 degrees across
+        let c = FlatLensCamera::physical(
+            Vector3::new(0., 0., 10.), Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.),
+            18., 36., 8., 1., 300, 200);
+        let edge = c.centre_ray(1., 0.5).rd;
+        assert_approx_eq!(edge.x.abs(), edge.z);
+        let top = c.centre_ray(0.5, 1.).rd;
+        assert_approx_eq!(top.y / top.z, 12. / 18.);
+        // 18mm at f/8 opens 2.25mm across
+        assert_approx_eq!(c.aperture, 0.001125);
+        let r = c.get_ray(0.5, 0.5, 0., 0.);
+        assert_approx_eq!(r.ro.z, 0.);
+        assert!(r.ro.norm() <= 0.001125 + 1e-9);
+    }
+
+    #[test]
+    fn lens_differentials_focus_on_neighbouring_pixels() {
+        let c = FlatLensCamera::physical(
+            Vector3::new(0., 0., 0.), Vector3::new(0., 0., 10.), Vector3::new(0., 1., 0.),
+            50., 36., 1.4, 1., 300, 200);
+        let (r, d, _) = c.get_sample_differential(0.3, 0.4, 0.001, 0.002, 0., (0.01, 0.02));
+        // Through the same point on the lens, and crossing the focal plane (z = 0) where the
+        // neighbouring pixels are in focus
+        assert_eq!(d.rx.ro, r.ro);
+        let focused = |ray: &Ray| ray.ro - ray.rd * (ray.ro.z / ray.rd.z);
+        let (x, y) = c.get_coord_for_point(focused(&d.rx)).unwrap();
+        assert_approx_eq!(x, 0.311);
+        assert_approx_eq!(y, 0.402);
+        let (x, y) = c.get_coord_for_point(focused(&d.ry)).unwrap();
+        assert_approx_eq!(x, 0.301);
+        assert_approx_eq!(y, 0.422);
+    }
+
+    #[test]
+    fn sunny_16_exposure() {
+        assert_approx_eq!(photographic_exposure(100., 0.01, 16.), 1.);
+        // A stop wider doubles the light
+        assert_approx_eq!(photographic_exposure(100., 0.01, 16. / 2f64.sqrt()), 2.);
+        assert_approx_eq!(photographic_exposure(400., 0.01, 16.), 4.);
+    }
+
+    #[test]
+    fn moving_camera_follows_motion() {
+        let start = (Vector3::new(0., 0., 10.), Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.));
+        // Step right and turn to face along x
+        let end = (Vector3::new(20., 0., 0.), Vector3::new(10., 0., 0.), Vector3::new(0., 1., 0.));
+        let inner = SimpleCamera::new(start.0, start.1, start.2, 0.5, 100, 100);
+        let c = MovingCamera::new(Box::new(inner), start, end);
+        let r0 = c.get_ray_at(0.5, 0.5, 0., 0., 0.);
+        assert_approx_eq!((r0.rd.normalize() - Vector3::new(0., 0., 1.)).norm(), 0.);
+        let r1 = c.get_ray_at(0.5, 0.5, 0., 0., 1.);
+        assert_approx_eq!((r1.ro - Vector3::new(10., 0., 0.)).norm(), 0.);
+        assert_approx_eq!((r1.rd.normalize() - Vector3::new(1., 0., 0.)).norm(), 0.);
+        assert_eq!(r1.time, 1.);
+        let half = c.get_ray_at(0.5, 0.5, 0., 0., 0.5).rd.normalize();
+        assert_approx_eq!(half.x, half.z);
+    }
+
+    #[test]
+    fn polygonal_aperture() {
+        let hexagon = Aperture::Polygon { blades: 6, rotation: 0. };
+        // The flat sides are cos(30) of the radius from the centre
+        let apothem = (f64::consts::PI / 6.).cos();
+        let mut furthest_up: f64 = 0.;
+        for _ in 0 .. 2000 {
+            let p = hexagon.sample(1.);
+            assert!(p.norm() <= 1. + 1e-9);
+            furthest_up = furthest_up.max(p.y);
+        }
+        assert!(furthest_up <= apothem + 1e-9);
+        assert!(furthest_up > apothem - 0.05);
+    }
+
+    #[test]
+    fn aperture_mask() {
+        // Only the top right quarter lets light through
+        let mask = Aperture::mask(2, 2, vec![0., 1., 0., 0.].into_iter());
+        for _ in 0 .. 100 {
+            let p = mask.sample(2.);
+            assert!(p.x >= 0. && p.x <= 2. && p.y >= 0. && p.y <= 2.);
+        }
+    }
+
+    #[test]
+    fn barrel_distortion_and_cats_eye() {
+        let straight = FlatLensCamera::new(
+            Vector3::new(0., 0., 10.), Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.), 0.5, 100, 100, 0.);
+        let barrel = FlatLensCamera::new(
+            Vector3::new(0., 0., 10.), Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.), 0.5, 100, 100, 0.)
+            .with_distortion(0.2, 0.);
+        // The centre is unchanged, and the edges see further out
+        assert_eq!(straight.get_ray(0.5, 0.5, 0., 0.).rd, barrel.get_ray(0.5, 0.5, 0., 0.).rd);
+        assert!(barrel.get_ray(0.5, 1., 0., 0.).rd.y > straight.get_ray(0.5, 1., 0., 0.).rd.y);
+
+        let cats_eye = FlatLensCamera::new(
+            Vector3::new(0., 0., 10.), Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.), 0.5, 100, 100, 1.)
+            .with_cats_eye(1.);
+        let blocked = |x: f64| (0 .. 1000).filter(|_| cats_eye.get_ray(x, 0.5, 0., 0.).rd == Vector3::zeros()).count();
+        assert_eq!(blocked(0.5), 0);
+        // Half the width across, the shifted circle overlaps about 40% of the aperture
+        assert!(blocked(1.) > 450 && blocked(1.) < 750, "{}", blocked(1.));
+    }
+
+    #[test]
+    fn stereo_eyes_converge() {
+        let lookat = Vector3::new(0., 0., 10.);
+        let location = Vector3::new(0., 0., 0.);
+        let up = Vector3::new(0., 1., 0.);
+        let eye = SimpleCamera::new(lookat, location, up, 0.5, 100, 100);
+        let c = StereoCamera::new(Box::new(eye), lookat, location, up, 0.1, 10., false, StereoLayout::SideBySide);
+        let left = c.get_ray(0.25, 0.5, 0., 0.);
+        let right = c.get_ray(0.75, 0.5, 0., 0.);
+        assert_approx_eq!(left.ro.x, -0.05);
+        assert_approx_eq!(right.ro.x, 0.05);
+        // Both centres look at lookat
+        let at = |r: &Ray| r.ro + r.rd * ((10. - r.ro.z) / r.rd.z);
+        assert_approx_eq!((at(&left) - lookat).norm(), 0.);
+        assert_approx_eq!((at(&right) - lookat).norm(), 0.);
+    }
+
+    #[test]
+    fn omnidirectional_stereo() {
+        let lookat = Vector3::new(0., 0., 10.);
+        let location = Vector3::new(0., 0., 0.);
+        let up = Vector3::new(0., 1., 0.);
+        let pano = EquirectangularCamera::new(lookat, location, up);
+        let c = StereoCamera::new(Box::new(pano), lookat, location, up, 0.1, 10., true, StereoLayout::TopBottom);
+        // Looking forward the left eye is to the left, looking right it's in front
+        let forward = c.get_ray(0.5, 0.75, 0., 0.);
+        assert_approx_eq!(forward.ro.x, -0.05);
+        let right = c.get_ray(0.75, 0.75, 0., 0.);
+        assert_approx_eq!(right.ro.z, 0.05);
+        // Parallel eyes, and none at the poles
+        assert_approx_eq!((forward.rd - Vector3::new(0., 0., 1.)).norm(), 0.);
+        assert_approx_eq!(c.get_ray(0.5, 1., 0., 0.).ro.norm(), 0.);
+    }
+
+    #[test]
+    fn coord_for_point_inverts_get_ray() {
+        let lookat = Vector3::new(1., 2., 10.);
+        let location = Vector3::new(0., 1., 0.);
+        let up = Vector3::new(0., 1., 0.);
+        let cameras: Vec<Box<dyn Camera>> = vec![
+            Box::new(SimpleCamera::new(lookat, location, up, 0.5, 200, 100)),
+            Box::new(FlatLensCamera::new(lookat, location, up, 0.5, 200, 100, 0.).with_distortion(0.1, 0.02)),
+            Box::new(OrthographicCamera::new(lookat, location, up, 4., 200, 100)),
+            Box::new(EquirectangularCamera::new(lookat, location, up)),
+            Box::new(FisheyeCamera::new(lookat, location, up, 3., FisheyeProjection::Equisolid, 200, 100)),
+            Box::new(CubemapCamera::new(lookat, location, up)),
+        ];
+        for c in cameras {
+            for (x, y) in [(0.5, 0.5), (0.1, 0.2), (0.4, 0.9), (0.8, 0.3)] {
+                let r = c.get_ray(x, y, 0., 0.);
+                if r.rd == Vector3::zeros() {
+                    continue;
+                }
+                let (px, py) = c.get_coord_for_point(r.ro + r.rd * 5.).unwrap();
+                assert_approx_eq!(px, x);
+                assert_approx_eq!(py, y);
+            }
+            // Behind a narrow view
+            assert!(c.get_coord_for_point(location * 2. - lookat).is_none_or(|(x, _)| !(0.25 .. 0.75).contains(&x)));
+        }
+    }
 }
@@ -74,6 +74,40 @@ impl Color {
             self.rgb.z * self_factor + other.rgb.z * clamped_factor,
         )
     }
+
+    /// Perceived brightness (Rec. 709 luma weights)
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.rgb.x + 0.7152 * self.rgb.y + 0.0722 * self.rgb.z
+    }
+
+    /// Parse a named colour ("white", "red"...) or a hex colour ("#ffcc88").
+    pub fn from_name(name: &str) -> Option<Color> {
+        let name = name.trim().to_lowercase();
+        if let Some(hex) = name.strip_prefix('#') {
+            if hex.len() != 6 {
+                return None;
+            }
+            let channel = |i: usize| u8::from_str_radix(&hex[i .. i + 2], 16).ok().map(|c| c as f64 / 255.);
+            return Some(Color::new(channel(0)?, channel(2)?, channel(4)?));
+        }
+        match name.as_str() {
+            "white" => Some(Color::white()),
+            "black" => Some(Color::black()),
+            "red" => Some(Color::red()),
+            "green" => Some(Color::green()),
+            "blue" => Some(Color::blue()),
+            "yellow" => Some(Color::new(1., 1., 0.)),
+            "cyan" => Some(Color::new(0., 1., 1.)),
+            "magenta" => Some(Color::new(1., 0., 1.)),
+            "orange" => Some(Color::new(1., 0.5, 0.)),
+            "grey" | "gray" => Some(Color::new(0.5, 0.5, 0.5)),
+            // Approximate colours of common light sources
+            "warm" | "tungsten" => Some(Color::new(1., 0.71, 0.42)),
+            "daylight" => Some(Color::new(1., 0.98, 0.95)),
+            "cool" => Some(Color::new(0.79, 0.86, 1.)),
+            _ => None,
+        }
+    }
 }
 
 
@@ -33,6 +33,71 @@ pub fn random_point_on_disc(radius: f64) -> Vector2<f64>{
     Vector2::new(r * theta.cos(), r * theta.sin())
 }
 
+/// Uniformly distributed point in a regular polygon with its corners `radius` from the
+/// centre, the first at `rotation` radians from the x axis.
+pub fn random_point_on_polygon(radius: f64, sides: usize, rotation: f64) -> Vector2<f64>{
+    // Every side makes an equal triangle with the centre, so pick one and sample within it.
+    let side = ((rand() * sides as f64) as usize).min(sides - 1);
+    let corner = |i: usize| {
+        let a = rotation + i as f64 * 2. * f64::consts::PI / sides as f64;
+        Vector2::new(a.cos(), a.sin()) * radius
+    };
+    let (mut u, mut v) = (rand(), rand());
+    if u + v > 1. {
+        u = 1. - u;
+        v = 1. - v;
+    }
+    corner(side) * u + corner(side + 1) * v
+}
+
+/// Uniformly distributed direction (a point on the surface of the unit sphere).
+pub fn random_unit_vector() -> Vector3<f64>{
+    let z = 1. - 2. * rand();
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * f64::consts::PI * rand();
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Uniformly distributed direction within a cone around `axis` (normalized), where `cos_max`
+/// is the cosine of the cone's half angle.
+pub fn random_direction_in_cone(axis: &Vector3<f64>, cos_max: f64) -> Vector3<f64>{
+    let cos_theta = 1. - rand() * (1. - cos_max);
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let phi = 2. * f64::consts::PI * rand();
+    let (t, b) = tangents(axis);
+    t * (sin_theta * phi.cos()) + b * (sin_theta * phi.sin()) + axis * cos_theta
+}
+
+/// Uniformly distributed point on a triangle. See "Shape Distributions" (Osada et al.)
+pub fn random_point_on_triangle(v0: Vector3<f64>, v1: Vector3<f64>, v2: Vector3<f64>) -> Vector3<f64>{
+    let r1 = rand().sqrt();
+    let r2 = rand();
+    v0 * (1. - r1) + v1 * (r1 * (1. - r2)) + v2 * (r1 * r2)
+}
+
+/// Build a cumulative distribution from a list of weights (eg. areas).
+pub fn cumulative(weights: impl Iterator<Item=f64>) -> Vec<f64> {
+    let mut total = 0.;
+    weights.map(|w| { total += w; total }).collect()
+}
+
+/// Pick an index from a cumulative distribution, with probability proportional to its weight.
+pub fn sample_cumulative(cdf: &[f64]) -> Option<usize> {
+    let total = *cdf.last()?;
+    if total <= 0. {
+        return None;
+    }
+    let x = rand() * total;
+    Some(cdf.partition_point(|c| *c <= x).min(cdf.len() - 1))
+}
+
+/// Orthonormal basis (tangent, bitangent) perpendicular to a normal.
+pub fn tangents(n: &Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let a = if n.x.abs() > 0.9 { Vector3::new(0., 1., 0.) } else { Vector3::new(1., 0., 0.) };
+    let t = n.cross(&a).normalize();
+    (t, n.cross(&t))
+}
+
 pub fn uniform_sample_hemisphere(r1: f64, r2: f64) -> Vector3<f64>{
     let sin_theta = (1. - r1 * r1).sqrt(); 
     let  phi = 2. * f64::consts::PI * r2; 
@@ -1,24 +1,185 @@
-use crate::na::Vector3;
+use crate::na::{Vector2, Vector3};
 use crate::sceneobject::SceneObject;
+use crate::geometry::{tangents, rand};
+use crate::ray::{Ray, RayDifferential};
 use std::cmp;
 use std::fmt;
 use std::sync::Arc;
 
-// TODO PBRT DifferentialGeometry
+/// Where a ray hits a surface, and the shape of the surface there (the differential geometry).
+///
+/// ## References
+/// 1. PBRT - 2.10 Interactions
 #[derive(Debug, Copy, Clone)]
 pub struct RawIntersection {
     pub dist: f64,
     pub point: Vector3<f64>,
+    /// Normal used for shading. The same as the geometric normal unless the shape interpolates
+    /// normals across its surface (eg. a smooth mesh).
     pub normal: Vector3<f64>,
+    /// Normal of the actual surface
+    pub geometric_normal: Vector3<f64>,
+    /// Surface coordinates of the point, for texturing
+    pub uv: Vector2<f64>,
+    /// How the point moves with u and v
+    pub dpdu: Vector3<f64>,
+    pub dpdv: Vector3<f64>,
+    /// How the shading normal turns with u and v. Zero for flat surfaces.
+    pub dndu: Vector3<f64>,
+    pub dndv: Vector3<f64>,
+    /// Whether the ray hit the outside of the surface. Flat shapes have no inside, and their
+    /// normals face the ray, so this is whether the ray hit the side they were defined facing.
+    pub front_face: bool,
     // TODO error : rayEpsilon for float errors
 }
 
+impl RawIntersection {
+    /// An intersection with no particular parameterisation; uv is 0, and the derivatives
+    /// are an arbitrary pair of tangents.
+    pub fn new(dist: f64, point: Vector3<f64>, normal: Vector3<f64>, front_face: bool) -> RawIntersection {
+        let (dpdu, dpdv) = tangents(&normal);
+        RawIntersection {
+            dist,
+            point,
+            normal,
+            geometric_normal: normal,
+            uv: Vector2::zeros(),
+            dpdu,
+            dpdv,
+            dndu: Vector3::zeros(),
+            dndv: Vector3::zeros(),
+            front_face,
+        }
+    }
+
+    pub fn with_uv(mut self, uv: Vector2<f64>, dpdu: Vector3<f64>, dpdv: Vector3<f64>) -> RawIntersection {
+        self.uv = uv;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    pub fn with_shading_normal(mut self, normal: Vector3<f64>) -> RawIntersection {
+        self.normal = normal;
+        self
+    }
+
+    pub fn with_normal_derivatives(mut self, dndu: Vector3<f64>, dndv: Vector3<f64>) -> RawIntersection {
+        self.dndu = dndu;
+        self.dndv = dndv;
+        self
+    }
+
+    /// The same point, seen from the other side of the surface
+    pub fn flipped(mut self) -> RawIntersection {
+        self.normal = -self.normal;
+        self.geometric_normal = -self.geometric_normal;
+        self.dndu = -self.dndu;
+        self.dndv = -self.dndv;
+        self.front_face = !self.front_face;
+        self
+    }
+}
+
+/// How much of the surface one camera sample covers around an intersection, found from the
+/// ray's differentials: how the point and its surface coordinates change with a step across
+/// (x) and up (y) the image. Zero where the ray has no differentials, which textures treat as
+/// a point.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Footprint {
+    pub dpdx: Vector3<f64>,
+    pub dpdy: Vector3<f64>,
+    pub duvdx: Vector2<f64>,
+    pub duvdy: Vector2<f64>,
+}
+
+impl Footprint {
+    pub fn zero() -> Footprint {
+        Footprint {
+            dpdx: Vector3::zeros(),
+            dpdy: Vector3::zeros(),
+            duvdx: Vector2::zeros(),
+            duvdy: Vector2::zeros(),
+        }
+    }
+
+    /// Size of the footprint in the scene
+    pub fn width(&self) -> f64 {
+        self.dpdx.norm().max(self.dpdy.norm())
+    }
+
+    /// Size of the footprint in surface coordinates
+    pub fn uv_width(&self) -> f64 {
+        self.duvdx.amax().max(self.duvdy.amax())
+    }
+
+    /// A random offset from the centre of the footprint to somewhere within it
+    pub fn jitter(&self) -> Vector3<f64> {
+        self.dpdx * (rand() - 0.5) + self.dpdy * (rand() - 0.5)
+    }
+}
+
 #[derive(Clone)]
 pub struct Intersection {
     pub dist: f64,
     pub point: Vector3<f64>,
     pub normal: Vector3<f64>,
+    pub geometric_normal: Vector3<f64>,
+    pub uv: Vector2<f64>,
+    pub dpdu: Vector3<f64>,
+    pub dpdv: Vector3<f64>,
+    pub dndu: Vector3<f64>,
+    pub dndv: Vector3<f64>,
+    pub front_face: bool,
+    pub footprint: Footprint,
     pub object: Arc<SceneObject>,
+    /// Time of the ray that hit, which rays leaving the surface share
+    pub time: f64,
+}
+
+impl Intersection {
+    pub fn new(i: &RawIntersection, object: Arc<SceneObject>, time: f64) -> Intersection {
+        Intersection {
+            dist: i.dist,
+            point: i.point,
+            normal: i.normal,
+            geometric_normal: i.geometric_normal,
+            uv: i.uv,
+            dpdu: i.dpdu,
+            dpdv: i.dpdv,
+            dndu: i.dndu,
+            dndv: i.dndv,
+            front_face: i.front_face,
+            footprint: Footprint::zero(),
+            object,
+            time,
+        }
+    }
+
+    /// Find the footprint where the ray's differentials meet the plane tangent to the surface.
+    ///
+    /// ## References
+    /// 1. PBRT - 10.1.1 Finding the Texture Sampling Rate
+    pub fn with_differential(mut self, d: &RayDifferential) -> Intersection {
+        let n = self.geometric_normal;
+        let on_plane = |r: &Ray| {
+            let t = n.dot(&(self.point - r.ro)) / n.dot(&r.rd);
+            if t.is_finite() { Some(r.ro + r.rd * t - self.point) } else { None }
+        };
+        let (dpdx, dpdy) = match (on_plane(&d.rx), on_plane(&d.ry)) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return self,
+        };
+
+        // Solve dp = du * dpdu + dv * dpdv in least squares, as the offsets aren't exactly in
+        // the span of the derivatives
+        let a = na::Matrix3x2::from_columns(&[self.dpdu, self.dpdv]);
+        let duv = |dp: Vector3<f64>| (a.transpose() * a).try_inverse()
+            .map(|inv| inv * a.transpose() * dp)
+            .unwrap_or(Vector2::zeros());
+        self.footprint = Footprint { dpdx, dpdy, duvdx: duv(dpdx), duvdy: duv(dpdy) };
+        self
+    }
 }
 
 /*
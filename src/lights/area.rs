@@ -0,0 +1,201 @@
+/// Area lights
+///
+/// Area lights are shapes that emit light from their surface. Each time a point is shaded we
+/// pick a point on the light, so averaged over many samples, partially occluded lights cast
+/// soft shadows whose penumbra grows with the size of the light.
+///
+/// Unlike point lights, area lights are physical: `intensity` is emitted radiance, as with
+/// a `DiffuseLight` material, and falls off with distance. A sample's intensity is the
+/// irradiance it contributes divided by pi, which is what a Lambertian surface needs.
+///
+/// Samples can be chosen:
+/// - Uniformly over the area of the light. Cheap, but large or close lights are noisy, as
+///   much of the light is seen at a grazing angle or is far away.
+/// - By solid angle. We approximate this with resampled importance sampling (RIS): we draw
+///   several uniform candidates and pick one in proportion to the solid angle it subtends,
+///   which keeps the estimate unbiased and works for any shape we can sample.
+///
+/// Lights are invisible to camera rays. To see an emitter, add an object with a
+/// `DiffuseLight` material and mark it with `"area_light": true` to sample it (ObjectLight).
+///
+/// ## References
+/// 1. PBRT (3rd Ed.) 14.2 - Sampling Light Sources
+/// 2. Talbot et al. - Importance Resampling for Global Illumination (2005)
+use std::f64;
+use std::sync::Arc;
+use crate::na::Vector3;
+use crate::color::Color;
+use crate::sceneobject::SceneObject;
+use crate::shapes::geometry::Geometry;
+use crate::shapes::bbox::BBox;
+use crate::lights::light::{Light, LightSample};
+use crate::geometry::rand;
+
+const RESAMPLE_CANDIDATES: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightSampling {
+    Uniform,
+    SolidAngle,
+}
+
+impl LightSampling {
+    pub fn candidates(&self) -> usize {
+        match self {
+            LightSampling::Uniform => 1,
+            LightSampling::SolidAngle => RESAMPLE_CANDIDATES,
+        }
+    }
+}
+
+/// Pick a point on the geometry to illuminate pt.
+/// Returns the point, and the weight of the sample: the solid angle the light would
+/// subtend if all of it looked like the chosen point.
+fn sample_shape(shape: &dyn Geometry, pt: &Vector3<f64>, sampling: LightSampling, two_sided: bool) -> Option<(Vector3<f64>, f64)> {
+    let area = shape.area();
+    let candidates = sampling.candidates();
+    let mut total = 0.;
+    let mut chosen = None;
+    for _ in 0 .. candidates {
+        let (p, n) = shape.sample_surface()?;
+        let d = pt - p;
+        let dist2 = d.norm_squared();
+        if dist2 <= 0. {
+            continue;
+        }
+        let cos_l = n.dot(&d) / dist2.sqrt();
+        let cos_l = if two_sided { cos_l.abs() } else { cos_l.max(0.) };
+        let w = cos_l * area / dist2;
+        total += w;
+        // Reservoir sampling: keep each candidate in proportion to its weight
+        if w > 0. && rand() * total < w {
+            chosen = Some(p);
+        }
+    }
+    chosen.map(|p| (p, total / candidates as f64))
+}
+
+/// A light emitting from the surface of a shape (sphere, quad, disk...)
+pub struct AreaLight {
+    pub shape: Box<dyn Geometry + Sync + Send>,
+    pub color: Color,
+    pub intensity: f64,
+    pub sampling: LightSampling,
+    // Flat emitters only emit from their front face (along the normal) unless two sided.
+    pub two_sided: bool,
+}
+
+impl Light for AreaLight {
+    fn sample(&self, pt: &Vector3<f64>) -> LightSample {
+        match sample_shape(&*self.shape, pt, self.sampling, self.two_sided) {
+            Some((position, w)) => LightSample {
+                position,
+                color: self.color,
+                intensity: self.intensity * w / f64::consts::PI,
+            },
+            None => LightSample {
+                position: self.shape.bounds().mid(),
+                color: self.color,
+                intensity: 0.,
+            }
+        }
+    }
+
+    fn bounds(&self) -> Option<BBox> {
+        Some(self.shape.bounds())
+    }
+
+    fn power(&self) -> f64 {
+        let sides = if self.two_sided { 2. } else { 1. };
+        self.intensity * self.color.luminance() * self.shape.area() * sides * f64::consts::PI
+    }
+}
+
+/// A scene object with an emissive material, sampled as an area light.
+/// The emitted color is looked up from the object's medium at the sampled point.
+pub struct ObjectLight {
+    pub object: Arc<SceneObject>,
+    pub sampling: LightSampling,
+}
+
+impl Light for ObjectLight {
+    fn sample(&self, pt: &Vector3<f64>) -> LightSample {
+        match sample_shape(&*self.object, pt, self.sampling, false) {
+            Some((position, w)) => LightSample {
+                position,
+                color: self.object.medium.material_at(position).emission().unwrap_or(Color::black()),
+                intensity: w / f64::consts::PI,
+            },
+            None => LightSample {
+                position: self.object.bounds().mid(),
+                color: Color::black(),
+                intensity: 0.,
+            }
+        }
+    }
+
+    fn bounds(&self) -> Option<BBox> {
+        Some(self.object.bounds())
+    }
+
+    fn power(&self) -> f64 {
+        // Assume the emission is roughly uniform over the surface
+        let emission = match self.object.sample_surface() {
+            Some((p, _)) => self.object.medium.material_at(p).emission().unwrap_or(Color::black()),
+            None => Color::black(),
+        };
+        emission.luminance() * self.object.area() * f64::consts::PI
+    }
+
+    fn visible(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::sphere::Sphere;
+    use crate::shapes::rectangle::Rectangle;
+
+    fn mean_intensity(l: &dyn Light, pt: &Vector3<f64>, samples: usize) -> f64 {
+        (0 .. samples).map(|_| l.sample(pt).intensity).sum::<f64>() / samples as f64
+    }
+
+    #[test]
+    fn test_sphere_light_irradiance() {
+        // A sphere of radiance L subtends solid angle 2pi(1 - cos(theta_max)), so the sample
+        // intensity (ignoring the receiver's cosine) should average 2L(1 - cos(theta_max)).
+        let dist: f64 = 10.;
+        let radius: f64 = 1.;
+        let expected = 2. * (1. - (1. - (radius / dist).powi(2)).sqrt());
+        for sampling in [LightSampling::Uniform, LightSampling::SolidAngle] {
+            let l = AreaLight {
+                shape: Box::new(Sphere::new(Vector3::new(0., dist, 0.), radius)),
+                color: Color::white(),
+                intensity: 1.,
+                sampling,
+                two_sided: false,
+            };
+            let i = mean_intensity(&l, &Vector3::new(0., 0., 0.), 200000);
+            assert!((i - expected).abs() / expected < 0.05, "{:?}: {} != {}", sampling, i, expected);
+        }
+    }
+
+    #[test]
+    fn test_quad_light_one_sided() {
+        // Quad facing down (u x v = -y), so it only lights points below it
+        let l = AreaLight {
+            shape: Box::new(Rectangle::new(
+                Vector3::new(-1., 5., -1.),
+                Vector3::new(2., 0., 0.),
+                Vector3::new(0., 0., 2.))),
+            color: Color::white(),
+            intensity: 1.,
+            sampling: LightSampling::SolidAngle,
+            two_sided: false,
+        };
+        assert!(l.sample(&Vector3::new(0., 0., 0.)).intensity > 0.);
+        assert_eq!(l.sample(&Vector3::new(0., 10., 0.)).intensity, 0.);
+    }
+}
@@ -0,0 +1,43 @@
+use crate::na::Vector3;
+use crate::color::Color;
+use crate::shapes::bbox::BBox;
+use crate::lights::light::{Light, LightSample, DISTANT};
+use crate::geometry::random_direction_in_cone;
+
+/// A light infinitely far away, so that it arrives from the same direction everywhere (eg. the
+/// sun.)
+///
+/// With an angular diameter of zero the light is a perfect direction and casts hard shadows.
+/// Otherwise it is a disc in the sky (the sun is about 0.53 degrees across) and samples are
+/// spread over it, giving soft shadows.
+pub struct DirectionalLight {
+    /// Direction the light travels (normalized)
+    pub direction: Vector3<f64>,
+    /// Angular diameter in radians
+    pub angular_diameter: f64,
+    pub color: Color,
+    pub intensity: f64,
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, pt: &Vector3<f64>) -> LightSample {
+        let to_light = if self.angular_diameter > 0. {
+            random_direction_in_cone(&-self.direction, (self.angular_diameter / 2.).cos())
+        } else {
+            -self.direction
+        };
+        LightSample {
+            position: pt + to_light * DISTANT,
+            color: self.color,
+            intensity: self.intensity,
+        }
+    }
+
+    fn bounds(&self) -> Option<BBox> {
+        None
+    }
+
+    fn power(&self) -> f64 {
+        self.intensity * self.color.luminance()
+    }
+}
@@ -0,0 +1,262 @@
+/// Image based lighting from an equirectangular environment map.
+///
+/// The map surrounds the scene at infinity: rays that miss every object see it, and it lights
+/// surfaces as a light source. Most of the light in a typical HDR capture comes from a few small
+/// regions (the sun, windows), so rather than picking directions uniformly we build a 2D
+/// distribution over the pixels weighted by luminance, and sample directions in proportion to
+/// their brightness. The sample intensity is divided by the probability of choosing the
+/// direction, so the estimate stays unbiased.
+///
+/// Radiance (.hdr) and PFM images are supported. Any other format the image crate can read
+/// will load too, but isn't high dynamic range.
+///
+/// ## References
+/// 1. PBRT (3rd Ed.) 14.2.4 - Infinite Area Lights
+/// 2. http://www.pauldebevec.com/Research/HDR/PFM/
+use std::f64;
+use std::fs;
+use crate::na::Vector3;
+use crate::color::Color;
+use crate::shapes::bbox::BBox;
+use crate::geometry::{rand, cumulative, sample_cumulative};
+use crate::lights::light::{Light, LightSample, DISTANT};
+
+/// Read a Portable Float Map. Returns width, height and pixels, top row first.
+pub fn load_pfm(bytes: &[u8]) -> Result<(usize, usize, Vec<Color>), String> {
+    // The header is three whitespace separated tokens after the magic, then a single
+    // whitespace character before the data.
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while tokens.len() < 4 {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("truncated header".to_string());
+        }
+        tokens.push(String::from_utf8_lossy(&bytes[start .. pos]).to_string());
+    }
+    pos += 1;
+
+    let channels = match tokens[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err("not a PFM file".to_string()),
+    };
+    let parse = |t: &str| t.parse::<f64>().map_err(|_| format!("invalid header value {}", t));
+    let width = parse(&tokens[1])? as usize;
+    let height = parse(&tokens[2])? as usize;
+    let scale = parse(&tokens[3])?;
+
+    let data = bytes.get(pos ..).unwrap_or(&[]);
+    if data.len() < width * height * channels * 4 {
+        return Err("truncated data".to_string());
+    }
+    let value = |i: usize| {
+        let b = [data[i * 4], data[i * 4 + 1], data[i * 4 + 2], data[i * 4 + 3]];
+        // A negative scale means little endian
+        (if scale < 0. { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) }) as f64
+    };
+
+    let mut pixels = Vec::with_capacity(width * height);
+    // Rows are stored bottom to top
+    for y in (0 .. height).rev() {
+        for x in 0 .. width {
+            let i = (y * width + x) * channels;
+            pixels.push(if channels == 3 {
+                Color::new(value(i), value(i + 1), value(i + 2))
+            } else {
+                Color::new(value(i), value(i), value(i))
+            });
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+pub struct EnvironmentLight {
+    pub width: usize,
+    pub height: usize,
+    /// Pixels, top row first
+    pub pixels: Vec<Color>,
+    /// Rotation around the y axis, in radians
+    pub rotation: f64,
+    pub intensity: f64,
+    /// Cumulative weight of each row
+    marginal: Vec<f64>,
+    /// Cumulative weight of each pixel within its row
+    conditional: Vec<Vec<f64>>,
+}
+
+impl EnvironmentLight {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>, rotation: f64, intensity: f64) -> EnvironmentLight {
+        // Rows near the poles cover less of the sphere, so are less likely to be chosen.
+        let conditional: Vec<Vec<f64>> = (0 .. height).map(|y| {
+            let sin_theta = ((y as f64 + 0.5) / height as f64 * f64::consts::PI).sin();
+            cumulative((0 .. width).map(|x| pixels[y * width + x].luminance().max(0.) * sin_theta))
+        }).collect();
+        let marginal = cumulative(conditional.iter().map(|row| row.last().cloned().unwrap_or(0.)));
+        EnvironmentLight { width, height, pixels, rotation, intensity, marginal, conditional }
+    }
+
+    pub fn from_file(pth: &str, rotation: f64, intensity: f64) -> EnvironmentLight {
+        let (width, height, pixels) = if pth.to_lowercase().ends_with(".pfm") {
+            let bytes = match fs::read(pth) {
+                Ok(b) => b,
+                Err(e) => panic!("Could not load {}: {:?}", pth, e),
+            };
+            match load_pfm(&bytes) {
+                Ok(x) => x,
+                Err(e) => panic!("Could not parse {}: {}", pth, e),
+            }
+        } else {
+            let img = match image::open(pth) {
+                Ok(i) => i.to_rgb32f(),
+                Err(e) => panic!("Could not load {}: {:?}", pth, e),
+            };
+            let pixels = img.pixels().map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64)).collect();
+            (img.width() as usize, img.height() as usize, pixels)
+        };
+        EnvironmentLight::new(width, height, pixels, rotation, intensity)
+    }
+
+    /// Image coordinates (0 - 1) of a direction
+    fn uv_of(&self, d: &Vector3<f64>) -> (f64, f64) {
+        let d = d.normalize();
+        let phi = d.z.atan2(d.x) + self.rotation;
+        let theta = d.y.clamp(-1., 1.).acos();
+        ((phi / (2. * f64::consts::PI)).rem_euclid(1.), theta / f64::consts::PI)
+    }
+
+    fn direction_of(&self, u: f64, v: f64) -> Vector3<f64> {
+        let phi = u * 2. * f64::consts::PI - self.rotation;
+        let theta = v * f64::consts::PI;
+        Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+    }
+
+    fn pixel(&self, u: f64, v: f64) -> Color {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[y * self.width + x]
+    }
+
+    /// Radiance arriving from a direction
+    pub fn radiance(&self, d: &Vector3<f64>) -> Color {
+        let (u, v) = self.uv_of(d);
+        self.pixel(u, v) * self.intensity
+    }
+
+    /// Choose a direction in proportion to its brightness. Returns the direction, and the
+    /// probability density of choosing it (per steradian).
+    pub fn sample_direction(&self) -> Option<(Vector3<f64>, f64)> {
+        let y = sample_cumulative(&self.marginal)?;
+        let x = sample_cumulative(&self.conditional[y])?;
+        let u = (x as f64 + rand()) / self.width as f64;
+        let v = (y as f64 + rand()) / self.height as f64;
+
+        let sin_theta = (v * f64::consts::PI).sin();
+        if sin_theta <= 0. {
+            return None;
+        }
+        let row_weight = self.conditional[y][x] - if x > 0 { self.conditional[y][x - 1] } else { 0. };
+        let p = row_weight / self.marginal[self.marginal.len() - 1];
+        // p is the chance of the pixel, which spans 1 / (width * height) of the image, and the
+        // image spans 2pi^2 sin(theta) steradians per unit area.
+        let pdf = p * (self.width * self.height) as f64 / (2. * f64::consts::PI * f64::consts::PI * sin_theta);
+        Some((self.direction_of(u, v), pdf))
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample(&self, pt: &Vector3<f64>) -> LightSample {
+        match self.sample_direction() {
+            Some((d, pdf)) if pdf > 0. => LightSample {
+                position: pt + d * DISTANT,
+                color: self.radiance(&d),
+                intensity: 1. / (pdf * f64::consts::PI),
+            },
+            _ => LightSample {
+                position: pt + Vector3::new(0., DISTANT, 0.),
+                color: Color::black(),
+                intensity: 0.,
+            }
+        }
+    }
+
+    fn bounds(&self) -> Option<BBox> {
+        None
+    }
+
+    fn power(&self) -> f64 {
+        // Radiance integrated over the sphere; each pixel spans 2pi^2 sin(theta) / (w * h) sr.
+        let total = self.marginal.last().cloned().unwrap_or(0.);
+        self.intensity * total * 2. * f64::consts::PI * f64::consts::PI / (self.width * self.height) as f64
+    }
+
+    fn visible(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constant(w: usize, h: usize, c: Color) -> EnvironmentLight {
+        EnvironmentLight::new(w, h, vec![c; w * h], 0., 1.)
+    }
+
+    #[test]
+    fn test_uv_roundtrip() {
+        let mut env = constant(8, 4, Color::white());
+        env.rotation = 0.7;
+        for d in [Vector3::new(1., 0.2, 0.), Vector3::new(-0.3, -0.5, 0.8), Vector3::new(0.1, 0.9, -0.4)] {
+            let (u, v) = env.uv_of(&d);
+            assert!((env.direction_of(u, v) - d.normalize()).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_constant_environment_irradiance() {
+        // Irradiance on an upward facing surface under a uniform sky of radiance L is pi * L,
+        // so the mean of cos(theta) * intensity should be L.
+        let env = constant(32, 16, Color::new(0.5, 0.5, 0.5));
+        let pt = Vector3::new(0., 0., 0.);
+        let n = 100000;
+        let mut sum = 0.;
+        for _ in 0 .. n {
+            let s = env.sample(&pt);
+            let cos = (s.position - pt).normalize().y.max(0.);
+            sum += cos * s.intensity * s.color.rgb.x;
+        }
+        let mean = sum / n as f64;
+        assert!((mean - 0.5).abs() < 0.02, "{}", mean);
+    }
+
+    #[test]
+    fn test_importance_sampling_finds_bright_pixel() {
+        let mut pixels = vec![Color::new(0.01, 0.01, 0.01); 16 * 8];
+        pixels[2 * 16 + 5] = Color::new(1000., 1000., 1000.);
+        let env = EnvironmentLight::new(16, 8, pixels, 0., 1.);
+        let bright = (0 .. 100).filter(|_| {
+            let (d, _) = env.sample_direction().unwrap();
+            env.radiance(&d).rgb.x > 1.
+        }).count();
+        assert!(bright > 90);
+    }
+
+    #[test]
+    fn test_load_pfm() {
+        let mut bytes = b"PF\n2 1\n-1.0\n".to_vec();
+        for v in [1f32, 2., 3., 4., 5., 6.] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        let (w, h, p) = load_pfm(&bytes).unwrap();
+        assert_eq!((w, h), (2, 1));
+        assert_eq!(p[1], Color::new(4., 5., 6.));
+        assert!(load_pfm(b"P6\n1 1\n255\n").is_err());
+    }
+}
@@ -0,0 +1,258 @@
+/// Photometric lights from IES (IESNA LM-63) files.
+///
+/// Manufacturers publish the light distribution of real luminaires as IES files: a table of
+/// candela values over a grid of vertical and horizontal angles. We use the table as the shape
+/// of a point light's emission, normalized so that the brightest direction has the light's
+/// intensity.
+///
+/// Only type C photometry is supported, which covers almost every file in the wild. The
+/// vertical angle is measured from the nadir (0 degrees points along the light's direction)
+/// and the horizontal angle around it.
+///
+/// ## References
+/// 1. ANSI/IES LM-63-19 - Standard File Format for the Electronic Transfer of Photometric Data
+/// 2. http://lumen.iee.put.poznan.pl/kw/iesna.txt
+use std::fs;
+use crate::na::Vector3;
+use crate::color::Color;
+use crate::shapes::bbox::BBox;
+use crate::geometry::tangents;
+use crate::lights::light::{Light, LightSample};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IesProfile {
+    /// Vertical angles in degrees, ascending
+    pub vertical: Vec<f64>,
+    /// Horizontal angles in degrees, ascending
+    pub horizontal: Vec<f64>,
+    /// Relative intensity for each horizontal angle, then each vertical angle. Peak is 1.
+    pub values: Vec<Vec<f64>>,
+}
+
+/// Index and fraction of x between the angles at i and i + 1. Clamps to the ends.
+fn locate(angles: &[f64], x: f64) -> (usize, f64) {
+    if angles.len() < 2 || x <= angles[0] {
+        return (0, 0.);
+    }
+    let last = angles.len() - 1;
+    if x >= angles[last] {
+        return (last, 0.);
+    }
+    let i = angles.partition_point(|a| *a <= x) - 1;
+    let span = angles[i + 1] - angles[i];
+    (i, if span > 0. { (x - angles[i]) / span } else { 0. })
+}
+
+impl IesProfile {
+    pub fn from_file(pth: &str) -> IesProfile {
+        let src = match fs::read_to_string(pth) {
+            Ok(s) => s,
+            Err(e) => panic!("Could not load {}: {:?}", pth, e),
+        };
+        match IesProfile::parse(&src) {
+            Ok(p) => p,
+            Err(e) => panic!("Could not parse {}: {}", pth, e),
+        }
+    }
+
+    pub fn parse(src: &str) -> Result<IesProfile, String> {
+        let mut lines = src.lines();
+        let tilt = loop {
+            match lines.next() {
+                Some(l) if l.trim_start().starts_with("TILT=") => break l.trim()[5..].to_string(),
+                Some(_) => continue,
+                None => return Err("missing TILT line".to_string()),
+            }
+        };
+
+        let rest: Vec<&str> = lines.collect();
+        let mut nums = rest.iter()
+            .flat_map(|l| l.split(|c: char| c.is_whitespace() || c == ','))
+            .filter(|t| !t.is_empty())
+            .map(|t| t.parse::<f64>().map_err(|_| format!("invalid number {}", t)));
+        let mut next = || nums.next().unwrap_or(Err("unexpected end of file".to_string()));
+
+        if tilt == "INCLUDE" {
+            // Lamp to luminaire geometry, then a table of tilt angles and factors we don't use
+            next()?;
+            let pairs = next()? as usize;
+            for _ in 0 .. pairs * 2 {
+                next()?;
+            }
+        }
+
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let n_vertical = next()? as usize;
+        let n_horizontal = next()? as usize;
+        let photometric_type = next()?;
+        if photometric_type != 1. {
+            println!("!! [ Warning in IES parse ] Only type C photometry is supported, got type {}", photometric_type);
+        }
+        // Units, width, length, height, ballast factor, future use, input watts
+        for _ in 0 .. 7 {
+            next()?;
+        }
+
+        if n_vertical == 0 || n_horizontal == 0 {
+            return Err("no angles".to_string());
+        }
+        let vertical = (0 .. n_vertical).map(|_| next()).collect::<Result<Vec<f64>, String>>()?;
+        let horizontal = (0 .. n_horizontal).map(|_| next()).collect::<Result<Vec<f64>, String>>()?;
+        let mut values = Vec::with_capacity(n_horizontal);
+        for _ in 0 .. n_horizontal {
+            let row = (0 .. n_vertical).map(|_| next().map(|c| c * multiplier)).collect::<Result<Vec<f64>, String>>()?;
+            values.push(row);
+        }
+
+        let max = values.iter().flatten().cloned().fold(0., f64::max);
+        if max <= 0. {
+            return Err("no light emitted".to_string());
+        }
+        for row in values.iter_mut() {
+            for v in row.iter_mut() {
+                *v /= max;
+            }
+        }
+
+        Ok(IesProfile { vertical, horizontal, values })
+    }
+
+    /// Relative intensity (0 - 1) in a direction, from angles in degrees.
+    pub fn intensity(&self, vertical: f64, horizontal: f64) -> f64 {
+        if vertical < self.vertical[0] || vertical > self.vertical[self.vertical.len() - 1] {
+            return 0.;
+        }
+
+        // Files only describe as much of the distribution as they need to, given its symmetry.
+        let last = self.horizontal[self.horizontal.len() - 1];
+        let h = horizontal.rem_euclid(360.);
+        let h = if self.horizontal.len() == 1 {
+            0.
+        } else if last <= 90. {
+            let q = h % 180.;
+            if q > 90. { 180. - q } else { q }
+        } else if last <= 180. {
+            if h > 180. { 360. - h } else { h }
+        } else {
+            h
+        };
+
+        let (vi, vt) = locate(&self.vertical, vertical);
+        let (hi, ht) = locate(&self.horizontal, h);
+        let at = |hi: usize, vi: usize| {
+            let hi = hi.min(self.horizontal.len() - 1);
+            let vi = vi.min(self.vertical.len() - 1);
+            self.values[hi][vi]
+        };
+        let lower = at(hi, vi) * (1. - vt) + at(hi, vi + 1) * vt;
+        let upper = at(hi + 1, vi) * (1. - vt) + at(hi + 1, vi + 1) * vt;
+        lower * (1. - ht) + upper * ht
+    }
+}
+
+/// A point light with an intensity distribution from an IES profile. Like point lights, the
+/// intensity doesn't fall off with distance.
+pub struct IesLight {
+    pub position: Vector3<f64>,
+    /// The nadir of the profile, ie. the direction the luminaire points (normalized)
+    pub direction: Vector3<f64>,
+    pub profile: IesProfile,
+    pub color: Color,
+    pub intensity: f64,
+}
+
+impl IesLight {
+    /// Profile angles, in degrees, of the direction from the light to pt
+    fn angles(&self, pt: &Vector3<f64>) -> (f64, f64) {
+        let d = (pt - self.position).normalize();
+        let (t, b) = tangents(&self.direction);
+        let vertical = d.dot(&self.direction).clamp(-1., 1.).acos().to_degrees();
+        let horizontal = d.dot(&b).atan2(d.dot(&t)).to_degrees();
+        (vertical, horizontal)
+    }
+}
+
+impl Light for IesLight {
+    fn sample(&self, pt: &Vector3<f64>) -> LightSample {
+        let (vertical, horizontal) = self.angles(pt);
+        LightSample {
+            position: self.position,
+            color: self.color,
+            intensity: self.intensity * self.profile.intensity(vertical, horizontal),
+        }
+    }
+
+    fn bounds(&self) -> Option<BBox> {
+        Some(BBox::new(self.position, self.position))
+    }
+
+    fn power(&self) -> f64 {
+        self.intensity * self.color.luminance()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] test
+[MANUFAC] rays
+TILT=NONE
+1 1000 2 5 1 1 2 0.1 0.1 0
+1.0 1.0 20
+0 22.5 45 67.5 90
+0
+100, 80, 50, 20, 0
+";
+
+    const BILATERAL: &str = "IESNA:LM-63-2002
+TILT=NONE
+1 1000 1 2 3 1 2 0 0 0
+1 1 10
+0 90
+0 90 180
+10 10
+10 0
+10 5
+";
+
+    #[test]
+    fn test_parse_ies() {
+        let p = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(p.vertical, vec![0., 22.5, 45., 67.5, 90.]);
+        assert_eq!(p.horizontal, vec![0.]);
+        assert_eq!(p.values[0], vec![1., 0.8, 0.5, 0.2, 0.]);
+        assert!(IesProfile::parse("TILT=NONE\n1 1000").is_err());
+        assert!(IesProfile::parse("no tilt").is_err());
+    }
+
+    #[test]
+    fn test_ies_intensity() {
+        let p = IesProfile::parse(DOWNLIGHT).unwrap();
+        assert_eq!(p.intensity(0., 0.), 1.);
+        assert!((p.intensity(33.75, 123.) - 0.65).abs() < 1e-9);
+        assert_eq!(p.intensity(120., 0.), 0.);
+
+        let b = IesProfile::parse(BILATERAL).unwrap();
+        assert_eq!(b.intensity(90., 90.), 0.);
+        assert_eq!(b.intensity(90., 180.), 0.5);
+        // Mirrored about the 0-180 plane
+        assert!((b.intensity(90., 315.) - b.intensity(90., 45.)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ies_light_orientation() {
+        let l = IesLight {
+            position: Vector3::new(0., 10., 0.),
+            direction: Vector3::new(0., -1., 0.),
+            profile: IesProfile::parse(DOWNLIGHT).unwrap(),
+            color: Color::white(),
+            intensity: 2.,
+        };
+        assert_eq!(l.sample(&Vector3::new(0., 0., 0.)).intensity, 2.);
+        assert_eq!(l.sample(&Vector3::new(0., 20., 0.)).intensity, 0.);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::na::Vector3;
+use std::sync::Arc;
+use crate::color::Color;
+use crate::shapes::bbox::BBox;
+
+/// Lights at infinity (directional, environment) place their samples this far from the shaded
+/// point. Far enough to be outside any scene, but short of the `Infinite` geometry (eg. the
+/// skysphere) which would otherwise shadow them.
+pub const DISTANT: f64 = 1e9;
+
+/// A point on a light, chosen to illuminate a point in the scene.
+///
+/// Materials shade with a sample exactly as they would with a point light at `position`, so
+/// every kind of light works with the same direct lighting code. Lights that aren't points
+/// fold their size and sampling weight into `intensity`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LightSample {
+    pub position: Vector3<f64>,
+    pub color: Color,
+    pub intensity: f64,
+}
+
+pub trait Light: Sync + Send {
+    /// Choose a point on the light to illuminate `pt`.
+    fn sample(&self, pt: &Vector3<f64>) -> LightSample;
+
+    /// As `sample`, at a time (0 - 1) of the scene's motion. Only lights that move differ.
+    fn sample_at(&self, pt: &Vector3<f64>, _time: f64) -> LightSample {
+        self.sample(pt)
+    }
+
+    /// Region the light emits from, or None for lights at infinity.
+    fn bounds(&self) -> Option<BBox>;
+
+    /// Rough estimate of the total light emitted, used to choose between lights. It only needs
+    /// to be comparable between lights, not exact.
+    fn power(&self) -> f64;
+
+    /// Whether rays can hit the light (eg. the environment), so that its light already reaches
+    /// surfaces that scatter randomly without sampling it.
+    fn visible(&self) -> bool { false }
+}
+
+/// Lights that are also needed elsewhere (eg. the environment, which is seen by rays that miss)
+/// are shared with the scene's light list.
+impl<T: Light> Light for Arc<T> {
+    fn sample(&self, pt: &Vector3<f64>) -> LightSample {
+        (**self).sample(pt)
+    }
+
+    fn sample_at(&self, pt: &Vector3<f64>, time: f64) -> LightSample {
+        (**self).sample_at(pt, time)
+    }
+
+    fn bounds(&self) -> Option<BBox> {
+        (**self).bounds()
+    }
+
+    fn power(&self) -> f64 {
+        (**self).power()
+    }
+
+    fn visible(&self) -> bool {
+        (**self).visible()
+    }
+}
+
+/// A light moving by `velocity` over the scene's motion (ray time 0 - 1), like the geometry
+/// it lights from, so that moving emitters light their surroundings where they are.
+pub struct Moving {
+    pub light: Box<dyn Light>,
+    pub velocity: Vector3<f64>,
+}
+
+impl Light for Moving {
+    fn sample(&self, pt: &Vector3<f64>) -> LightSample {
+        self.sample_at(pt, 0.)
+    }
+
+    fn sample_at(&self, pt: &Vector3<f64>, time: f64) -> LightSample {
+        let offset = self.velocity * time;
+        let mut sample = self.light.sample_at(&(pt - offset), time);
+        sample.position += offset;
+        sample
+    }
+
+    /// Everywhere it passes through
+    fn bounds(&self) -> Option<BBox> {
+        self.light.bounds().map(|b| b.union(&BBox::new(b.min.coords + self.velocity, b.max.coords + self.velocity)))
+    }
+
+    fn power(&self) -> f64 {
+        self.light.power()
+    }
+
+    fn visible(&self) -> bool {
+        self.light.visible()
+    }
+}
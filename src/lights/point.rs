@@ -0,0 +1,30 @@
+use crate::na::Vector3;
+use crate::color::Color;
+use crate::shapes::bbox::BBox;
+use crate::lights::light::{Light, LightSample};
+
+/// An infinitely small omnidirectional light. For historical reasons the intensity doesn't
+/// fall off with distance.
+pub struct PointLight {
+    pub position: Vector3<f64>,
+    pub color: Color,
+    pub intensity: f64,
+}
+
+impl Light for PointLight {
+    fn sample(&self, _pt: &Vector3<f64>) -> LightSample {
+        LightSample {
+            position: self.position,
+            color: self.color,
+            intensity: self.intensity,
+        }
+    }
+
+    fn bounds(&self) -> Option<BBox> {
+        Some(BBox::new(self.position, self.position))
+    }
+
+    fn power(&self) -> f64 {
+        self.intensity * self.color.luminance()
+    }
+}
@@ -0,0 +1,83 @@
+use crate::na::Vector3;
+use crate::color::Color;
+use crate::shapes::bbox::BBox;
+use crate::lights::light::{Light, LightSample};
+
+/// A point light that only shines within a cone.
+///
+/// Inside `angle - penumbra` of the axis the light is at full intensity, and it fades smoothly
+/// to nothing at `angle`. Like point lights, the intensity doesn't fall off with distance.
+pub struct SpotLight {
+    pub position: Vector3<f64>,
+    /// Direction the light points (normalized)
+    pub direction: Vector3<f64>,
+    /// Half angle of the cone, in radians
+    pub angle: f64,
+    /// Width of the soft edge of the cone, in radians
+    pub penumbra: f64,
+    pub color: Color,
+    pub intensity: f64,
+}
+
+impl SpotLight {
+    /// Fraction of the intensity reaching `pt`
+    pub fn falloff(&self, pt: &Vector3<f64>) -> f64 {
+        let cos_theta = (pt - self.position).normalize().dot(&self.direction);
+        let cos_outer = self.angle.cos();
+        let cos_inner = (self.angle - self.penumbra.min(self.angle)).cos();
+        if cos_theta >= cos_inner {
+            return 1.;
+        }
+        if cos_theta <= cos_outer {
+            return 0.;
+        }
+        let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3. - 2. * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, pt: &Vector3<f64>) -> LightSample {
+        LightSample {
+            position: self.position,
+            color: self.color,
+            intensity: self.intensity * self.falloff(pt),
+        }
+    }
+
+    fn bounds(&self) -> Option<BBox> {
+        Some(BBox::new(self.position, self.position))
+    }
+
+    fn power(&self) -> f64 {
+        self.intensity * self.color.luminance()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spot_falloff() {
+        let l = SpotLight {
+            position: Vector3::new(0., 10., 0.),
+            direction: Vector3::new(0., -1., 0.),
+            angle: 30f64.to_radians(),
+            penumbra: 10f64.to_radians(),
+            color: Color::white(),
+            intensity: 1.,
+        };
+        let at_angle = |deg: f64| {
+            let d = deg.to_radians();
+            l.falloff(&Vector3::new(10. * d.sin(), 10. - 10. * d.cos(), 0.))
+        };
+        assert_eq!(at_angle(0.), 1.);
+        assert_eq!(at_angle(15.), 1.);
+        let edge = at_angle(25.);
+        assert!(edge > 0. && edge < 1.);
+        assert_eq!(at_angle(35.), 0.);
+        // Behind the light
+        assert_eq!(l.falloff(&Vector3::new(0., 20., 0.)), 0.);
+    }
+}
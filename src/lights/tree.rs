@@ -0,0 +1,264 @@
+/// Light hierarchy, for scenes with many lights.
+///
+/// Sampling every light from every shading point costs a shadow ray per light, which is far
+/// too slow once there are thousands of emitters (eg. firework particles). Instead we build a
+/// bounding volume hierarchy over the lights, where each node knows its bounds and the total
+/// power of the lights below it. To pick a light we walk down from the root, choosing each
+/// child in proportion to an estimate of how much it contributes to the shading point - its
+/// power over the squared distance to it. The chosen light's sample is divided by the chance of
+/// choosing it, so distant and dim lights are rarely sampled but the estimate stays unbiased.
+///
+/// Lights at infinity (directional, environment) have no position, so like infinite objects in
+/// the SceneGraph they are kept outside the tree and always sampled.
+///
+/// Materials that scatter diffusely already receive light from lights that rays can hit
+/// (emissive objects, the environment), so they only sample the hidden ones. Each node also
+/// knows the power of its hidden lights, so they can be chosen without the visible ones.
+///
+/// ## References
+/// 1. Conty Estevez, Kulla - Importance Sampling of Many Lights with Adaptive Tree Splitting (2018)
+use crate::na::Vector3;
+use crate::shapes::bbox::BBox;
+use crate::lights::light::Light;
+use crate::geometry::rand;
+
+struct LightNode {
+    bounds: BBox,
+    power: f64,
+    /// Power of the lights below that rays can't hit
+    hidden_power: f64,
+    /// Index of the light for leaves, or of the child nodes
+    light: usize,
+    children: Option<(usize, usize)>,
+}
+
+pub struct LightTree {
+    pub lights: Vec<Box<dyn Light>>,
+    infinite: Vec<usize>,
+    /// Lights in the tree
+    finite: Vec<usize>,
+    nodes: Vec<LightNode>,
+    root: Option<usize>,
+}
+
+impl LightTree {
+    pub fn new(lights: Vec<Box<dyn Light>>) -> LightTree {
+        let mut infinite = Vec::new();
+        let mut finite = Vec::new();
+        for (i, l) in lights.iter().enumerate() {
+            match l.bounds() {
+                Some(_) => if l.power() > 0. { finite.push(i) },
+                None => infinite.push(i),
+            }
+        }
+
+        let mut tree = LightTree { lights, infinite, finite: finite.clone(), nodes: Vec::new(), root: None };
+        if !finite.is_empty() {
+            tree.root = Some(tree.build(&mut finite));
+        }
+        tree
+    }
+
+    fn build(&mut self, items: &mut [usize]) -> usize {
+        if items.len() == 1 {
+            let l = &self.lights[items[0]];
+            self.nodes.push(LightNode {
+                bounds: l.bounds().unwrap(),
+                power: l.power(),
+                hidden_power: if l.visible() { 0. } else { l.power() },
+                light: items[0],
+                children: None,
+            });
+            return self.nodes.len() - 1;
+        }
+
+        // Split at the median along the longest axis of the light centres
+        let centre = |i: &usize| self.lights[*i].bounds().unwrap().mid();
+        let mut centres = BBox::new(centre(&items[0]), centre(&items[0]));
+        for i in items.iter() {
+            centres = centres.union_point(&centre(i));
+        }
+        let size = centres.size();
+        let axis = if size.x >= size.y && size.x >= size.z { 0 } else if size.y >= size.z { 1 } else { 2 };
+        items.sort_by(|a, b| centre(a)[axis].total_cmp(&centre(b)[axis]));
+
+        let mid = items.len() / 2;
+        let (l, r) = items.split_at_mut(mid);
+        let left = self.build(l);
+        let right = self.build(r);
+        let node = LightNode {
+            bounds: self.nodes[left].bounds.union(&self.nodes[right].bounds),
+            power: self.nodes[left].power + self.nodes[right].power,
+            hidden_power: self.nodes[left].hidden_power + self.nodes[right].hidden_power,
+            light: 0,
+            children: Some((left, right)),
+        };
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// Estimated contribution of a node to pt, from all its lights or only the hidden ones
+    fn importance(&self, node: usize, pt: &Vector3<f64>, hidden: bool) -> f64 {
+        let n = &self.nodes[node];
+        let power = if hidden { n.hidden_power } else { n.power };
+        // Inside (or near) a cluster we can't tell which lights are closer, so don't let the
+        // distance drop below the size of the cluster.
+        let radius = n.bounds.size().norm() / 2.;
+        let dist2 = (pt - n.bounds.mid()).norm_squared().max(radius * radius).max(1e-9);
+        power / dist2
+    }
+
+    /// Walk down the tree to pick a light (only hidden ones if `hidden`). Returns it and the
+    /// probability of having chosen it.
+    fn pick(&self, pt: &Vector3<f64>, hidden: bool) -> Option<(usize, f64)> {
+        let mut node = self.root?;
+        if hidden && self.nodes[node].hidden_power <= 0. {
+            return None;
+        }
+        let mut prob = 1.;
+        while let Some((left, right)) = self.nodes[node].children {
+            let il = self.importance(left, pt, hidden);
+            let ir = self.importance(right, pt, hidden);
+            let p_left = if il + ir > 0. { il / (il + ir) } else { 0.5 };
+            if rand() < p_left {
+                node = left;
+                prob *= p_left;
+            } else {
+                node = right;
+                prob *= 1. - p_left;
+            }
+        }
+        Some((self.nodes[node].light, prob))
+    }
+
+    /// Choose the lights to sample from pt, with the weight to scale each sample by.
+    /// If there are no more than `n` lights they are all used, otherwise `n` are picked.
+    pub fn choose(&self, pt: &Vector3<f64>, n: usize) -> Vec<(&dyn Light, f64)> {
+        self.choose_from(pt, n, false)
+    }
+
+    /// As `choose`, but only lights that rays can't hit, for materials that scatter diffusely
+    /// and so already receive the others.
+    pub fn choose_hidden(&self, pt: &Vector3<f64>, n: usize) -> Vec<(&dyn Light, f64)> {
+        self.choose_from(pt, n, true)
+    }
+
+    fn choose_from(&self, pt: &Vector3<f64>, n: usize, hidden: bool) -> Vec<(&dyn Light, f64)> {
+        let wanted = |i: &&usize| !hidden || !self.lights[**i].visible();
+        let mut chosen: Vec<(&dyn Light, f64)> = self.infinite.iter()
+            .filter(wanted)
+            .map(|i| (&*self.lights[*i], 1.))
+            .collect();
+
+        let finite: Vec<&usize> = self.finite.iter().filter(wanted).collect();
+        if finite.len() <= n {
+            chosen.extend(finite.iter().map(|i| (&*self.lights[**i], 1.)));
+            return chosen;
+        }
+
+        for _ in 0 .. n {
+            if let Some((i, prob)) = self.pick(pt, hidden) {
+                chosen.push((&*self.lights[i], 1. / (prob * n as f64)));
+            }
+        }
+        chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::lights::point::PointLight;
+    use crate::lights::directional::DirectionalLight;
+
+    fn grid(n: usize) -> Vec<Box<dyn Light>> {
+        (0 .. n).map(|i| Box::new(PointLight {
+            position: Vector3::new((i % 10) as f64 * 3., 5., (i / 10) as f64 * 3.),
+            color: Color::white(),
+            intensity: 1. + (i % 7) as f64,
+        }) as Box<dyn Light>).collect()
+    }
+
+    #[test]
+    fn test_choose_all_when_few() {
+        let mut lights = grid(3);
+        lights.push(Box::new(DirectionalLight {
+            direction: Vector3::new(0., -1., 0.),
+            angular_diameter: 0.,
+            color: Color::white(),
+            intensity: 1.,
+        }));
+        let t = LightTree::new(lights);
+        let chosen = t.choose(&Vector3::new(0., 0., 0.), 4);
+        assert_eq!(chosen.len(), 4);
+        assert!(chosen.iter().all(|(_, w)| *w == 1.));
+    }
+
+    #[test]
+    fn test_choose_is_unbiased() {
+        // The weighted sum of chosen intensities should average the total intensity
+        let lights = grid(200);
+        let total: f64 = lights.iter().map(|l| l.power()).sum();
+        let t = LightTree::new(lights);
+        let pt = Vector3::new(4., 0., 7.);
+        let trials = 20000;
+        let mut sum = 0.;
+        for _ in 0 .. trials {
+            for (l, w) in t.choose(&pt, 4) {
+                sum += l.sample(&pt).intensity * w;
+            }
+        }
+        let mean = sum / trials as f64;
+        assert!((mean - total).abs() / total < 0.05, "{} != {}", mean, total);
+    }
+
+    /// A light that rays can hit, like an emissive object
+    struct Seen(PointLight);
+
+    impl Light for Seen {
+        fn sample(&self, pt: &Vector3<f64>) -> crate::lights::light::LightSample { self.0.sample(pt) }
+        fn bounds(&self) -> Option<BBox> { self.0.bounds() }
+        fn power(&self) -> f64 { self.0.power() }
+        fn visible(&self) -> bool { true }
+    }
+
+    #[test]
+    fn test_choose_hidden() {
+        let mut lights = grid(20);
+        let hidden: f64 = lights.iter().map(|l| l.power()).sum();
+        lights.extend((0 .. 20).map(|i| Box::new(Seen(PointLight {
+            position: Vector3::new(i as f64, 1., 0.),
+            color: Color::white(),
+            intensity: 10.,
+        })) as Box<dyn Light>));
+        let t = LightTree::new(lights);
+        let pt = Vector3::new(4., 0., 7.);
+        assert_eq!(t.choose_hidden(&pt, 20).len(), 20);
+        assert!(t.choose_hidden(&pt, 20).iter().all(|(l, w)| !l.visible() && *w == 1.));
+
+        let trials = 20000;
+        let mut sum = 0.;
+        for _ in 0 .. trials {
+            let chosen = t.choose_hidden(&pt, 4);
+            assert_eq!(chosen.len(), 4);
+            for (l, w) in chosen {
+                assert!(!l.visible());
+                sum += l.sample(&pt).intensity * w;
+            }
+        }
+        let mean = sum / trials as f64;
+        assert!((mean - hidden).abs() / hidden < 0.05, "{} != {}", mean, hidden);
+    }
+
+    #[test]
+    fn test_prefers_nearby_lights() {
+        // Picking uniformly, the three closest lights would be chosen 3% of the time
+        let t = LightTree::new(grid(100));
+        let pt = Vector3::new(0., 4., 0.);
+        let near = (0 .. 1000)
+            .filter(|_| t.pick(&pt, false).map(|(i, _)| i < 2 || i == 10).unwrap_or(false))
+            .count();
+        assert!(near > 150, "{}", near);
+    }
+}
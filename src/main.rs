@@ -35,10 +35,21 @@ mod material {
     pub mod legacy;
     pub mod plastic;
     pub mod noise;
+    pub mod image_texture;
+    pub mod mtl;
 }
 mod intersection;
 mod sceneobject;
-mod light;
+mod lights {
+    pub mod light;
+    pub mod point;
+    pub mod area;
+    pub mod spot;
+    pub mod directional;
+    pub mod ies;
+    pub mod environment;
+    pub mod tree;
+}
 mod shapes {
     pub mod transform;
     pub mod bbox;
@@ -50,6 +61,16 @@ mod shapes {
     pub mod mesh;
     pub mod infinite;
     pub mod repeating_mesh;
+    pub mod rectangle;
+    pub mod disk;
+    pub mod instance;
+    pub mod cylinder;
+    pub mod torus;
+    pub mod sdf;
+    pub mod heightfield;
+    pub mod voxels;
+    pub mod implicit;
+    pub mod subdivision;
 }
 mod octree;
 mod scenegraph;
@@ -61,7 +82,7 @@ mod camera;
 mod trace;
 mod rendercontext;
 mod paint;
-//mod wireframe;
+mod wireframe;
 mod geometry;
 mod procedural {
     pub mod box_terrain;
@@ -100,6 +121,10 @@ struct Args {
     ///Set the height of the output image; overrides the scenefile if specified
     #[arg(long)]
     height: Option<usize>,
+
+    ///Draw the bounds of objects and the partitions of the scene's octree over the render
+    #[arg(short, long)]
+    wireframe: bool,
 }
 
 fn main() {
@@ -110,15 +135,14 @@ fn main() {
     );
 
     let s = scenefile::SceneFile::from_file(
-       &args.scene 
+       &args.scene,
+       args.width,
+       args.height,
     );
-    let width = args.width.unwrap_or(s.image.width);
-    let height = args.height.unwrap_or(s.image.height);
-    // TODO: Overriding here isn't picked up in the camera config that happens in the parse.
     
     let rc = RenderContext::new(
-            width,
-            height,
+            s.image.width,
+            s.image.height,
             args.progressive_render,
             &args.scene,
             );
@@ -155,9 +179,15 @@ fn main() {
         });
     pb.finish();
 
-    let rc = rcmtx.lock().unwrap();
-    //wireframe::wireframe(&s, &mut rc);
-    paint::to_png(&rc);
+    let mut rc = rcmtx.lock().unwrap();
+    if args.wireframe {
+        wireframe::wireframe(&s, &mut rc);
+    }
+    if s.image.stereo_files {
+        paint::to_stereo_pngs(&rc);
+    } else {
+        paint::to_png(&rc);
+    }
     paint::poor_mans(&rc);
     rc.print_stats();
 }
@@ -2,7 +2,7 @@ use crate::color::Color;
 use crate::scene::Scene;
 use crate::material::model::{MaterialModel, ScatteredRay};
 use crate::intersection::Intersection;
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::geometry::{rand};
 use crate::material::functions::scatter_dielectric;
 
@@ -15,4 +15,14 @@ impl MaterialModel for Dielectric {
     fn scatter(&self, r: &Ray, intersection: &Intersection, _s: &Scene) -> ScatteredRay{
         scatter_dielectric(self.refractive_index, self.attenuate, r, intersection)
     }
+
+    fn scatter_differential(&self, r: &Ray, d: &RayDifferential, intersection: &Intersection, scattered: &Ray) -> Option<RayDifferential> {
+        let drn = r.rd.dot(&intersection.normal);
+        if drn * scattered.rd.dot(&intersection.normal) < 0. {
+            return Some(d.reflect(r, intersection, &scattered.rd));
+        }
+        // As scatter_dielectric, assuming the other side is air
+        let eta = if drn > 0. { self.refractive_index } else { 1. / self.refractive_index };
+        Some(d.refract(r, intersection, &scattered.rd, eta))
+    }
 }
@@ -15,4 +15,8 @@ impl MaterialModel for DiffuseLight {
             attenuate:self.color * self.intensity,
             ray: None }
     }
+
+    fn emission(&self) -> Option<Color> {
+        Some(self.color * self.intensity)
+    }
 }
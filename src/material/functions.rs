@@ -5,7 +5,8 @@ use crate::geometry::{random_point_on_unit_sphere};
 use crate::intersection::Intersection;
 use crate::color::Color;
 use crate::geometry::{rand};
-use crate::light::Light;
+use crate::lights::light::{Light, LightSample};
+use crate::scene::Scene;
 
 pub fn refract(v: Vector3<f64>, n: Vector3<f64>, ni_over_nt:f64) -> Option<Vector3<f64>> {
     let uv = v.normalize();
@@ -34,7 +35,9 @@ pub fn reflect(v: Vector3<f64>, normal: Vector3<f64>) -> Vector3<f64> {
 pub fn scatter_lambertian(albedo: Color, intersection: &Intersection) -> ScatteredRay {
     let refl = Ray {
         ro: intersection.point,
-        rd: intersection.normal + random_point_on_unit_sphere(),
+        // Unit length, as shapes' intersections (spheres, triangles) assume it
+        rd: (intersection.normal + random_point_on_unit_sphere()).normalize(),
+        time: intersection.time,
     };
     ScatteredRay{ attenuate:albedo, ray: Some(refl) }
 }
@@ -79,7 +82,8 @@ pub fn scatter_dielectric(
                     attenuate: albedo,
                     ray: Some( Ray {
                         ro: intersection.point + (refracted * 0.001),
-                        rd: refracted
+                        rd: refracted,
+                        time: r.time
                     }),
                 };
             }
@@ -95,13 +99,48 @@ pub fn scatter_dielectric(
         attenuate: Color::white(),
         ray: Some(Ray {
             ro: intersection.point,
-            rd: reflected
+            rd: reflected,
+            time: r.time
         }) 
     }
 }
 
 
-pub fn diffuse (pigment: Color, i: &Intersection, light_vec: &Vector3<f64>, light: &Light) -> Color {
+/// Sample the lights in the scene from the intersection, returning the vector to the sampled
+/// point and the sample for each that isn't in shadow. In scenes with many lights only some
+/// are sampled, with their intensity scaled up to compensate.
+pub fn visible_lights(intersection: &Intersection, s: &Scene, min: f64) -> Vec<(Vector3<f64>, LightSample)> {
+    unshadowed(intersection, s, min, s.lights.choose(&intersection.point, s.render.light_samples))
+}
+
+/// As visible_lights, but only for lights that rays can't hit (points, spots, the sun...),
+/// which randomly scattering materials would otherwise never receive. Lights that rays can
+/// hit already reach them by scattering, so sampling those too would count them twice.
+pub fn visible_hidden_lights(intersection: &Intersection, s: &Scene, min: f64) -> Vec<(Vector3<f64>, LightSample)> {
+    unshadowed(intersection, s, min, s.lights.choose_hidden(&intersection.point, s.render.light_samples))
+}
+
+fn unshadowed(intersection: &Intersection, s: &Scene, min: f64, chosen: Vec<(&dyn Light, f64)>) -> Vec<(Vector3<f64>, LightSample)> {
+    let mut visible = Vec::with_capacity(chosen.len());
+    for (light, weight) in chosen {
+        let mut sample = light.sample_at(&intersection.point, intersection.time);
+        sample.intensity *= weight;
+        if sample.intensity <= 0. {
+            continue;
+        }
+        let light_vec = sample.position - intersection.point;
+        let dist = light_vec.norm();
+        let shadow_ray = Ray {ro: intersection.point, rd: light_vec / dist, time: intersection.time};
+        // Stop just short of the light, so that emissive objects don't shadow themselves.
+        let shadow_intersection = s.objects.nearest_intersection(&shadow_ray, dist * (1. - 1e-6), min);
+        if shadow_intersection.is_none() {
+            visible.push((light_vec, sample));
+        }
+    }
+    visible
+}
+
+pub fn diffuse (pigment: Color, i: &Intersection, light_vec: &Vector3<f64>, light: &LightSample) -> Color {
     let diffuse_scale = light_vec.normalize().dot(&i.normal) * light.intensity;
     if diffuse_scale.is_sign_positive() {
         light.color * pigment * diffuse_scale
@@ -0,0 +1,182 @@
+use crate::color::Color;
+use crate::ray::{Ray, RayDifferential};
+use crate::intersection::Intersection;
+use crate::scene::Scene;
+use crate::material::model::{MaterialModel, ScatteredRay};
+use crate::na::Vector2;
+use std::path::Path;
+
+/// One level of a mip map
+struct Level {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Level {
+    /// Pixel at (x, y), repeating the image in both directions
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    /// Bilinear interpolation between the pixels around (u, v)
+    fn bilerp(&self, u: f64, v: f64) -> Color {
+        let x = u * self.width as f64 - 0.5;
+        let y = v * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        self.texel(x0, y0) * ((1. - dx) * (1. - dy)) +
+            self.texel(x0 + 1, y0) * (dx * (1. - dy)) +
+            self.texel(x0, y0 + 1) * ((1. - dx) * dy) +
+            self.texel(x0 + 1, y0 + 1) * (dx * dy)
+    }
+
+    /// Half the size, each pixel the average of the 2x2 pixels it covers
+    fn downsample(&self) -> Level {
+        let width = self.width.div_ceil(2);
+        let height = self.height.div_ceil(2);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                // Odd sizes repeat the last row / column
+                let (x0, y0) = (2 * x, 2 * y);
+                let x1 = (x0 + 1).min(self.width - 1);
+                let y1 = (y0 + 1).min(self.height - 1);
+                pixels.push((
+                    self.pixels[y0 * self.width + x0] +
+                    self.pixels[y0 * self.width + x1] +
+                    self.pixels[y1 * self.width + x0] +
+                    self.pixels[y1 * self.width + x1]) / 4.);
+            }
+        }
+        Level { width, height, pixels }
+    }
+}
+
+/// An image pre-filtered at every power of two size, down to a single pixel, so that lookups
+/// can average over a footprint of any size in constant time.
+///
+/// ## References
+/// 1. PBRT - 10.4 Image Texture
+pub struct MipMap {
+    levels: Vec<Level>,
+}
+
+impl MipMap {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> MipMap {
+        assert!(width > 0 && height > 0 && pixels.len() == width * height, "Bad image size for mip map");
+        let mut levels = vec![Level { width, height, pixels }];
+        while levels.last().map(|l| l.width > 1 || l.height > 1).unwrap() {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+        MipMap { levels }
+    }
+
+    pub fn from_file(pth: &str) -> MipMap {
+        match MipMap::open(Path::new(pth)) {
+            Ok(m) => m,
+            Err(e) => panic!("Could not load {}: {:?}", pth, e),
+        }
+    }
+
+    pub fn open(pth: &Path) -> image::ImageResult<MipMap> {
+        let img = image::open(pth)?.to_rgb32f();
+        let pixels = img.pixels().map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64)).collect();
+        Ok(MipMap::new(img.width() as usize, img.height() as usize, pixels))
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Colour at `uv` averaged over a footprint `width` across (in uv), trilinearly
+    /// interpolated between the two levels whose pixels are closest to that size.
+    /// v runs up the image, as it does up most surfaces.
+    pub fn lookup(&self, uv: Vector2<f64>, width: f64) -> Color {
+        let (u, v) = (uv.x, 1. - uv.y);
+        let top = (self.levels.len() - 1) as f64;
+        let level = top + width.max(1e-12).log2();
+        if level <= 0. {
+            return self.levels[0].bilerp(u, v);
+        }
+        if level >= top {
+            return self.levels[self.levels.len() - 1].bilerp(u, v);
+        }
+        let l = level.floor();
+        let t = level - l;
+        let l = l as usize;
+        self.levels[l].bilerp(u, v) * (1. - t) + self.levels[l + 1].bilerp(u, v) * t
+    }
+}
+
+/// Image texture material that tints a base material by an image, mapped onto the
+/// surface by its uv coordinates.
+pub struct ImageTexture {
+    /// Base material model to apply the image to
+    pub base_material: Box<dyn MaterialModel + Sync + Send>,
+    /// Image, filtered for every footprint size
+    pub texture: MipMap,
+    /// Number of times the image repeats across the uv range
+    pub scale: f64,
+}
+
+impl ImageTexture {
+    /// Colour of the texture at an intersection, filtered over its footprint
+    fn color_at(&self, intersection: &Intersection) -> Color {
+        self.texture.lookup(intersection.uv * self.scale, intersection.footprint.uv_width() * self.scale)
+    }
+}
+
+impl MaterialModel for ImageTexture {
+    fn scatter(&self, r: &Ray, intersection: &Intersection, s: &Scene) -> ScatteredRay {
+        let base_scatter = self.base_material.scatter(r, intersection, s);
+        ScatteredRay {
+            ray: base_scatter.ray,
+            attenuate: base_scatter.attenuate * self.color_at(intersection),
+        }
+    }
+
+    fn direct(&self, r: &Ray, intersection: &Intersection, s: &Scene) -> Color {
+        self.base_material.direct(r, intersection, s) * self.color_at(intersection)
+    }
+
+    fn scatter_differential(&self, r: &Ray, d: &RayDifferential, intersection: &Intersection, scattered: &Ray) -> Option<RayDifferential> {
+        self.base_material.scatter_differential(r, d, intersection, scattered)
+    }
+
+    fn samples_lights(&self) -> bool {
+        self.base_material.samples_lights()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checks(size: usize) -> MipMap {
+        let pixels = (0 .. size * size)
+            .map(|i| if (i % size + i / size).is_multiple_of(2) { Color::white() } else { Color::black() })
+            .collect();
+        MipMap::new(size, size, pixels)
+    }
+
+    #[test]
+    fn test_mipmap_levels() {
+        assert_eq!(checks(8).levels(), 4);
+        assert_eq!(MipMap::new(3, 1, vec![Color::white(); 3]).levels(), 3);
+    }
+
+    #[test]
+    fn test_mipmap_filters_by_width() {
+        let m = checks(8);
+        let uv = Vector2::new(1. / 16., 1. / 16.);
+        // A point lookup is a single check, a wide one is the average of them all
+        assert_eq!(m.lookup(uv, 0.).rgb.x, 0.);
+        assert!((m.lookup(uv, 1.).rgb.x - 0.5).abs() < 1e-9);
+        assert!((m.lookup(uv, 0.5).rgb.x - 0.5).abs() < 1e-9);
+    }
+}
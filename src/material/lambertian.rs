@@ -3,7 +3,7 @@ use crate::ray::Ray;
 use crate::intersection::Intersection;
 use crate::scene::Scene;
 use crate::material::model::{MaterialModel, ScatteredRay};
-use crate::material::functions::scatter_lambertian;
+use crate::material::functions::{scatter_lambertian, diffuse, visible_hidden_lights};
 
 pub struct Lambertian {
     pub albedo: Color,
@@ -12,5 +12,60 @@ impl MaterialModel for Lambertian {
     fn scatter(&self, _r: &Ray, intersection: &Intersection, _s: &Scene) -> ScatteredRay{
         scatter_lambertian(self.albedo, intersection)
     }
+
+    /// Random bounces can never hit point-like lights, and almost never find the sun, so
+    /// sample the lights that rays can't hit directly.
+    fn direct(&self, _r: &Ray, intersection: &Intersection, s: &Scene) -> Color {
+        visible_hidden_lights(intersection, s, f64::MIN_POSITIVE)
+            .iter()
+            .fold(Color::black(), |acc, (light_vec, light)| acc + diffuse(self.albedo, intersection, light_vec, light))
+    }
+
+    fn samples_lights(&self) -> bool {
+        true
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::na::Vector3;
+    use crate::scenefile::SceneFile;
+    use crate::trace::trace;
+
+    /// Mean brightness of a floor of `material`, a Lambertian (W) or noise over it (N),
+    /// looking straight down at it under `lights`
+    fn floor_brightness(material: &str, lights: &str) -> f64 {
+        let scene = SceneFile::from_string(format!(r#"{{
+            "variables": {{}}, "output": "/dev/null", "width": 8, "height": 8,
+            "supersamples": 1, "chunk_size": 8, "samples_per_chunk": 1,
+            "background": [0, 0, 0], "shadow_bias": 0.0001, "max_depth": 2,
+            "materials": {{
+                "W": {{"type": "lambertian", "albedo": [0.8, 0.8, 0.8]}},
+                "N": {{"type": "noise", "base_material": "W", "noise_type": "perlin", "color": [0.2, 0.4, 0.8]}}
+            }},
+            "media": {{}},
+            "camera": {{"location": [0, 2, -5], "lookat": [0, 0, 0], "up": [0, 1, 0], "angle": 0.8}},
+            "lights": [{}],
+            "objects": [{{"type": "plane", "y": 0, "material": "{}"}}]
+        }}"#, lights, material), None, None);
+        let r = Ray::new(Vector3::new(0., 1., 0.), Vector3::new(0., -1., 0.));
+        let samples = 1000;
+        (0 .. samples).map(|_| trace(&r, 0, &scene).1.luminance()).sum::<f64>() / samples as f64
+    }
+
+    #[test]
+    fn test_lit_by_lights_rays_cant_hit() {
+        assert_eq!(floor_brightness("W", ""), 0.);
+        // Area, point and spot lights
+        for light in [
+            r#"{"type": "sphere", "location": [0, 3, 0], "radius": 0.5, "intensity": 4}"#,
+            r#"{"location": [0, 3, 0], "color": "white", "intensity": 1}"#,
+            r#"{"type": "spot", "location": [0, 3, 0], "lookat": [0, 0, 0], "angle": 30, "color": "white", "intensity": 1}"#,
+        ] {
+            assert!(floor_brightness("W", light) > 0.05, "{}", light);
+            assert!(floor_brightness("N", light) > 0.05, "noise: {}", light);
+        }
+    }
+}
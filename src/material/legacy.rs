@@ -4,8 +4,7 @@ use crate::material::model::{MaterialModel, ScatteredRay};
 use crate::intersection::Intersection;
 use crate::ray::Ray;
 use crate::na::Vector3;
-use crate::light::Light;
-use crate::material::functions::{reflect, diffuse, phong};
+use crate::material::functions::{reflect, diffuse, phong, visible_lights};
 
 pub struct Whitted {
     pub pigment: Color,
@@ -16,21 +15,15 @@ pub struct Whitted {
 impl MaterialModel for Whitted {
     fn scatter(&self, r: &Ray, intersection: &Intersection, s: &Scene) -> ScatteredRay{
         let mut out = Color::black();
-        for light in &s.lights {
-            let light_vec = light.position - intersection.point;
-            let shadow_ray = Ray {ro: intersection.point, rd: light_vec.normalize()};
-            let shadow_intersection = s.objects.nearest_intersection(&shadow_ray, light_vec.norm(), 0.001); 
-
-            match shadow_intersection {
-                Some(_) => (),// Point in shadow...
-                None => out = diffuse(self.pigment, intersection, &light_vec, light) + phong(self.phong, r, intersection, &light_vec),
-            }
+        for (light_vec, light) in visible_lights(intersection, s, 0.001) {
+            out = out + diffuse(self.pigment, intersection, &light_vec, &light) + phong(self.phong, r, intersection, &light_vec);
         }
 
         if self.reflection > 0. {
             let refl = Ray {
                 ro: intersection.point,
-                rd: reflect(r.rd, intersection.normal)
+                rd: reflect(r.rd, intersection.normal),
+                time: r.time
             };
             return ScatteredRay{ attenuate: out * self.reflection, ray: Some(refl) };
         }
@@ -47,3 +40,36 @@ impl MaterialModel for FlatColor {
         ScatteredRay{ attenuate: self.pigment, ray: None }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::na::Vector3;
+    use crate::ray::Ray;
+    use crate::scenefile::SceneFile;
+    use crate::trace::trace;
+
+    /// Brightness of a Whitted floor at the origin under `lights`
+    fn floor_brightness(lights: &str) -> f64 {
+        let scene = SceneFile::from_string(format!(r#"{{
+            "variables": {{}}, "output": "/dev/null", "width": 8, "height": 8,
+            "supersamples": 1, "chunk_size": 8, "samples_per_chunk": 1,
+            "background": [0, 0, 0], "shadow_bias": 0.0001, "max_depth": 2,
+            "materials": {{"F": {{"type": "whitted", "pigment": [0.5, 0.5, 0.5]}}}},
+            "media": {{}},
+            "camera": {{"location": [0, 2, -5], "lookat": [0, 0, 0], "up": [0, 1, 0], "angle": 0.8}},
+            "lights": [{}],
+            "objects": [{{"type": "plane", "y": 0, "material": "F"}}]
+        }}"#, lights), None, None);
+        let r = Ray::new(Vector3::new(0., 1., 0.), Vector3::new(0., -1., 0.));
+        trace(&r, 0, &scene).1.luminance()
+    }
+
+    #[test]
+    fn test_whitted_adds_lights() {
+        let left = r#"{"location": [-2, 3, 0], "color": "white", "intensity": 1}"#;
+        let right = r#"{"location": [1, 2, 0], "color": "white", "intensity": 2}"#;
+        let (l, r) = (floor_brightness(left), floor_brightness(right));
+        assert!(l > 0. && r > 0.);
+        assert!((floor_brightness(&format!("{}, {}", left, right)) - (l + r)).abs() < 1e-9);
+    }
+}
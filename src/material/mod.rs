@@ -68,6 +68,7 @@ pub mod functions;
 pub mod lambertian;
 pub mod legacy;
 pub mod model;
+pub mod mtl;
 pub mod normal;
 pub mod noise;
 pub mod plastic;
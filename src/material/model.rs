@@ -1,5 +1,5 @@
 use crate::color::Color;
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::intersection::Intersection;
 use crate::scene::Scene;
 
@@ -29,13 +29,33 @@ use crate::scene::Scene;
 pub trait MaterialModel: Sync{
     /// Scatter an intersection ray.
     fn scatter(&self, r: &Ray, intersection: &Intersection, s: &Scene) -> ScatteredRay;
+
+    /// Light reflected directly from light sources, added to the traced ray. Only for materials
+    /// that don't already fold direct lighting into `attenuate`.
+    fn direct(&self, _r: &Ray, _intersection: &Intersection, _s: &Scene) -> Color { Color::black() }
+
+    /// Light emitted by the material, if it is a light source.
+    fn emission(&self) -> Option<Color> { None }
+
+    /// Whether `direct` samples the lights that rays can't hit, so that rays it scatters
+    /// shouldn't count the emitters among those lights again.
+    fn samples_lights(&self) -> bool { false }
+
+    /// Whether the material's emission is also one of the scene's lights that rays can't hit
+    /// (eg. firework particles), and so is already counted where `samples_lights`.
+    fn sampled_emitter(&self) -> bool { false }
+
+    /// Differentials of a scattered ray, given the incoming ray's. Only worth following for
+    /// specular bounces, where the footprint stays small; other materials spread the ray out
+    /// so much that filtering at its footprint would blur everything.
+    fn scatter_differential(&self, _r: &Ray, _d: &RayDifferential, _intersection: &Intersection, _scattered: &Ray) -> Option<RayDifferential> { None }
 }
 
 /// The outgoing ray, and the weight to assign the color of the traced ray.
 /// - Attenuate: the scaling of the subsequent reflections/refractions
 /// - Option<Ray>:
-///     Some: Another ray to cast into the image, multiply by attenuate
-///     None: Return attenuate (attenuate the background color)
+///   Some: Another ray to cast into the image, multiply by attenuate
+///   None: Return attenuate (attenuate the background color)
 ///
 #[derive(Clone, Debug, PartialEq)]
 pub struct ScatteredRay {
@@ -0,0 +1,183 @@
+/// Conversion of Wavefront .mtl materials (as loaded by tobj) into material models.
+///
+/// MTL describes a Phong-era material with a handful of loosely specified properties. We map
+/// the ones that matter onto the closest material model we have:
+///
+/// - `Ke` (emission) non-black        -> DiffuseLight
+/// - `d` < 1, or illum 4, 6, 7, 9     -> Dielectric, using `Ni` as the refractive index
+/// - illum 3, 5 (reflection on)       -> Specular, tinted by `Ks` with roughness from `Ns`
+/// - `Ks` non-black                   -> Plastic, `Ks` gives the chance of a glossy bounce
+/// - otherwise                        -> Lambertian with `Kd`
+///
+/// `map_Kd` is loaded relative to the .obj as an ImageTexture over the material, mapped by the
+/// mesh's texture coordinates.
+///
+/// ## References
+/// 1. http://paulbourke.net/dataformats/mtl/
+use std::path::Path;
+use tobj;
+use crate::color::Color;
+use crate::material::model::MaterialModel;
+use crate::material::lambertian::Lambertian;
+use crate::material::plastic::Plastic;
+use crate::material::specular::Specular;
+use crate::material::dielectric::Dielectric;
+use crate::material::diffuse_light::DiffuseLight;
+use crate::material::image_texture::{ImageTexture, MipMap};
+
+fn to_color(c: [f32; 3]) -> Color {
+    Color::new(c[0] as f64, c[1] as f64, c[2] as f64)
+}
+
+fn max_component(c: Color) -> f64 {
+    c.rgb.x.max(c.rgb.y).max(c.rgb.z)
+}
+
+/// Parse a colour from an unrecognised MTL parameter, eg. "Ke 1.0 0.8 0.2"
+fn param_color(m: &tobj::Material, key: &str) -> Option<Color> {
+    let v: Vec<f64> = m.unknown_param.get(key)?
+        .split_whitespace()
+        .filter_map(|x| x.parse::<f64>().ok())
+        .collect();
+    match v.len() {
+        0 => None,
+        1 | 2 => Some(Color::new(v[0], v[0], v[0])),
+        _ => Some(Color::new(v[0], v[1], v[2])),
+    }
+}
+
+/// Map Phong shininess (`Ns`, 0-1000) to a roughness (0-1)
+/// Uses the Beckmann equivalence alpha = sqrt(2 / (Ns + 2))
+pub fn roughness_from_shininess(ns: f64) -> f64 {
+    (2. / (ns.max(0.) + 2.)).sqrt().min(1.)
+}
+
+/// The `map_Kd` image, or None if there isn't one or it can't be read.
+fn diffuse_texture(m: &tobj::Material, base_dir: &Path) -> Option<MipMap> {
+    if m.diffuse_texture.is_empty() {
+        return None;
+    }
+    match MipMap::open(&base_dir.join(&m.diffuse_texture)) {
+        Ok(t) => Some(t),
+        Err(_) => {
+            println!("!! [ Warning in MTL parse ] Could not read texture {} for {}", m.diffuse_texture, m.name);
+            None
+        }
+    }
+}
+
+/// Convert an MTL material to a material model. Textures are resolved relative to `base_dir`,
+/// which should be the directory of the .obj file.
+pub fn from_mtl(m: &tobj::Material, base_dir: &Path) -> Box<dyn MaterialModel + Sync + Send> {
+    if let Some(ke) = param_color(m, "Ke") {
+        let intensity = max_component(ke);
+        if intensity > 0. {
+            return Box::new(DiffuseLight {
+                color: ke / intensity,
+                intensity,
+            });
+        }
+    }
+
+    let texture = diffuse_texture(m, base_dir);
+    let mut kd = to_color(m.diffuse);
+    // MTL says the map is multiplied by Kd, but exporters commonly leave Kd black or unset
+    // when a map is present.
+    if texture.is_some() && max_component(kd) == 0. {
+        kd = Color::white();
+    }
+    let ks = to_color(m.specular);
+    let ni = if m.optical_density > 0. { m.optical_density as f64 } else { 1. };
+    let roughness = roughness_from_shininess(m.shininess as f64);
+    let illum = m.illumination_model.unwrap_or(2);
+
+    let base = surface(kd, ks, ni, roughness, m.dissolve, illum);
+    match texture {
+        Some(texture) => Box::new(ImageTexture { base_material: base, texture, scale: 1. }),
+        None => base,
+    }
+}
+
+/// The non-emissive material model for a diffuse colour `kd`
+fn surface(kd: Color, ks: Color, ni: f64, roughness: f64, dissolve: f32, illum: u8) -> Box<dyn MaterialModel + Sync + Send> {
+    if dissolve < 1. || [4, 6, 7, 9].contains(&illum) {
+        return Box::new(Dielectric {
+            refractive_index: ni,
+            attenuate: if max_component(kd) > 0. { kd } else { Color::white() },
+        });
+    }
+
+    if illum == 3 || illum == 5 {
+        return Box::new(Specular {
+            albedo: if max_component(ks) > 0. { ks } else { kd },
+            roughness,
+        });
+    }
+
+    if max_component(ks) > 0. {
+        return Box::new(Plastic {
+            albedo: kd,
+            refractive_index: if ni > 1. { ni } else { 1.5 },
+            roughness,
+            opacity: max_component(ks).min(1.),
+        });
+    }
+
+    Box::new(Lambertian { albedo: kd })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn material() -> tobj::Material {
+        tobj::Material {
+            name: "test".to_string(),
+            ambient: [0.; 3],
+            diffuse: [0.5, 0.4, 0.3],
+            specular: [0.; 3],
+            shininess: 0.,
+            dissolve: 1.,
+            optical_density: 1.,
+            ambient_texture: String::new(),
+            diffuse_texture: String::new(),
+            specular_texture: String::new(),
+            normal_texture: String::new(),
+            dissolve_texture: String::new(),
+            illumination_model: None,
+            unknown_param: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_param_color() {
+        let mut m = material();
+        m.unknown_param.insert("Ke".to_string(), "1.0 0.5 0.25".to_string());
+        assert_eq!(param_color(&m, "Ke"), Some(Color::new(1., 0.5, 0.25)));
+        m.unknown_param.insert("Ke".to_string(), "2".to_string());
+        assert_eq!(param_color(&m, "Ke"), Some(Color::new(2., 2., 2.)));
+        assert_eq!(param_color(&m, "Tf"), None);
+    }
+
+    #[test]
+    fn test_roughness_from_shininess() {
+        assert_eq!(roughness_from_shininess(0.), 1.);
+        assert!(roughness_from_shininess(1000.) < 0.05);
+        assert!(roughness_from_shininess(10.) > roughness_from_shininess(100.));
+    }
+
+    #[test]
+    fn test_diffuse_texture() {
+        let dir = std::env::temp_dir();
+        image::RgbImage::from_pixel(4, 2, image::Rgb([255, 0, 0])).save(dir.join("rays-mtl-test.png")).unwrap();
+        let mut m = material();
+        assert!(diffuse_texture(&m, &dir).is_none());
+        m.diffuse_texture = "rays-mtl-test.png".to_string();
+        let texture = diffuse_texture(&m, &dir).unwrap();
+        assert_eq!(texture.levels(), 3);
+        assert_eq!(texture.lookup(crate::na::Vector2::new(0.3, 0.6), 0.), Color::new(1., 0., 0.));
+        m.diffuse_texture = "missing.png".to_string();
+        assert!(diffuse_texture(&m, &dir).is_none());
+    }
+}
@@ -1,10 +1,14 @@
 use crate::color::Color;
-use crate::ray::Ray;
-use crate::intersection::Intersection;
+use crate::ray::{Ray, RayDifferential};
+use crate::intersection::{Intersection, Footprint};
 use crate::scene::Scene;
 use crate::material::model::{MaterialModel, ScatteredRay};
 use crate::na::Vector3;
-use crate::noise::{PerlinNoise, WorleyNoise};
+use crate::noise::{PerlinNoise, WorleyNoise, octave_weight};
+use std::f64::consts::PI;
+
+// Average of |perlin noise|, which turbulence octaves fade to once they're too fine to resolve.
+const MEAN_ABS_PERLIN: f64 = 0.22;
 
 /// Noise texture material that modifies the color of a base material
 /// based on noise functions.
@@ -152,16 +156,24 @@ impl NoiseTexture {
         }
     }
 
-    /// Generate turbulence value at a point
-    fn turbulence(&self, p: Vector3<f64>, octaves: u32) -> f64 {
+    /// Generate turbulence value at a point, averaged over a footprint `width` across
+    fn turbulence(&self, p: Vector3<f64>, octaves: u32, width: f64) -> f64 {
         let mut value = 0.0;
         let mut temp_p = p;
         let mut weight = 1.0;
+        let mut frequency = 1.0;
 
         for _ in 0..octaves {
-            value += weight * self.perlin.noise(temp_p.x, temp_p.y, temp_p.z).abs();
+            let resolved = octave_weight(frequency * width);
+            let octave = if resolved > 0. {
+                self.perlin.noise(temp_p.x, temp_p.y, temp_p.z).abs()
+            } else {
+                0.
+            };
+            value += weight * (octave * resolved + MEAN_ABS_PERLIN * (1. - resolved));
             weight *= 0.5;
             temp_p *= 2.0;
+            frequency *= 2.0;
         }
 
         value
@@ -169,19 +181,30 @@ impl NoiseTexture {
 
     /// Calculate noise value at a point based on the selected noise type
     fn noise_value(&self, p: Vector3<f64>) -> f64 {
+        self.filtered_noise_value(p, &Footprint::zero())
+    }
+
+    /// Noise value averaged over the footprint of a ray differential around `p`.
+    ///
+    /// Perlin based patterns clamp away frequencies the footprint can't resolve. Worley noise
+    /// has no such decomposition, so it's sampled at a random point within the footprint and
+    /// left to the pixel's samples to average.
+    fn filtered_noise_value(&self, p: Vector3<f64>, footprint: &Footprint) -> f64 {
+        let width = footprint.width() * self.scale;
         let scaled_p = p * self.scale;
 
         match &self.noise_type {
             NoiseType::Perlin => {
                 // Map from [-1,1] to [0,1]
-                (self.perlin.noise(scaled_p.x, scaled_p.y, scaled_p.z) + 1.0) * 0.5
+                (self.perlin.noise(scaled_p.x, scaled_p.y, scaled_p.z) * octave_weight(width) + 1.0) * 0.5
             }
             NoiseType::Fbm { octaves, persistence, lacunarity } => {
-                self.perlin.fbm(scaled_p.x, scaled_p.y, scaled_p.z, *octaves, *persistence, *lacunarity)
+                self.perlin.fbm_filtered(scaled_p.x, scaled_p.y, scaled_p.z, *octaves, *persistence, *lacunarity, width)
             }
             NoiseType::Worley { .. } => {
                 if let Some(worley) = &self.worley {
-                    let value = worley.noise(scaled_p.x, scaled_p.y, scaled_p.z);
+                    let jittered = scaled_p + footprint.jitter() * self.scale;
+                    let value = worley.noise(jittered.x, jittered.y, jittered.z);
                     // Normalize Worley noise to [0,1] range (approximately)
                     (1.0 - value.min(1.0)).max(0.0)
                 } else {
@@ -190,17 +213,19 @@ impl NoiseTexture {
             }
             NoiseType::Marble => {
                 let pattern = scaled_p.x + 
-                    self.perlin.fbm(
+                    self.perlin.fbm_filtered(
                         scaled_p.x, 
                         scaled_p.y, 
                         scaled_p.z, 
-                        4, 0.5, 2.0
+                        4, 0.5, 2.0,
+                        width
                     ) * 10.0;
                 
-                (pattern.sin() * 0.5 + 0.5).abs()
+                // The stripes repeat every 2π
+                (pattern.sin() * octave_weight(width / (2. * PI)) * 0.5 + 0.5).abs()
             }
             NoiseType::Turbulence { octaves } => {
-                self.turbulence(scaled_p, *octaves)
+                self.turbulence(scaled_p, *octaves, width)
             }
         }
     }
@@ -212,7 +237,7 @@ impl MaterialModel for NoiseTexture {
         let base_scatter = self.base_material.scatter(r, intersection, s);
         
         // Calculate noise value at the intersection point
-        let noise_value = self.noise_value(intersection.point);
+        let noise_value = self.filtered_noise_value(intersection.point, &intersection.footprint);
         
         // Blend the base material's color with the noise color based on the noise value
         let noise_influence = noise_value * self.blend_factor;
@@ -224,6 +249,27 @@ impl MaterialModel for NoiseTexture {
             attenuate: blended_color,
         }
     }
+
+    /// The base material's direct light, tinted from its colour to the blended colour
+    fn direct(&self, r: &Ray, intersection: &Intersection, s: &Scene) -> Color {
+        let direct = self.base_material.direct(r, intersection, s);
+        if direct == Color::black() {
+            return direct;
+        }
+        let base = self.base_material.scatter(r, intersection, s).attenuate;
+        let noise_influence = self.filtered_noise_value(intersection.point, &intersection.footprint) * self.blend_factor;
+        let blended = base.blend(&self.color, noise_influence);
+        let tint = blended.rgb.zip_map(&base.rgb, |b, a| if a > 0. { b / a } else { 0. });
+        Color { rgb: direct.rgb.component_mul(&tint) }
+    }
+
+    fn scatter_differential(&self, r: &Ray, d: &RayDifferential, intersection: &Intersection, scattered: &Ray) -> Option<RayDifferential> {
+        self.base_material.scatter_differential(r, d, intersection, scattered)
+    }
+
+    fn samples_lights(&self) -> bool {
+        self.base_material.samples_lights()
+    }
 }
 
 #[cfg(test)]
@@ -4,7 +4,7 @@ use crate::intersection::Intersection;
 use crate::scene::Scene;
 use crate::geometry::{random_point_on_unit_sphere, rand};
 use crate::material::model::{MaterialModel, ScatteredRay};
-use crate::material::functions::{scatter_lambertian, scatter_dielectric, diffuse};
+use crate::material::functions::{scatter_lambertian, scatter_dielectric, diffuse, visible_hidden_lights};
 
 
 /// A plastic model.
@@ -26,16 +26,9 @@ impl MaterialModel for Plastic {
         if diffuse_probability > self.opacity {
             let mut diffuse_refl = Color::black() + s.render.background;
 
-            for light in &s.lights {
-                let light_vec = light.position - intersection.point;
-                let shadow_ray = Ray {ro: intersection.point, rd: light_vec};
-                let shadow_intersection = s.objects.nearest_intersection(&shadow_ray, light_vec.norm(), f64::MIN_POSITIVE); 
-                match shadow_intersection {
-                    Some(_) => (),// Point in shadow...
-                    None => {
-                        diffuse_refl = diffuse_refl + diffuse(self.albedo, intersection, &light_vec, light);
-                    },
-                }
+            // Lights rays can hit are found by the diffuse bounce
+            for (light_vec, light) in visible_hidden_lights(intersection, s, f64::MIN_POSITIVE) {
+                diffuse_refl = diffuse_refl + diffuse(self.albedo, intersection, &light_vec, &light);
             }
             
             scatter_lambertian(diffuse_refl, intersection)
@@ -2,7 +2,7 @@ use crate::color::Color;
 use crate::scene::Scene;
 use crate::material::model::{MaterialModel, ScatteredRay};
 use crate::intersection::Intersection;
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::geometry::{random_point_on_unit_sphere};
 use crate::material::functions::{reflect};
 
@@ -17,11 +17,16 @@ impl MaterialModel for Specular {
 
         let refl = Ray {
             ro: intersection.point,
-            rd: reflect(r.rd, intersection.normal) + fuzz
+            rd: reflect(r.rd, intersection.normal) + fuzz,
+            time: r.time
         };
 
         ScatteredRay{ attenuate:self.albedo, ray: Some(refl) }
     }
+
+    fn scatter_differential(&self, r: &Ray, d: &RayDifferential, intersection: &Intersection, scattered: &Ray) -> Option<RayDifferential> {
+        Some(d.reflect(r, intersection, &scattered.rd))
+    }
 }
 
 
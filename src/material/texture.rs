@@ -1,9 +1,20 @@
-use crate::na::{Vector3};
+use crate::na::{Vector2, Vector3};
 use crate::material::model::MaterialModel;
+use crate::intersection::{Intersection, Footprint};
+use crate::geometry::rand;
 use crate::noise::{PerlinNoise, WorleyNoise, combined_noise};
+use crate::shapes::voxels::VoxelGrid;
+use std::sync::Arc;
 
 pub trait Medium : Sync{
     fn material_at(&self, pt: Vector3<f64>) -> &(dyn MaterialModel + Sync + Send); 
+
+    /// The material at an intersection, filtered over its footprint. Patterns with detail
+    /// finer than the footprint would alias, so pick between materials in proportion to how
+    /// much of the footprint each covers.
+    fn material_for(&self, intersection: &Intersection) -> &(dyn MaterialModel + Sync + Send) {
+        self.material_at(intersection.point)
+    }
 }
 
 pub struct Solid {
@@ -34,6 +45,100 @@ impl Medium for CheckeredYPlane {
         // zig XOR zag
         if zig != zag { &*self.m1 } else { &*self.m2 }
     }
+
+    fn material_for(&self, intersection: &Intersection) -> &(dyn MaterialModel + Sync + Send) {
+        let coverage = self.m1_coverage(intersection.point, &intersection.footprint);
+        if coverage == 0. || coverage == 1. {
+            return self.material_at(intersection.point);
+        }
+        if rand() < coverage { &*self.m1 } else { &*self.m2 }
+    }
+}
+
+impl CheckeredYPlane {
+    /// How much of a footprint around `pt` falls on `m1` squares, box filtering the
+    /// checks analytically.
+    ///
+    /// ## References
+    /// 1. PBRT - 10.5.3 Antialiasing the checkerboard
+    pub fn m1_coverage(&self, pt: Vector3<f64>, footprint: &Footprint) -> f64 {
+        let dx = footprint.dpdx.x.abs().max(footprint.dpdy.x.abs()) / self.xsize;
+        let dz = footprint.dpdx.z.abs().max(footprint.dpdy.z.abs()) / self.zsize;
+        checker_coverage(pt.x / self.xsize, pt.z / self.zsize, dx, dz)
+    }
+}
+
+/// Checks laid out in the surface coordinates of whatever they're applied to, so that they
+/// follow its orientation (eg. a tilted plane) rather than the world axes.
+///
+/// Away from a surface there are no surface coordinates, so `material_at` checks over x and
+/// z like `CheckeredYPlane`.
+pub struct Checkered {
+    pub m1: Box<dyn MaterialModel + Sync + Send>,
+    pub m2: Box<dyn MaterialModel + Sync + Send>,
+    pub usize: f64,
+    pub vsize: f64,
+}
+
+impl Checkered {
+    /// How much of a footprint around `uv` falls on `m1` squares
+    pub fn m1_coverage(&self, uv: Vector2<f64>, footprint: &Footprint) -> f64 {
+        let du = footprint.duvdx.x.abs().max(footprint.duvdy.x.abs()) / self.usize;
+        let dv = footprint.duvdx.y.abs().max(footprint.duvdy.y.abs()) / self.vsize;
+        checker_coverage(uv.x / self.usize, uv.y / self.vsize, du, dv)
+    }
+}
+
+impl Medium for Checkered {
+    fn material_at(&self, pt: Vector3<f64>) -> &(dyn MaterialModel + Sync + Send) {
+        let coverage = self.m1_coverage(Vector2::new(pt.x, pt.z), &Footprint::zero());
+        if coverage == 1. { &*self.m1 } else { &*self.m2 }
+    }
+
+    fn material_for(&self, intersection: &Intersection) -> &(dyn MaterialModel + Sync + Send) {
+        let coverage = self.m1_coverage(intersection.uv, &intersection.footprint);
+        if rand() < coverage { &*self.m1 } else { &*self.m2 }
+    }
+}
+
+/// Fraction of a `ds` x `dt` box around (s, t) that is on `m1` checks, where checks are a
+/// unit across.
+fn checker_coverage(s: f64, t: f64, ds: f64, dt: f64) -> f64 {
+    let ss = bump_average(s, ds / 2.);
+    let st = bump_average(t, dt / 2.);
+    // m1 where exactly one of the waves is up
+    ss + st - 2. * ss * st
+}
+
+/// Integral from 0 to x of a square wave that is 1 over [1, 2), [3, 4) ...
+fn bump_int(x: f64) -> f64 {
+    (x / 2.).floor() + 2. * (x / 2. - (x / 2.).floor() - 0.5).max(0.)
+}
+
+/// Average of the square wave over [x - w, x + w]
+fn bump_average(x: f64, w: f64) -> f64 {
+    if w == 0. {
+        return (x.floor().rem_euclid(2.) == 1.) as u8 as f64;
+    }
+    (bump_int(x + w) - bump_int(x - w)) / (2. * w)
+}
+
+/// The materials of a voxel grid, picked by the palette index of each voxel.
+pub struct VoxelPalette {
+    pub grid: Arc<VoxelGrid>,
+    /// Indexed by palette index. Index 0 (empty) is only used for points outside any voxel.
+    pub materials: Vec<Box<dyn MaterialModel + Sync + Send>>,
+}
+
+impl Medium for VoxelPalette {
+    fn material_at(&self, pt: Vector3<f64>) -> &(dyn MaterialModel + Sync + Send) {
+        &*self.materials[self.grid.value_at(&pt) as usize]
+    }
+
+    fn material_for(&self, intersection: &Intersection) -> &(dyn MaterialModel + Sync + Send) {
+        // Normals face out of the voxel that was hit, so step back into it
+        self.material_at(intersection.point - intersection.geometric_normal * self.grid.voxel_size() / 2.)
+    }
 }
 
 /// A medium that mixes between two materials based on noise patterns
@@ -277,5 +382,59 @@ impl Medium for NoiseMedium {
             &*self.m1
         }
     }
+
+    // Thresholding isn't linear, so averaging the noise over the footprint first would pick
+    // the wrong material. Instead look up somewhere random within the footprint, so that each
+    // material is picked in proportion to its coverage over the samples of a pixel.
+    fn material_for(&self, intersection: &Intersection) -> &(dyn MaterialModel + Sync + Send) {
+        self.material_at(intersection.point + intersection.footprint.jitter())
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::material::lambertian::Lambertian;
+    use crate::shapes::plane::Plane;
+    use crate::shapes::geometry::Geometry;
+    use crate::ray::Ray;
+
+    #[test]
+    fn test_checker_coverage() {
+        let checks = CheckeredYPlane::new(
+            Box::new(Lambertian { albedo: Color::white() }),
+            Box::new(Lambertian { albedo: Color::black() }),
+            1., 1.);
+        let mut footprint = Footprint::zero();
+        // A point is entirely on one check
+        assert_eq!(checks.m1_coverage(Vector3::new(0.5, 0., 0.5), &footprint), 0.);
+        assert_eq!(checks.m1_coverage(Vector3::new(1.5, 0., 0.5), &footprint), 1.);
+        // Half way across a boundary
+        footprint.dpdx = Vector3::new(1., 0., 0.);
+        assert!((checks.m1_coverage(Vector3::new(1., 0., 0.5), &footprint) - 0.5).abs() < 1e-9);
+        // Many checks across average out
+        footprint.dpdx = Vector3::new(20., 0., 0.);
+        footprint.dpdy = Vector3::new(0., 0., 20.);
+        assert!((checks.m1_coverage(Vector3::new(0.3, 0., 0.7), &footprint) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_checks_follow_surface() {
+        let checks = Checkered {
+            m1: Box::new(Lambertian { albedo: Color::white() }),
+            m2: Box::new(Lambertian { albedo: Color::black() }),
+            usize: 1., vsize: 1.,
+        };
+        // Down onto a wall facing +x, where world x and z don't change across the surface
+        let wall = Plane::new(Vector3::zeros(), Vector3::new(1., 0., 0.));
+        let r = Ray::new(Vector3::new(5., 0.5, 0.5), Vector3::new(-1., 0., 0.));
+        let i = wall.intersects(&r).unwrap();
+        let footprint = Footprint::zero();
+        assert_eq!(checks.m1_coverage(i.uv, &footprint), 0.);
+        let r = Ray { ro: Vector3::new(5., 0.5, 1.5), ..r };
+        let i = wall.intersects(&r).unwrap();
+        assert_eq!(checks.m1_coverage(i.uv, &footprint), 1.);
+    }
+}
@@ -1,12 +1,15 @@
-/// Noise module for procedural patterns generation
-/// 
-/// This module implements various noise functions used for procedural generation,
-/// including 3D Perlin noise and fractal Brownian motion (fBm).
-/// Used for creating procedural textures and patterns.
+//! Noise module for procedural patterns generation
+//! 
+//! This module implements various noise functions used for procedural generation,
+//! including 3D Perlin noise and fractal Brownian motion (fBm).
+//! Used for creating procedural textures and patterns.
 
 use crate::na::Vector3;
 use std::f64;
 use std::f64::consts::PI;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
 
 /// Perlin noise generator for 3D space
 #[derive(Clone)]
@@ -63,6 +66,16 @@ impl PerlinNoise {
         
         Self { perm, grad3 }
     }
+
+    /// A different, but repeatable, noise pattern for each seed
+    pub fn with_seed(seed: u64) -> Self {
+        let mut noise = Self::new();
+        let mut rng = StdRng::seed_from_u64(seed);
+        noise.perm[..256].shuffle(&mut rng);
+        let (first, second) = noise.perm.split_at_mut(256);
+        second.copy_from_slice(first);
+        noise
+    }
     
     /// Get noise value at a 3D point
     pub fn noise(&self, x: f64, y: f64, z: f64) -> f64 {
@@ -123,13 +136,25 @@ impl PerlinNoise {
     /// * `persistence` - How much each octave's amplitude decreases (typically 0.5)
     /// * `lacunarity` - How much each octave's frequency increases (typically 2.0)
     pub fn fbm(&self, x: f64, y: f64, z: f64, octaves: u32, persistence: f64, lacunarity: f64) -> f64 {
+        self.fbm_filtered(x, y, z, octaves, persistence, lacunarity, 0.)
+    }
+
+    /// fBm averaged over a footprint `width` across (in noise space).
+    ///
+    /// Octaves too fine to be resolved over the footprint are faded out to their mean
+    /// rather than aliasing (frequency clamping).
+    #[allow(clippy::too_many_arguments)]
+    pub fn fbm_filtered(&self, x: f64, y: f64, z: f64, octaves: u32, persistence: f64, lacunarity: f64, width: f64) -> f64 {
         let mut result = 0.0;
         let mut amplitude = 1.0;
         let mut frequency = 1.0;
         let mut max_value = 0.0;
         
         for _ in 0..octaves {
-            result += self.noise(x * frequency, y * frequency, z * frequency) * amplitude;
+            let weight = octave_weight(frequency * width);
+            if weight > 0. {
+                result += self.noise(x * frequency, y * frequency, z * frequency) * amplitude * weight;
+            }
             max_value += amplitude;
             amplitude *= persistence;
             frequency *= lacunarity;
@@ -160,6 +185,12 @@ impl PerlinNoise {
     }
 }
 
+/// How much of a noise octave survives being averaged over a footprint `width` across,
+/// measured in the octave's own lattice cells: all of it under half a cell, none past a whole one.
+pub fn octave_weight(width: f64) -> f64 {
+    (2. - 2. * width).clamp(0., 1.)
+}
+
 /// Worley noise (cellular noise) generator
 #[derive(Clone)]
 pub struct WorleyNoise {
@@ -442,8 +473,6 @@ mod tests {
             println!("{}", line);
         }
         println!("----------------------------------------");
-        
         // This test always passes - it's for visual inspection
-        assert!(true);
     }
 }
\ No newline at end of file
@@ -461,7 +461,8 @@ impl MaterialModel for OceanMaterial {
 			attenuate: Color::white(),
 			ray: Some(Ray {
 				ro: intersection.point,
-				rd: reflected
+				rd: reflected,
+				time: r.time
 			}) 
 		}
 	}
@@ -137,11 +137,10 @@ impl<T: Geometry> Octree<T> {
         let mut cdist = max;
         let mut closest = None;
         for i in items {
-            if let Some(x) = self.items[i].intersects(r) {
-                if x.dist < cdist && x.dist >= min {
-                    cdist = x.dist;
-                    closest = Some((i, x));
-                }
+            if let Some(x) = self.items[i].intersects(r)
+                && x.dist < cdist && x.dist >= min {
+                cdist = x.dist;
+                closest = Some((i, x));
             }
         }
         closest
@@ -151,6 +150,21 @@ impl<T: Geometry> Octree<T> {
        self.root.bounds 
     }
 
+    pub fn items(&self) -> &[Arc<T>] {
+        &self.items
+    }
+
+    /// Bounds of every node in the tree, outermost first
+    pub fn partitions(&self) -> Vec<BBox> {
+        let mut out = Vec::new();
+        let mut stack = vec![&self.root];
+        while let Some(node) = stack.pop() {
+            out.push(node.bounds);
+            stack.extend(node.children.iter().flatten().map(|c| c.as_ref()));
+        }
+        out
+    }
+
 /*
     pub fn new_node(&self, txm:f64, x:u8, tym:f64, y:u8, tzm:f64, z:u8) -> u8{
         if txm < tym {
@@ -218,10 +232,7 @@ impl<T: Geometry> Octree<T> {
             a |= 1 ; 
         }
 
-        let rn = Ray {
-            ro: Vector3::new(ro0, ro1, ro2),
-            rd: Vector3::new(rd0, rd1, rd2),
-        };
+        let rn = Ray::new(Vector3::new(ro0, ro1, ro2), Vector3::new(rd0, rd1, rd2));
 
         let divx:f64 = 1.0 / rn.rd[0];
         let divy:f64 = 1.0 / rn.rd[1];
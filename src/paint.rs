@@ -2,7 +2,7 @@ extern crate image;
 
 use std::io::Write;
 use crate::rendercontext::RenderContext;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use termcolor::{BufferWriter, Color, ColorChoice, ColorSpec, WriteColor};
 
 pub fn to_png(ctx: &RenderContext) {
@@ -14,6 +14,27 @@ pub fn to_png(ctx: &RenderContext) {
     let _ = img.save(&Path::new(&ctx.output_filename));
 }
 
+/// `<dir>/<stem>-<eye>.<ext>` for an output `<dir>/<stem>.<ext>`, as png if it has no extension
+fn eye_filename(output_filename: &str, eye: &str) -> PathBuf {
+    let pth = Path::new(output_filename);
+    let stem = pth.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let ext = pth.extension().map(|e| e.to_string_lossy()).unwrap_or("png".into());
+    pth.with_file_name(format!("{}-{}.{}", stem, eye, ext))
+}
+
+/// Write the left and right halves of a side by side stereo render beside the output file, as
+/// `<stem>-left.<ext>` and `<stem>-right.<ext>`.
+pub fn to_stereo_pngs(ctx: &RenderContext) {
+    let eye_width = ctx.width / 2;
+    for (eye, offset) in [("left", 0), ("right", eye_width)] {
+        let img = image::ImageBuffer::from_fn(eye_width as u32, ctx.height as u32, |x, y| {
+            let (r,g,b) = ctx.get_pixel(x as usize + offset, ctx.height - y as usize - 1).as_u8();
+            image::Rgb([r,g,b])
+        });
+        let _ = img.save(eye_filename(&ctx.output_filename, eye));
+    }
+}
+
 
 pub fn poor_mans(ctx: &RenderContext) {
     let bufwtr = BufferWriter::stderr(ColorChoice::Auto);
@@ -35,3 +56,15 @@ pub fn poor_mans(ctx: &RenderContext) {
     write!(&mut buffer, "").expect("Could not write");
     bufwtr.print(&buffer).expect("Could not write");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eye_filename() {
+        assert_eq!(eye_filename("out/demo.png", "left"), PathBuf::from("out/demo-left.png"));
+        assert_eq!(eye_filename("a.png.dir/render.jpg", "right"), PathBuf::from("a.png.dir/render-right.jpg"));
+        assert_eq!(eye_filename("render", "left"), PathBuf::from("render-left.png"));
+    }
+}
@@ -48,6 +48,7 @@ impl MaterialModel for HomogenousFog {
             ray: Some(Ray {
                 ro: i.point,
                 rd: (r.rd + (random_point_on_unit_sphere() * self.scatter * rand())).normalize(),
+                time: r.time,
             }),
             attenuate: self.color,
         }
@@ -58,11 +59,7 @@ impl Geometry for HomogenousFog {
     fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
         if rand() < self.density {
             let dist = rand().powf(3.) * BIG_NUMBER; 
-            Some(RawIntersection {
-                dist,
-                point: r.ro + r.rd * dist,
-                normal: r.rd,
-            })
+            Some(RawIntersection::new(dist, r.ro + r.rd * dist, r.rd, true))
         } else {
             None
         }
@@ -21,26 +21,45 @@ use crate::octree::Octree;
 use crate::shapes::bbox::BBox;
 use crate::intersection::RawIntersection;
 use std::sync::Arc;
+use crate::lights::light::{Light, Moving};
+use crate::lights::area::{AreaLight, LightSampling};
 use std::f64;
 
 fn rand(rng: &mut StdRng) -> f64 {
     rng.gen_range(0.0, 1.0)
 }
 
+#[derive(Clone)]
 struct Particle {
     s: Sphere,
     intensity: f64,
+    /// How far the particle moves over the scene's motion (ray time 0 - 1)
+    velocity: Vector3<f64>,
 }
 
 impl Geometry for Particle {
-    fn intersects(&self, r: &Ray) -> Option<RawIntersection> { self.s.intersects(r) }
-    fn bounds(&self) -> BBox{ self.s.bounds() }
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        if self.velocity == Vector3::zeros() {
+            return self.s.intersects(r);
+        }
+        let offset = self.velocity * r.time;
+        let moved = Ray { ro: r.ro - offset, ..*r };
+        self.s.intersects(&moved).map(|mut i| {
+            i.point += offset;
+            i
+        })
+    }
+    fn bounds(&self) -> BBox{
+        let b = self.s.bounds();
+        b.union(&BBox::new(b.min.coords + self.velocity, b.max.coords + self.velocity))
+    }
 }
 
 
 // Misuse a ray as the initial position and speed vector.
 // assume gravity is down y-axis
-fn trace_particle(impulse: Ray, time: f64, samples: usize, gravity: f64) -> Vec<Particle>{
+// frame_time is the time in seconds that the scene's motion spans, for motion blur.
+fn trace_particle(impulse: Ray, time: f64, samples: usize, gravity: f64, frame_time: f64) -> Vec<Particle>{
     let mut particles = Vec::new();
 
     for x in 0..samples {
@@ -53,9 +72,11 @@ fn trace_particle(impulse: Ray, time: f64, samples: usize, gravity: f64) -> Vec<
         );
         let intensity = (t/time).powf(3.);
         let radius = intensity * 0.1;
+        let velocity = Vector3::new(impulse.rd.x, impulse.rd.y - gravity * t, impulse.rd.z);
         let p = Particle {
             s: Sphere::new(position, radius),
             intensity,
+            velocity: velocity * frame_time,
         };
         particles.push(p);
     }
@@ -73,6 +94,7 @@ fn create_particles(
         min_particles: usize,
         max_particles: usize,
         upward_bias: f64,
+        frame_time: f64,
     ) -> Vec<Particle> {
     let mut particles = Vec::new();
     let num_particles = min_particles + ((rand(rng) * (max_particles - min_particles) as f64) as usize);
@@ -80,11 +102,8 @@ fn create_particles(
     for _p in 0 .. num_particles {
         let u = rand(rng);
         let v = rand(rng);
-        let impulse = Ray {
-            ro: origin,
-            rd: (point_on_unit_sphere(u, v) * radius) + bias,
-        };
-        particles.append(&mut trace_particle(impulse, time, samples, gravity));
+        let impulse = Ray::new(origin, (point_on_unit_sphere(u, v) * radius) + bias);
+        particles.append(&mut trace_particle(impulse, time, samples, gravity, frame_time));
     }
     particles
 }
@@ -93,6 +112,8 @@ fn create_particles(
 pub struct FireworkMaterial {
     particles: Octree<Particle>,
     color: Color,
+    /// Whether the particles are also lights, sampled by diffuse surfaces
+    lights: bool,
 }
 
 impl MaterialModel for FireworkMaterial {
@@ -112,10 +133,30 @@ impl MaterialModel for FireworkMaterial {
             }
         }
     }
+
+    fn sampled_emitter(&self) -> bool {
+        self.lights
+    }
+}
+
+/// The light of a particle, moving with it
+fn particle_light(p: &Particle, color: Color) -> Box<dyn Light> {
+    let light = Box::new(AreaLight {
+        shape: Box::new(p.s.clone()),
+        color,
+        intensity: p.intensity,
+        sampling: LightSampling::SolidAngle,
+        two_sided: false,
+    });
+    if p.velocity == Vector3::zeros() {
+        return light;
+    }
+    Box::new(Moving { light, velocity: p.velocity })
 }
 
 // Radius is the radius of explosion at time=1.
-pub fn create_firework(o: &Value) -> SceneObject {
+// Returns the firework, and a light for each particle unless "area_light" is false.
+pub fn create_firework(o: &Value) -> (SceneObject, Vec<Box<dyn Light>>) {
 
     let mut rng: StdRng = SeedableRng::from_seed([0; 32]);
 
@@ -128,6 +169,10 @@ pub fn create_firework(o: &Value) -> SceneObject {
     let upward_bias = SceneFile::parse_number(&o["upward_bias"], 2.);
     let intensity = SceneFile::parse_number(&o["intensity"], 2.);
     let color = SceneFile::parse_color_def(o, "color", Color::white()) * intensity;
+    // Seconds the shutter interval covers; particles are frozen unless this is set.
+    let frame_time = SceneFile::parse_number(&o["frame_time"], 0.);
+    // Every particle is an emitter, so they light the scene unless asked not to.
+    let area_light = o["area_light"].as_bool().unwrap_or(true);
 
     let particles = create_particles(
                         &mut rng,
@@ -138,20 +183,97 @@ pub fn create_firework(o: &Value) -> SceneObject {
                         gravity,
                         num_particles,
                         num_particles,
-                        upward_bias
+                        upward_bias,
+                        frame_time,
                         );
+    let lights = particles
+                    .iter()
+                    .filter(|p| area_light && p.s.radius > 0.)
+                    .map(|p| particle_light(p, color))
+                    .collect();
     let boxed_particles = particles
                             .iter()
-                            .map(|p| Box::new(p.s.clone()) as Box<dyn Geometry + Sync + Send>)
+                            .map(|p| Box::new(p.clone()) as Box<dyn Geometry + Sync + Send>)
                             .collect();
     let geom = Union::new(boxed_particles);
 
     let particle_arcs: Vec<Arc<Particle>> = particles.into_iter().map(Arc::new).collect();
     let tree = Octree::new(8, geom.bounds(), &particle_arcs);
-    let m = Box::new(FireworkMaterial { particles: tree, color });
+    let m = Box::new(FireworkMaterial { particles: tree, color, lights: area_light });
 
-	SceneObject {
+	(SceneObject {
 		geometry: Box::new(geom),
 		medium: Box::new(Solid { m }),
-	}
+	}, lights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scenegraph::SceneGraph;
+    use crate::lights::tree::LightTree;
+    use crate::trace::trace;
+
+    /// Mean brightness of a floor of albedo 0.5 at the origin, under a white particle of
+    /// radius 1 and intensity 1 at (0, 2, 0)
+    fn floor_under_particle(lights: bool) -> f64 {
+        let mut scene = SceneFile::from_string(r#"{
+            "variables": {}, "output": "/dev/null", "width": 8, "height": 8,
+            "supersamples": 1, "chunk_size": 8, "samples_per_chunk": 1,
+            "background": [0, 0, 0], "shadow_bias": 0.0001, "max_depth": 2,
+            "materials": {"W": {"type": "lambertian", "albedo": [0.5, 0.5, 0.5]}},
+            "media": {},
+            "camera": {"location": [0, 1, -3], "lookat": [0, 0, 0], "up": [0, 1, 0], "angle": 0.8},
+            "lights": [],
+            "objects": [{"type": "plane", "y": 0, "material": "W"}]
+        }"#.to_string(), None, None);
+        let p = Particle { s: Sphere::new(Vector3::new(0., 2., 0.), 1.), intensity: 1., velocity: Vector3::zeros() };
+        let particles = Octree::new(8, p.bounds(), &[Arc::new(p.clone())]);
+        let m = Box::new(FireworkMaterial { particles, color: Color::white(), lights });
+        let mut objects = scene.objects.infinite_items.clone();
+        objects.push(Arc::new(SceneObject { geometry: Box::new(p.clone()), medium: Box::new(Solid { m }) }));
+        scene.objects = SceneGraph::new(2, objects, scene.max_bounding);
+        scene.lights = LightTree::new(if lights { vec![particle_light(&p, Color::white())] } else { Vec::new() });
+
+        let r = Ray::new(Vector3::new(0., 1., -3.), Vector3::new(0., -1., 3.));
+        let samples = 4000;
+        (0 .. samples).map(|_| trace(&r, 0, &scene).1.luminance()).sum::<f64>() / samples as f64
+    }
+
+    #[test]
+    fn test_particle_light_counted_once() {
+        // Lambertian radiance under a sphere of radiance L seen at angular radius a is
+        // albedo · L · sin²(a) = 0.5 · 1 · (1 / 2)²
+        let lit = floor_under_particle(true);
+        assert!((lit - 0.125).abs() < 0.006, "{}", lit);
+        // Without sampling the light, bounces still find the particle
+        assert!(floor_under_particle(false) > 0.05);
+    }
+
+    #[test]
+    fn test_particle_lights_move() {
+        let impulse = Ray::new(Vector3::new(0., 10., 0.), Vector3::new(3., 4., 0.));
+        let particles = trace_particle(impulse, 0.9, 10, 9.8, 0.5);
+        let p = particles.last().unwrap();
+        let light = particle_light(p, Color::white());
+        let pt = Vector3::new(0., 0., 0.);
+        for time in [0., 1.] {
+            let centre = p.s.center + p.velocity * time;
+            let mut lit = 0;
+            for _ in 0 .. 100 {
+                let s = light.sample_at(&pt, time);
+                // Samples finding no lit point fall back to the centre, unlit
+                if s.intensity > 0. {
+                    assert!(((s.position - centre).norm() - p.s.radius).abs() < 1e-9);
+                    lit += 1;
+                } else {
+                    assert!((s.position - centre).norm() < 1e-9);
+                }
+            }
+            assert!(lit > 0);
+        }
+        // Far enough that the two times can't both be on one sphere
+        assert!(p.velocity.norm() > 2. * p.s.radius);
+        assert!(light.bounds().unwrap().contains_point(&(p.s.center + p.velocity)));
+    }
 }
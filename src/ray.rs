@@ -1,11 +1,15 @@
 use crate::na::{Vector3, Point3};
 use crate::na::geometry::{Transform3, Affine3};
+use crate::intersection::Intersection;
 use std::fmt;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Ray {
     pub ro: Vector3<f64>,
     pub rd: Vector3<f64>,
+    /// When the ray was cast, as a fraction (0 - 1) of the way through the motion of moving
+    /// objects. Camera rays are spread over the shutter interval, which blurs anything moving.
+    pub time: f64,
 }
 
 // Expanded Ray definition.
@@ -18,11 +22,69 @@ pub struct RayX {
     pub depth: i64,
 }
 
+/// Rays offset a step across (rx) and up (ry) the image from a camera ray, which spread out as
+/// the ray travels, telling us how much of the scene it stands for.
+///
+/// ## References
+/// 1. PBRT - 10.1 Sampling and Antialiasing
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct RayDifferential {
     pub rx: Ray,
     pub ry: Ray,
 }
 
+impl RayDifferential {
+    /// The differentials of a ray scattered from an intersection. `direction` gives the
+    /// neighbouring scattered direction from the neighbouring incoming direction and how
+    /// the normal turns between them.
+    fn scattered(
+            &self,
+            i: &Intersection,
+            direction: impl Fn(&Vector3<f64>, &Vector3<f64>) -> Vector3<f64>,
+        ) -> RayDifferential {
+        let f = &i.footprint;
+        let dndx = i.dndu * f.duvdx.x + i.dndv * f.duvdx.y;
+        let dndy = i.dndu * f.duvdy.x + i.dndv * f.duvdy.y;
+        RayDifferential {
+            rx: Ray { ro: i.point + f.dpdx, rd: direction(&self.rx.rd.normalize(), &dndx), time: i.time },
+            ry: Ray { ro: i.point + f.dpdy, rd: direction(&self.ry.rd.normalize(), &dndy), time: i.time },
+        }
+    }
+
+    /// Differentials of `wi`, the mirror reflection of ray r at an intersection
+    ///
+    /// ## References
+    /// 1. PBRT - 10.1.3 Ray Differentials for Specular Reflection and Transmission
+    pub fn reflect(&self, r: &Ray, i: &Intersection, wi: &Vector3<f64>) -> RayDifferential {
+        let n = i.normal;
+        let wo = -r.rd.normalize();
+        let wi = wi.normalize();
+        self.scattered(i, |neighbour, dndp| {
+            let dwo = -neighbour - wo;
+            let ddn = dwo.dot(&n) + wo.dot(dndp);
+            wi - dwo + (dndp * wo.dot(&n) + n * ddn) * 2.
+        })
+    }
+
+    /// Differentials of `wi`, ray r refracted at an intersection, where `eta` is the ratio of
+    /// refractive indices across the surface (incoming over transmitted)
+    pub fn refract(&self, r: &Ray, i: &Intersection, wi: &Vector3<f64>, eta: f64) -> RayDifferential {
+        let wo = -r.rd.normalize();
+        let wi = wi.normalize();
+        // Work with the normal on the incoming side
+        let sign = if wo.dot(&i.normal) < 0. { -1. } else { 1. };
+        let n = i.normal * sign;
+        self.scattered(i, |neighbour, dndp| {
+            let dndp = dndp * sign;
+            let dwo = -neighbour - wo;
+            let ddn = dwo.dot(&n) + wo.dot(&dndp);
+            let mu = eta * wo.dot(&n) - wi.dot(&n).abs();
+            let dmu = (eta - eta * eta * wo.dot(&n) / wi.dot(&n).abs()) * ddn;
+            wi - dwo * eta + dndp * mu + n * dmu
+        })
+    }
+}
+
 impl fmt::Display for Ray {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "(Ray {}->{})", self.ro, self.rd)
@@ -30,17 +92,24 @@ impl fmt::Display for Ray {
 }
 
 impl Ray {
+    /// A ray at the start of the scene's motion (time 0)
+    pub fn new(ro: Vector3<f64>, rd: Vector3<f64>) -> Ray {
+        Ray { ro, rd, time: 0. }
+    }
+
     pub fn transform(&self, t: &Transform3<f64>) -> Ray {
         Ray {
             ro: (t * Point3::from(self.ro)).coords,
-            rd: t * self.rd
+            rd: t * self.rd,
+            time: self.time,
         }
     }
 
     pub fn inverse_transform(&self, t: &Affine3<f64>) -> Ray {
         Ray {
             ro: t.inverse_transform_point(&Point3::from(self.ro)).coords,
-            rd: t.inverse_transform_vector(&self.rd)
+            rd: t.inverse_transform_vector(&self.rd),
+            time: self.time,
         }
     }
 }
@@ -2,7 +2,9 @@ use std::time::{Instant};
 
 use crate::scene::Scene;
 use crate::color::Color;
-use crate::trace::trace;
+use crate::trace::trace_differential;
+use crate::na::Vector3;
+use crate::geometry::rand;
 
 
 // The render context is the data structure
@@ -83,6 +85,16 @@ impl RenderContext {
         self.pixels_rendered += 1;
     }
 
+    /// Replace a pixel with a colour, as drawn over the finished render
+    pub fn overlay_pixel(&mut self, x: usize, y: usize, c: Color) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let i = y*self.width + x;
+        self.samples[i] = self.samples[i].max(1);
+        self.image[i] = c * self.samples[i] as f64;
+    }
+
     pub fn apply_chunk(&mut self, c: &RenderableChunk, p: &RenderedChunk){
         let mut i = 0;
         for y in c.ymin .. c.ymax {
@@ -245,20 +257,30 @@ fn render_pixel(x: usize, y: usize, max_samples: usize, s: &Scene) -> (u64, usiz
     let mut cast = 0;
     let mut samples = 0;
 
+    // Each sample stands for its share of the pixel, so textures are filtered to match
+    let share = (1. / max_samples as f64).max(0.125);
+    let step = (share / s.image.width as f64, share / s.image.height as f64);
+
     // Monte-Carlo method: We sample many times and average.
     for sx in 0..max_samples {
         for sy in 0..max_samples {
-            let (rays_cast, c) = trace(
-                    &s.camera.get_ray(
+            let (open, close) = s.render.shutter;
+            let (ray, differential, weight) = s.camera.get_sample_differential(
                         x as f64 / (s.image.width as f64),
                         y as f64 / (s.image.height as f64),
                         sx as f64 / (max_samples as f64) * 1. / (s.image.width as f64),
-                        sy as f64 / (max_samples as f64) * 1. / (s.image.height as f64))
-                    , 0, s);
-            cast += rays_cast;
-            pixel = pixel + c;
+                        sy as f64 / (max_samples as f64) * 1. / (s.image.height as f64),
+                        open + (close - open) * rand(),
+                        step);
             samples += 1;
+            // Cameras return no direction where they see nothing (eg. outside a fisheye circle)
+            if ray.rd == Vector3::zeros() {
+                continue;
+            }
+            let (rays_cast, c) = trace_differential(&ray, Some(&differential), 0, s);
+            cast += rays_cast;
+            pixel = pixel + c * weight;
         }
     }
-    (cast, samples, pixel)
+    (cast, samples, pixel * s.camera.exposure())
 }
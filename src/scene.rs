@@ -1,7 +1,9 @@
+use std::sync::Arc;
 use crate::camera;
 use crate::na::Vector3;
 use crate::scenegraph::SceneGraph;
-use crate::light::Light;
+use crate::lights::tree::LightTree;
+use crate::lights::environment::EnvironmentLight;
 use crate::color::Color;
 use crate::shapes::bbox::BBox;
 use crate::participatingmedia::ParticipatingMedium;
@@ -17,6 +19,8 @@ pub enum PathCulling {
 pub struct ImageOpts {
     pub width: usize,
     pub height: usize,
+    /// Write the left and right halves of a stereo image to separate files
+    pub stereo_files: bool,
 }
 
 #[derive(Debug)]
@@ -27,6 +31,10 @@ pub struct RenderOpts {
     pub supersamples: usize,
     pub chunk_size: usize,
     pub samples_per_chunk: usize,
+    /// Number of lights to sample at each shading point when there are many
+    pub light_samples: usize,
+    /// When the shutter opens and closes, as fractions (0 - 1) of the motion of the scene
+    pub shutter: (f64, f64),
 }
 
 pub struct Scene {
@@ -34,9 +42,19 @@ pub struct Scene {
     pub render: RenderOpts,
     pub camera: Box<dyn camera::Camera + Sync>,
     pub objects: SceneGraph,
-    pub lights: Vec<Light>,
+    pub lights: LightTree,
+    pub environment: Option<Arc<EnvironmentLight>>,
     pub max_bounding: BBox,
     pub black_threshold: f64,
     pub air_medium: Box<dyn ParticipatingMedium>,
 }
 
+impl Scene {
+    /// Light arriving along a ray that doesn't hit anything
+    pub fn background(&self, rd: &Vector3<f64>) -> Color {
+        match &self.environment {
+            Some(env) => env.radiance(rd),
+            None => self.render.background,
+        }
+    }
+}
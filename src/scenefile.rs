@@ -1,28 +1,48 @@
 use crate::camera;
-use crate::na::{Vector3, Vector2};
+use crate::na::{Vector3, Vector2, Point3, Rotation3, Translation3, Affine3, Isometry3, Matrix4, Matrix3x4, Unit};
 use crate::scenegraph::SceneGraph;
 use crate::shapes::sphere::Sphere;
 use crate::shapes::triangle::Triangle;
-use crate::shapes::plane::Plane;
-use crate::shapes::mesh::{Mesh, SmoothMesh};
-use crate::shapes::csg::{Primitive, Difference};
+use crate::shapes::plane::{Plane, Extent};
+use crate::shapes::rectangle::Rectangle;
+use crate::shapes::disk::Disk;
+use crate::shapes::cylinder::Cylinder;
+use crate::shapes::torus::Torus;
+use crate::shapes::mesh::{Mesh, SmoothMesh, uses_mtl};
+use crate::shapes::csg::{Csg, Operation};
+use crate::shapes::sdf::{Sdf, SdfGeometry};
+use crate::shapes::heightfield::Heightfield;
+use crate::shapes::voxels::{VoxelGrid, load_vox};
+use crate::shapes::implicit::{Implicit, Field, Metaball};
+use crate::shapes::subdivision::{PolyMesh, Scheme};
+use crate::noise::{PerlinNoise, WorleyNoise};
 use crate::shapes::transform::{Transform};
+use crate::shapes::instance::Instance;
 use crate::ocean::create_ocean;
 use crate::shapes::bbox::BBox;
-use crate::light::Light;
+use crate::lights::light::Light;
+use crate::lights::point::PointLight;
+use crate::lights::area::{AreaLight, ObjectLight, LightSampling};
+use crate::lights::spot::SpotLight;
+use crate::lights::directional::DirectionalLight;
+use crate::lights::ies::{IesLight, IesProfile};
+use crate::lights::environment::EnvironmentLight;
+use crate::lights::tree::LightTree;
 use crate::color::Color;
-use crate::skysphere::create_sky_sphere;
+use crate::skysphere::{create_sky_sphere, create_sky_sun};
 use crate::procedural::box_terrain::create_box_terrain;
 use crate::procedural::fireworks::create_firework;
 use crate::participatingmedia::create_fog;
 use std::sync::Arc;
+use std::collections::HashMap;
 use crate::sceneobject::SceneObject;
 use serde_json::{Value, Map};
 use crate::scene::{Scene, ImageOpts, RenderOpts};
 use std::io::prelude::*;
 use std::fs::File;
+use std::path::Path;
 use crate::material::model::MaterialModel;
-use crate::material::texture::{Solid, CheckeredYPlane, Medium, NoiseMedium, self};
+use crate::material::texture::{Solid, CheckeredYPlane, Checkered, VoxelPalette, Medium, NoiseMedium, self};
 use crate::material::specular::Specular;
 use crate::material::dielectric::Dielectric;
 use crate::material::plastic::Plastic;
@@ -31,9 +51,14 @@ use crate::material::normal::NormalShade;
 use crate::material::legacy::{ Whitted, FlatColor };
 use crate::material::diffuse_light::DiffuseLight;
 use crate::material::noise::{NoiseTexture, NoiseType};
+use crate::material::image_texture::{ImageTexture, MipMap};
+use crate::material::mtl::from_mtl;
 use crate::participatingmedia::{ParticipatingMedium, HomogenousFog, Vacuum};
 use crate::shapes::geometry::Geometry;
 
+/// Shared geometry by name, see `SceneFile::parse_geometries`
+pub type Geometries = HashMap<String, Arc<dyn Geometry + Sync + Send>>;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SceneFile {
     pub width: Value,
@@ -51,6 +76,9 @@ pub struct SceneFile {
     pub objects: Vec<Value>,
     pub variables: Value,
     pub air: Option<Value>,
+    pub environment: Option<Value>,
+    pub light_samples: Option<Value>,
+    pub geometries: Option<Map<String, Value>>,
 }
 
 impl SceneFile {
@@ -98,6 +126,12 @@ impl SceneFile {
     }
 
     pub fn parse_color(v: &Value) -> Color {
+        if let Some(name) = v.as_str() {
+            return Color::from_name(name).unwrap_or_else(|| {
+                println!("!! [ Warning in scene parse ] Unknown color {}", name);
+                Color::white()
+            });
+        }
         Color::new(v[0].as_f64().unwrap(),
                          v[1].as_f64().unwrap(),
                          v[2].as_f64().unwrap())
@@ -112,23 +146,249 @@ impl SceneFile {
         }
     }
 
-    pub fn parse_camera(c: Value, width: u32, height: u32) -> camera::FlatLensCamera {
-        camera::FlatLensCamera::new(
+    /// Build the camera for the final image size. A camera with a `"motion"` moves from its
+    /// location, lookat and up to those given in the motion over the shutter interval.
+    pub fn parse_camera(c: &Value, width: u32, height: u32, objects: &SceneGraph) -> Box<dyn camera::Camera + Sync> {
+        let cam = match c.get("stereo") {
+            Some(stereo) => SceneFile::parse_stereo(c, stereo, width, height, objects),
+            None => SceneFile::parse_camera_model(c, width, height, objects),
+        };
+        let motion = match c.get("motion") {
+            Some(m) => m,
+            None => return cam,
+        };
+        if c.get("location").is_none() {
+            println!("!! [ Warning in scene parse ] Camera motion needs a location, ignoring it");
+            return cam;
+        }
+        let start = (
             SceneFile::parse_vec3(&c["lookat"]),
             SceneFile::parse_vec3(&c["location"]),
-            SceneFile::parse_vec3(&c["up"]),
-            c["angle"].as_f64().unwrap(),
+            SceneFile::parse_vec3_def(c, "up", Vector3::new(0., 1., 0.)),
+        );
+        let end = (
+            SceneFile::parse_vec3_def(motion, "lookat", start.0),
+            SceneFile::parse_vec3_def(motion, "location", start.1),
+            SceneFile::parse_vec3_def(motion, "up", start.2),
+        );
+        Box::new(camera::MovingCamera::new(cam, start, end))
+    }
+
+    /// A stereo pair of cameras. `"interocular"` defaults to 65mm (given `"meters_per_unit"`)
+    /// and `"convergence"` to the distance to lookat. The eyes are laid out `"side-by-side"`,
+    /// `"top-bottom"` (the default for 360 degree panoramas), or side by side and written to
+    /// `"separate"` files.
+    pub fn parse_stereo(c: &Value, stereo: &Value, width: u32, height: u32, objects: &SceneGraph) -> Box<dyn camera::Camera + Sync> {
+        let t = c.get("type").and_then(|v| v.as_str()).unwrap_or("thin-lens");
+        let omnidirectional = t == "equirectangular" || t == "latlong";
+        let layout = match stereo["layout"].as_str() {
+            Some("side-by-side") | Some("separate") => camera::StereoLayout::SideBySide,
+            Some("top-bottom") => camera::StereoLayout::TopBottom,
+            None if omnidirectional => camera::StereoLayout::TopBottom,
+            None => camera::StereoLayout::SideBySide,
+            Some(l) => {
+                println!("!! [ Warning in scene parse ] Unknown stereo layout {}, using side-by-side", l);
+                camera::StereoLayout::SideBySide
+            }
+        };
+        let eye = match layout {
+            camera::StereoLayout::SideBySide => SceneFile::parse_camera_model(c, width / 2, height, objects),
+            camera::StereoLayout::TopBottom => SceneFile::parse_camera_model(c, width, height / 2, objects),
+        };
+        if c.get("location").is_none() {
+            println!("!! [ Warning in scene parse ] Stereo needs a camera location, rendering one eye");
+            return eye;
+        }
+
+        let lookat = SceneFile::parse_vec3(&c["lookat"]);
+        let location = SceneFile::parse_vec3(&c["location"]);
+        let meters_per_unit = SceneFile::parse_number(&c["meters_per_unit"], 1.);
+        Box::new(camera::StereoCamera::new(
+            eye,
+            lookat, location,
+            SceneFile::parse_vec3_def(c, "up", Vector3::new(0., 1., 0.)),
+            SceneFile::parse_number(&stereo["interocular"], 0.065 / meters_per_unit),
+            SceneFile::parse_number(&stereo["convergence"], (lookat - location).norm()),
+            omnidirectional,
+            layout,
+        ))
+    }
+
+    /// The fraction of the scene's motion (0 - 1) that the shutter is open for
+    pub fn parse_shutter(c: &Value) -> (f64, f64) {
+        let open = SceneFile::parse_number(&c["shutter_open"], 0.).clamp(0., 1.);
+        let close = SceneFile::parse_number(&c["shutter_close"], 1.).clamp(open, 1.);
+        (open, close)
+    }
+
+    /// The camera itself, selected by `"type"`. Without a type we use a thin lens, as older
+    /// scenes expect.
+    fn parse_camera_model(c: &Value, width: u32, height: u32, objects: &SceneGraph) -> Box<dyn camera::Camera + Sync> {
+        let t = c.get("type").and_then(|v| v.as_str()).unwrap_or("thin-lens");
+
+        if t == "isometric" {
+            return Box::new(camera::OrthographicCamera::isometric(
+                SceneFile::parse_vec3_def(c, "lookat", Vector3::new(0., 0., 0.)),
+                SceneFile::parse_number(&c["distance"], 100.),
+                SceneFile::parse_number(&c["size"], 10.),
+                width, height,
+            ));
+        }
+
+        let lookat = SceneFile::parse_vec3(&c["lookat"]);
+        let location = SceneFile::parse_vec3(&c["location"]);
+        let up = SceneFile::parse_vec3_def(c, "up", Vector3::new(0., 1., 0.));
+
+        if t == "orthographic" {
+            return Box::new(camera::OrthographicCamera::new(
+                lookat, location, up,
+                SceneFile::parse_number(&c["size"], 10.),
+                width, height,
+            ));
+        }
+
+        if t == "equirectangular" || t == "latlong" {
+            return Box::new(camera::EquirectangularCamera::new(lookat, location, up));
+        }
+
+        if t == "fisheye" {
+            let projection = match c["projection"].as_str().unwrap_or("equidistant") {
+                "equisolid" => camera::FisheyeProjection::Equisolid,
+                "equidistant" => camera::FisheyeProjection::Equidistant,
+                p => {
+                    println!("!! [ Warning in scene parse ] Unknown fisheye projection {}, using equidistant", p);
+                    camera::FisheyeProjection::Equidistant
+                }
+            };
+            return Box::new(camera::FisheyeCamera::new(
+                lookat, location, up,
+                SceneFile::parse_number(&c["fov"], 180.).to_radians(),
+                projection,
+                width, height,
+            ));
+        }
+
+        if t == "cubemap" {
+            return Box::new(camera::CubemapCamera::new(lookat, location, up));
+        }
+
+        if t == "perspective" || t == "pinhole" {
+            return Box::new(camera::SimpleCamera::new(
+                lookat, location, up,
+                c["angle"].as_f64().unwrap(),
+                width, height,
+            ));
+        }
+
+        if t != "thin-lens" {
+            println!("!! [ Warning in scene parse ] Unknown camera type {}, using thin-lens", t);
+        }
+        if c.get("focal_length").is_none() {
+            return Box::new(SceneFile::parse_lens(c, camera::FlatLensCamera::new(
+                lookat, location, up,
+                c["angle"].as_f64().unwrap(),
+                width, height,
+                SceneFile::parse_number(&c["aperture"], 0.2)
+            )));
+        }
+
+        // Photographic settings. Lengths are in mm, shutter in seconds.
+        let f_number = SceneFile::parse_number(&c["f_number"], 8.);
+        let cam = camera::FlatLensCamera::physical(
+            lookat, location, up,
+            SceneFile::parse_number(&c["focal_length"], 50.),
+            SceneFile::parse_number(&c["sensor_width"], 36.),
+            f_number,
+            SceneFile::parse_number(&c["meters_per_unit"], 1.),
             width, height,
-            SceneFile::parse_number(&c["aperture"], 0.2)
-        )
+        );
+        // Without a shutter speed, expose as the sunny 16 rule would.
+        let exposure = match c.get("shutter") {
+            Some(shutter) => camera::photographic_exposure(
+                SceneFile::parse_number(&c["iso"], 100.),
+                SceneFile::parse_number(shutter, 0.01),
+                f_number),
+            None => 1.,
+        };
+        let cam = cam.with_exposure(exposure);
+
+        let focus = match &c["focus"] {
+            Value::String(f) if f == "auto" => {
+                // Focus on whatever is at the centre of the image
+                objects.nearest_intersection(&cam.centre_ray(0.5, 0.5), f64::INFINITY, 0.)
+                    .map(|i| i.dist)
+                    .unwrap_or((lookat - location).norm())
+            },
+            f => SceneFile::parse_number(f, (lookat - location).norm()),
+        };
+        Box::new(SceneFile::parse_lens(c, cam.with_focus(focus)))
+    }
+
+    /// Lens imperfections: the shape of the aperture (`"blades"` and `"blade_rotation"` in
+    /// degrees, or an `"aperture_mask"` image), `"cats_eye"` vignetting, radial `"distortion"`
+    /// (k1 or [k1, k2]) and lateral `"chromatic_aberration"`.
+    pub fn parse_lens(c: &Value, cam: camera::FlatLensCamera) -> camera::FlatLensCamera {
+        let shape = match (c.get("aperture_mask"), c["blades"].as_u64()) {
+            (Some(src), _) => camera::Aperture::from_image(&SceneFile::parse_string(src)),
+            (None, Some(blades)) if blades >= 3 => camera::Aperture::Polygon {
+                blades: blades as usize,
+                rotation: SceneFile::parse_number(&c["blade_rotation"], 0.).to_radians(),
+            },
+            (None, Some(blades)) => {
+                println!("!! [ Warning in scene parse ] An aperture needs at least 3 blades, got {}", blades);
+                camera::Aperture::Circle
+            },
+            (None, None) => camera::Aperture::Circle,
+        };
+        let distortion = match &c["distortion"] {
+            Value::Array(k) => (
+                k.first().map(|x| SceneFile::parse_number(x, 0.)).unwrap_or(0.),
+                k.get(1).map(|x| SceneFile::parse_number(x, 0.)).unwrap_or(0.)),
+            k => (SceneFile::parse_number(k, 0.), 0.),
+        };
+        cam.with_aperture_shape(shape)
+            .with_distortion(distortion.0, distortion.1)
+            .with_chromatic_aberration(SceneFile::parse_number(&c["chromatic_aberration"], 0.))
+            .with_cats_eye(SceneFile::parse_number(&c["cats_eye"], 0.).clamp(0., 1.))
     }
 
-    pub fn parse_objects(objs: Vec<Value>, materials: &Map<String, Value>, media: &Map<String, Value>) ->Vec<Arc<SceneObject>> {
+    /// Returns the scene objects, and the lights for any emissive objects marked with
+    /// `"area_light": true`.
+    pub fn parse_objects(objs: Vec<Value>, materials: &Map<String, Value>, media: &Map<String, Value>, geometries: &Geometries) -> (Vec<Arc<SceneObject>>, Vec<Box<dyn Light>>) {
          let mut objects: Vec<Arc<SceneObject>> = Vec::new();
+         let mut lights: Vec<Box<dyn Light>> = Vec::new();
          for obj in objs {
-            if let Some(x) = SceneFile::parse_object(obj, materials, media) { objects.push(x) }
+            let t = obj["type"].as_str().unwrap().to_string();
+            let first = objects.len();
+            let area_light = obj["area_light"].as_bool().unwrap_or(false);
+            let sampling = SceneFile::parse_light_sampling(&obj);
+            if t == "firework" {
+                let (firework, mut particles) = create_firework(&obj);
+                objects.push(Arc::new(firework));
+                lights.append(&mut particles);
+                continue;
+            }
+            if t == "skysphere" && obj["sun_light"].as_bool().unwrap_or(true)
+                && let Some(sun) = create_sky_sun(&obj) {
+                lights.push(Box::new(sun));
+            }
+            if t == "mesh" || t == "smoothmesh" {
+                objects.append(&mut SceneFile::parse_mesh_objects(&obj, materials, media, geometries));
+            } else if let Some(x) = SceneFile::parse_object(obj, materials, media, geometries) {
+                objects.push(x)
+            }
+
+            if area_light {
+                for o in &objects[first..] {
+                    if o.area() > 0. {
+                        lights.push(Box::new(ObjectLight { object: o.clone(), sampling }));
+                    } else {
+                        println!("!! [ Warning in scene parse ] {} can't be sampled as an area light", t);
+                    }
+                }
+            }
          }
-         objects
+         (objects, lights)
     }
 
     pub fn parse_object_medium(o: &Value, materials: &Map<String, Value>, media: &Map<String, Value> ) -> Box<dyn Medium + Sync + Send> {
@@ -143,11 +403,10 @@ impl SceneFile {
                     let material_name = SceneFile::parse_string(material_key);
                     
                     // Check if this is a direct noise material definition
-                    if let Some(material_def) = materials.get(&material_name) {
-                        if let Some("noise") = material_def.get("type").and_then(|v| v.as_str()) {
-                            // This is a noise material, we need to parse it as a medium
-                            return SceneFile::parse_medium(material_def, materials).unwrap();
-                        }
+                    if let Some(material_def) = materials.get(&material_name)
+                        && let Some("noise") = material_def.get("type").and_then(|v| v.as_str()) {
+                        // This is a noise material, we need to parse it as a medium
+                        return SceneFile::parse_medium(material_def, materials).unwrap();
                     }
                     
                     // Default case - just use the referenced material
@@ -162,7 +421,62 @@ impl SceneFile {
         }
     }
 
-    pub fn parse_object(o: Value,  materials: &Map<String, Value>, media: &Map<String, Value>) -> Option<Arc<SceneObject>> {
+    /// Meshes become one scene object per MTL material in the obj. Any MTL material can be
+    /// replaced with a scene material using `"mtl_overrides": {"<mtl name>": "<material>"}`,
+    /// and faces without an MTL material use the object's own material / medium.
+    /// Set `"use_mtl": false` to ignore the .mtl entirely. Subdivided meshes are one surface,
+    /// so always use the object's material, with a warning if that drops MTL materials.
+    pub fn parse_mesh_objects(o: &Value, materials: &Map<String, Value>, media: &Map<String, Value>, geometries: &Geometries) -> Vec<Arc<SceneObject>> {
+        let use_mtl = o["use_mtl"].as_bool().unwrap_or(true);
+        if use_mtl && o.get("subdivide").is_some() && uses_mtl(&SceneFile::parse_string(&o["src"])) {
+            println!("!! [ Warning in scene parse ] Subdivided meshes ignore their .mtl, using the object's material for {}",
+                SceneFile::parse_string(&o["src"]));
+        }
+        if !use_mtl || o.get("subdivide").is_some() {
+            let geometry = SceneFile::parse_geometry(o, geometries).unwrap();
+            let medium = SceneFile::parse_object_medium(o, materials, media);
+            return vec![Arc::new(SceneObject { geometry, medium })];
+        }
+
+        let src = SceneFile::parse_string(&o["src"]);
+        let scale = SceneFile::parse_vec3_def(o, "scale", Vector3::new(1., 1., 1.));
+        let groups: Vec<(Option<tobj::Material>, Box<dyn Geometry + Sync + Send>)> =
+            if o["type"].as_str() == Some("smoothmesh") {
+                SmoothMesh::from_obj_groups(src.clone(), scale)
+                    .into_iter()
+                    .map(|(m, g)| (m, Box::new(g) as Box<dyn Geometry + Sync + Send>))
+                    .collect()
+            } else {
+                Mesh::from_obj_groups(src.clone(), scale)
+                    .into_iter()
+                    .map(|(m, g)| (m, Box::new(g) as Box<dyn Geometry + Sync + Send>))
+                    .collect()
+            };
+        let base_dir = Path::new(&src).parent().unwrap_or(Path::new("")).to_path_buf();
+        // The groups move together, about the middle of the whole mesh
+        let middle = groups.iter()
+            .map(|(_, g)| g.bounds())
+            .reduce(|a, b| a.union(&b))
+            .map(|b| b.mid())
+            .unwrap_or(Vector3::new(0., 0., 0.));
+
+        groups.into_iter().map(|(mtl, geometry)| {
+            let geometry = match o.get("motion") {
+                Some(m) => SceneFile::parse_motion(m, middle, geometry),
+                None => geometry,
+            };
+            let medium: Box<dyn Medium + Sync + Send> = match mtl {
+                Some(mtl) => match o.get("mtl_overrides").and_then(|x| x.get(&mtl.name)) {
+                    Some(key) => Box::new(Solid { m: SceneFile::parse_material_ref(key, materials).unwrap() }),
+                    None => Box::new(Solid { m: from_mtl(&mtl, &base_dir) }),
+                },
+                None => SceneFile::parse_object_medium(o, materials, media),
+            };
+            Arc::new(SceneObject { geometry, medium })
+        }).collect()
+    }
+
+    pub fn parse_object(o: Value,  materials: &Map<String, Value>, media: &Map<String, Value>, geometries: &Geometries) -> Option<Arc<SceneObject>> {
         let t = o["type"].as_str().unwrap();
 
         if t == "skysphere" {
@@ -176,17 +490,17 @@ impl SceneFile {
             let d = create_ocean(&o);
             return Some(Arc::new(d));
         }
-        if t == "firework" {
-            let d = create_firework(&o);
-            return Some(Arc::new(d));
-        }
 
         if t == "fog" {
             let f = create_fog(&o);
             return Some(Arc::new(f));
         }
 
-        let geom = SceneFile::parse_geometry(&o);
+        if t == "voxels" {
+            return Some(Arc::new(SceneFile::parse_voxels(&o, materials)));
+        }
+
+        let geom = SceneFile::parse_geometry(&o, geometries);
         let m = SceneFile::parse_object_medium(&o, materials, media);
         if let Some(geometry) = geom {
             return Some(Arc::new(SceneObject {
@@ -209,7 +523,31 @@ impl SceneFile {
         create_box_terrain()
     }
 
-    pub fn parse_geometry (o: &Value) -> Option<Box<dyn Geometry + Sync + Send>> {
+    pub fn parse_geometry (o: &Value, geometries: &Geometries) -> Option<Box<dyn Geometry + Sync + Send>> {
+        let g = SceneFile::parse_shape(o, geometries)?;
+        match o.get("motion") {
+            Some(m) => {
+                let center = g.bounds().mid();
+                Some(SceneFile::parse_motion(m, center, g))
+            },
+            None => Some(g),
+        }
+    }
+
+    /// Moving geometry. The motion is how far the object moves between time 0 and 1:
+    /// `"translate"`, and `"rotate"` ([roll, pitch, yaw] in degrees) about `"center"`, which
+    /// defaults to the middle of the object.
+    pub fn parse_motion(m: &Value, middle: Vector3<f64>, g: Box<dyn Geometry + Sync + Send>) -> Box<dyn Geometry + Sync + Send> {
+        let translate = SceneFile::parse_vec3_def(m, "translate", Vector3::new(0., 0., 0.));
+        let rotate = SceneFile::parse_vec3_def(m, "rotate", Vector3::new(0., 0., 0.));
+        let center = SceneFile::parse_vec3_def(m, "center", middle);
+        let rotation = Rotation3::from_euler_angles(
+            rotate.x.to_radians(), rotate.y.to_radians(), rotate.z.to_radians());
+        let end = Translation3::from(translate + center) * rotation * Translation3::from(-center);
+        Box::new(Transform::moving(g, Affine3::identity(), na::convert(end)))
+    }
+
+    fn parse_shape(o: &Value, geometries: &Geometries) -> Option<Box<dyn Geometry + Sync + Send>> {
         let t = o["type"].as_str().unwrap();
 
         if t == "sphere" {
@@ -236,27 +574,385 @@ impl SceneFile {
             return Some(SceneFile::parse_plane(o));
         }
 
+        if t == "cylinder" || t == "cone" {
+            return Some(SceneFile::parse_cylinder(o));
+        }
+
+        if t == "torus" {
+            return Some(SceneFile::parse_torus(o));
+        }
+
+        if t == "disk" {
+            return Some(Box::new(Disk::new(
+                SceneFile::parse_vec3(&o["location"]),
+                SceneFile::parse_vec3_def(o, "normal", Vector3::new(0., 1., 0.)),
+                o["radius"].as_f64().unwrap())));
+        }
+
+        if t == "quad" || t == "rectangle" {
+            return Some(Box::new(Rectangle::new(
+                SceneFile::parse_vec3(&o["corner"]),
+                SceneFile::parse_vec3(&o["u"]),
+                SceneFile::parse_vec3(&o["v"]))));
+        }
+
         if t == "rotate" {
-            return Some(SceneFile::parse_rotation(o));
+            return Some(SceneFile::parse_rotation(o, geometries));
+        }
+
+        if t == "transform" {
+            let item = SceneFile::parse_geometry(&o["item"], geometries).unwrap(); // Panic if fails
+            return Some(Box::new(Transform::affine(item, SceneFile::parse_transform(&o["transform"]))));
+        }
+
+        if t == "instance" {
+            return Some(SceneFile::parse_instance(o, geometries));
+        }
+
+        if t == "heightfield" {
+            return Some(SceneFile::parse_heightfield(o));
+        }
+
+        if t == "sdf" {
+            return Some(SceneFile::parse_sdf_geometry(o));
+        }
+
+        if t == "metaballs" || t == "implicit" {
+            return Some(SceneFile::parse_implicit(o));
+        }
+
+        if t == "union" {
+            return Some(SceneFile::parse_csg(o, Operation::Union, geometries));
+        }
+
+        if t == "intersection" {
+            return Some(SceneFile::parse_csg(o, Operation::Intersection, geometries));
         }
 
         if t == "difference" {
-            return Some(SceneFile::parse_difference(o));
+            return Some(SceneFile::parse_csg(o, Operation::Difference, geometries));
         }
         None
     }
 
-    pub fn parse_difference(o: &Value) -> Box<dyn Geometry + Sync + Send> {
-        let a = SceneFile::parse_geometry(&o["a"]).unwrap(); // Panic if fails
-        let b = SceneFile::parse_geometry(&o["b"]).unwrap();
-        Box::new(Difference { 
-            a: Primitive { item: a },
-            b: Primitive { item: b }, 
-        })
+    /// A boolean operation between solids, either `a` and `b`, or a list of `items`. A
+    /// difference takes all the other items away from the first.
+    pub fn parse_csg(o: &Value, op: Operation, geometries: &Geometries) -> Box<dyn Geometry + Sync + Send> {
+        let parse = |g: &Value| match SceneFile::parse_geometry(g, geometries) {
+            Some(g) => g,
+            None => panic!("Unknown geometry in CSG: {}", g),
+        };
+        let mut items: Vec<Box<dyn Geometry + Sync + Send>> = match o["items"].as_array() {
+            Some(items) => items.iter().map(parse).collect(),
+            None => vec![parse(&o["a"]), parse(&o["b"])],
+        };
+        if items.len() < 2 {
+            panic!("CSG needs at least two items: {}", o);
+        }
+        if op == Operation::Difference {
+            let a = items.remove(0);
+            return Box::new(Csg::new(a, SceneFile::balanced_csg(items, Operation::Union), op));
+        }
+        SceneFile::balanced_csg(items, op)
+    }
+
+    /// Combine items pairwise, so long lists don't nest too deeply
+    fn balanced_csg(mut items: Vec<Box<dyn Geometry + Sync + Send>>, op: Operation) -> Box<dyn Geometry + Sync + Send> {
+        if items.len() == 1 {
+            return items.pop().unwrap();
+        }
+        let rest = items.split_off(items.len() / 2);
+        Box::new(Csg::new(SceneFile::balanced_csg(items, op), SceneFile::balanced_csg(rest, op), op))
     }
 
-    pub fn parse_rotation(o: &Value) -> Box<dyn Geometry + Sync + Send> {
-        let a = SceneFile::parse_geometry(&o["item"]).unwrap(); // Panic if fails
+    /// Voxels from a MagicaVoxel file (`src`), or columns of `terrain` from fBm noise, with
+    /// the base centred on `location`. Each palette index is its colour, unless given a
+    /// material in `palette`, or all given `material`.
+    pub fn parse_voxels(o: &Value, materials: &Map<String, Value>) -> SceneObject {
+        let voxel_size = SceneFile::parse_number(&o["voxel_size"], 1.);
+        let location = SceneFile::parse_vec3_def(o, "location", Vector3::zeros());
+        let (grid, colors) = if let Some(src) = o["src"].as_str() {
+            load_vox(src, Vector3::zeros(), voxel_size)
+        } else if let Some(t) = o.get("terrain") {
+            SceneFile::voxel_terrain(t, voxel_size)
+        } else {
+            panic!("Voxels need a vox src or terrain: {}", o);
+        };
+        // Now the size is known, centre it
+        let size = grid.bounds().size();
+        let grid = Arc::new(grid.moved_to(location - Vector3::new(size.x / 2., 0., size.z / 2.)));
+
+        let palette = o.get("palette").and_then(|p| p.as_object());
+        let materials = (0..256).map(|i| {
+            let named = palette.and_then(|p| p.get(&i.to_string())).or(o.get("material"));
+            match named {
+                Some(m) => SceneFile::parse_material_ref(m, materials).unwrap(),
+                None => Box::new(Lambertian { albedo: colors[i] }),
+            }
+        }).collect();
+        SceneObject {
+            geometry: Box::new(Instance { geometry: grid.clone() }),
+            medium: Box::new(VoxelPalette { grid, materials }),
+        }
+    }
+
+    /// Columns of voxels as high as fBm noise over `size` ([x, z] voxels), up to `height`.
+    /// The top of each column is palette index 1 (green), and below it 2 (brown).
+    fn voxel_terrain(t: &Value, voxel_size: f64) -> (VoxelGrid, Vec<Color>) {
+        let (nx, nz) = match &t["size"] {
+            Value::Array(a) if a.len() == 2 => (a[0].as_u64().unwrap() as usize, a[1].as_u64().unwrap() as usize),
+            v => { let s = SceneFile::parse_number(v, 64.) as usize; (s, s) },
+        };
+        let height = SceneFile::parse_number(&t["height"], 16.) as usize;
+        let noise = PerlinNoise::with_seed(SceneFile::parse_number(&t["seed"], 0.) as u64);
+        let frequency = SceneFile::parse_number(&t["frequency"], 4.);
+        let octaves = SceneFile::parse_number(&t["octaves"], 4.) as u32;
+
+        let mut grid = VoxelGrid::new([nx, height, nz], Vector3::zeros(), voxel_size);
+        for x in 0..nx {
+            for z in 0..nz {
+                let n = noise.fbm(x as f64 / nx as f64 * frequency, z as f64 / nx as f64 * frequency, 0., octaves, 0.5, 2.);
+                let top = ((n * height as f64) as usize).clamp(1, height);
+                for y in 0..top {
+                    grid.set(x, y, z, if y == top - 1 { 1 } else { 2 });
+                }
+            }
+        }
+        let mut colors = vec![Color::white(); 256];
+        colors[1] = Color::new(0.3, 0.6, 0.2);
+        colors[2] = Color::new(0.45, 0.3, 0.2);
+        (grid, colors)
+    }
+
+    /// Terrain centred on `location`, `size` across (a number, or [x, z]) and `height` tall,
+    /// from the brightness of an image (`src`), or from fBm `noise` sampled `resolution` times
+    /// along x.
+    pub fn parse_heightfield(o: &Value) -> Box<dyn Geometry + Sync + Send> {
+        let (width, depth) = match &o["size"] {
+            Value::Array(a) if a.len() == 2 => (a[0].as_f64().unwrap(), a[1].as_f64().unwrap()),
+            v => { let s = SceneFile::parse_number(v, 10.); (s, s) },
+        };
+        let size = Vector3::new(width, SceneFile::parse_number(&o["height"], 1.), depth);
+        let corner = SceneFile::parse_vec3_def(o, "location", Vector3::zeros()) - Vector3::new(width / 2., 0., depth / 2.);
+
+        if let Some(src) = o["src"].as_str() {
+            return Box::new(Heightfield::from_image(src, corner, size));
+        }
+        let n = &o["noise"];
+        if n.is_null() {
+            panic!("Heightfield needs an image src or noise: {}", o);
+        }
+        let noise = PerlinNoise::with_seed(SceneFile::parse_number(&n["seed"], 0.) as u64);
+        let frequency = SceneFile::parse_number(&n["frequency"], 4.);
+        let octaves = SceneFile::parse_number(&n["octaves"], 6.) as u32;
+        let persistence = SceneFile::parse_number(&n["persistence"], 0.5);
+        let lacunarity = SceneFile::parse_number(&n["lacunarity"], 2.);
+        // Square cells, `resolution` samples along x
+        let nx = (SceneFile::parse_number(&o["resolution"], 256.) as usize).max(2);
+        let nz = ((nx as f64 * depth / width).round() as usize).max(2);
+        let aspect = depth / width;
+        Box::new(Heightfield::from_fn(nx, nz, corner, size, |u, v| {
+            noise.fbm(u * frequency, v * frequency * aspect, 0., octaves, persistence, lacunarity)
+        }))
+    }
+
+    /// A distance function expression, sphere traced within `bounds` (`min` and `max`).
+    /// `epsilon` is how close counts as on the surface, and `max_steps` how far to trace.
+    pub fn parse_sdf_geometry(o: &Value) -> Box<dyn Geometry + Sync + Send> {
+        let bounds = match o.get("bounds") {
+            Some(b) => BBox::new(SceneFile::parse_vec3(&b["min"]), SceneFile::parse_vec3(&b["max"])),
+            None => panic!("SDF geometry needs bounds: {}", o),
+        };
+        Box::new(SdfGeometry::new(
+            SceneFile::parse_sdf(&o["sdf"]),
+            bounds,
+            SceneFile::parse_number(&o["epsilon"], 0.0001),
+            SceneFile::parse_number(&o["max_steps"], 256.) as usize))
+    }
+
+    /// The isosurface of a field at `threshold` (default 0.5). Metaballs take their `balls`
+    /// directly; an implicit surface takes a `field`, either of `metaballs` or the noise
+    /// `density`, which needs `bounds`. `step` is the shortest step along rays, and `epsilon`
+    /// how closely the surface is found.
+    pub fn parse_implicit(o: &Value) -> Box<dyn Geometry + Sync + Send> {
+        let f = if o["type"] == "metaballs" { o } else { &o["field"] };
+        let field = match f["type"].as_str() {
+            Some("metaballs") => {
+                let balls: Vec<Metaball> = f["balls"].as_array()
+                    .unwrap_or_else(|| panic!("Metaballs need balls: {}", f))
+                    .iter()
+                    .map(|b| Metaball {
+                        center: SceneFile::parse_vec3(&b["location"]),
+                        radius: SceneFile::parse_number(&b["radius"], 1.),
+                        strength: SceneFile::parse_number(&b["strength"], 1.),
+                    })
+                    .collect();
+                if balls.is_empty() {
+                    panic!("Metaballs need at least one ball: {}", f);
+                }
+                Field::Metaballs(balls)
+            },
+            Some("density") => Field::Density {
+                perlin: Box::new(PerlinNoise::with_seed(SceneFile::parse_number(&f["seed"], 0.) as u64)),
+                worley: WorleyNoise::new(
+                    SceneFile::parse_number(&f["point_density"], 1.),
+                    SceneFile::parse_number(&f["seed"], 42.) as u32),
+                scale: SceneFile::parse_number(&f["scale"], 1.),
+                falloff: SceneFile::parse_number(&f["falloff"], 0.1),
+            },
+            _ => panic!("Unknown implicit field: {}", f),
+        };
+        let bounds = match (o.get("bounds"), field.bounds()) {
+            (Some(b), _) => BBox::new(SceneFile::parse_vec3(&b["min"]), SceneFile::parse_vec3(&b["max"])),
+            (None, Some(b)) => b,
+            (None, None) => panic!("Implicit surface needs bounds: {}", o),
+        };
+        let default_step = field.feature_size().unwrap_or((bounds.max - bounds.min).norm() / 20.) / 10.;
+        Box::new(Implicit::new(
+            field,
+            SceneFile::parse_number(&o["threshold"], 0.5),
+            bounds,
+            SceneFile::parse_number(&o["step"], default_step),
+            SceneFile::parse_number(&o["epsilon"], 0.000001)))
+    }
+
+    pub fn parse_sdf(o: &Value) -> Sdf {
+        let t = SceneFile::parse_string(&o["type"]);
+        let item = || Box::new(SceneFile::parse_sdf(&o["item"]));
+        match t.as_str() {
+            "sphere" => Sdf::Sphere { radius: SceneFile::parse_number(&o["radius"], 1.) },
+            "box" => Sdf::Box { half_size: SceneFile::parse_vec3_def(o, "size", Vector3::new(1., 1., 1.)) / 2. },
+            "torus" => Sdf::Torus {
+                radius: SceneFile::parse_number(&o["radius"], 1.),
+                tube_radius: SceneFile::parse_number(&o["tube_radius"], 0.25),
+            },
+            "capsule" => Sdf::Capsule {
+                a: SceneFile::parse_vec3(&o["a"]),
+                b: SceneFile::parse_vec3(&o["b"]),
+                radius: SceneFile::parse_number(&o["radius"], 1.),
+            },
+            "union" | "intersection" | "difference" => {
+                let smoothness = SceneFile::parse_number(&o["smoothness"], 0.);
+                let items = match o["items"].as_array() {
+                    Some(items) if items.len() >= 2 => items.iter().map(SceneFile::parse_sdf),
+                    _ => panic!("SDF {} needs at least two items: {}", t, o),
+                };
+                // Folded in order, so a difference takes the rest away from the first
+                items.reduce(|a, b| {
+                    let (a, b) = (Box::new(a), Box::new(b));
+                    match t.as_str() {
+                        "union" => Sdf::Union { a, b, smoothness },
+                        "intersection" => Sdf::Intersection { a, b, smoothness },
+                        _ => Sdf::Difference { a, b, smoothness },
+                    }
+                }).unwrap()
+            },
+            "translate" => Sdf::Translate { offset: SceneFile::parse_vec3(&o["offset"]), item: item() },
+            "scale" => Sdf::Scale { factor: SceneFile::parse_number(&o["factor"], 1.), item: item() },
+            "repeat" => Sdf::Repeat { period: SceneFile::parse_vec3(&o["period"]), item: item() },
+            "twist" => Sdf::Twist { rate: SceneFile::parse_number(&o["rate"], 1.), item: item() },
+            "mandelbulb" => Sdf::Mandelbulb {
+                power: SceneFile::parse_number(&o["power"], 8.),
+                iterations: SceneFile::parse_number(&o["iterations"], 10.) as usize,
+            },
+            "menger" => Sdf::Menger { iterations: SceneFile::parse_number(&o["iterations"], 4.) as usize },
+            _ => panic!("Unknown SDF type: {}", t),
+        }
+    }
+
+    /// Named geometry, defined once in the scene file's `"geometries"` and placed any number
+    /// of times by instances. Definitions can't themselves contain instances.
+    pub fn parse_geometries(defs: &Option<Map<String, Value>>) -> Geometries {
+        let mut geometries = Geometries::new();
+        for (name, def) in defs.iter().flatten() {
+            let g = match SceneFile::parse_geometry(def, &Geometries::new()) {
+                Some(g) => g,
+                None => panic!("Geometry '{}' has an unknown type", name),
+            };
+            geometries.insert(name.clone(), Arc::from(g));
+        }
+        geometries
+    }
+
+    /// A placement of shared geometry, `{"type": "instance", "geometry": "<name>"}`, with an
+    /// optional `"transform"` (see parse_transform). Instances share the triangles and
+    /// acceleration structure of the geometry, so they're cheap enough to use by the thousand.
+    pub fn parse_instance(o: &Value, geometries: &Geometries) -> Box<dyn Geometry + Sync + Send> {
+        let name = SceneFile::parse_string(&o["geometry"]);
+        let geometry = match geometries.get(&name) {
+            Some(g) => g.clone(),
+            None => panic!("Geometry '{}' not found in geometries map", name),
+        };
+        let instance = Box::new(Instance { geometry });
+        match o.get("transform") {
+            Some(t) => Box::new(Transform::affine(instance, SceneFile::parse_transform(t))),
+            None => instance,
+        }
+    }
+
+    /// An affine transform, as a list of steps applied in order (or a single step):
+    /// - `{"translate": [x, y, z]}`
+    /// - `{"scale": [x, y, z]}` or `{"scale": s}`
+    /// - `{"rotate": [roll, pitch, yaw]}` in degrees
+    /// - `{"axis": [x, y, z], "angle": a}`, a rotation of `a` degrees about the axis
+    /// - `{"look_at": [x, y, z], "from": [x, y, z], "up": [x, y, z]}`, which turns +z to face
+    ///   `look_at` and moves the origin to `from` (default [0, 0, 0], up defaults to +y)
+    /// - `{"matrix": [...]}`, 12 or 16 numbers, a 3x4 or 4x4 matrix by rows
+    pub fn parse_transform(o: &Value) -> Affine3<f64> {
+        if let Some(steps) = o.as_array() {
+            return steps.iter().fold(Affine3::identity(), |t, step| SceneFile::parse_transform(step) * t);
+        }
+        if let Some(v) = o.get("translate") {
+            return na::convert(Translation3::from(SceneFile::parse_vec3(v)));
+        }
+        if let Some(v) = o.get("scale") {
+            let s = match v.as_f64() {
+                Some(x) => Vector3::new(x, x, x),
+                None => SceneFile::parse_vec3(v),
+            };
+            if s.x * s.y * s.z == 0. {
+                panic!("Transform scale {} is flat, and can't be inverted", s);
+            }
+            return Affine3::from_matrix_unchecked(Matrix4::new_nonuniform_scaling(&s));
+        }
+        if let Some(v) = o.get("rotate") {
+            let r = SceneFile::parse_vec3(v);
+            return na::convert(Rotation3::from_euler_angles(r.x.to_radians(), r.y.to_radians(), r.z.to_radians()));
+        }
+        if let Some(v) = o.get("axis") {
+            let axis = Unit::new_normalize(SceneFile::parse_vec3(v));
+            let angle = SceneFile::parse_number(&o["angle"], 0.).to_radians();
+            return na::convert(Rotation3::from_axis_angle(&axis, angle));
+        }
+        if let Some(v) = o.get("look_at") {
+            let from = SceneFile::parse_vec3_def(o, "from", Vector3::new(0., 0., 0.));
+            let up = SceneFile::parse_vec3_def(o, "up", Vector3::new(0., 1., 0.));
+            return na::convert(Isometry3::face_towards(
+                &Point3::from(from), &Point3::from(SceneFile::parse_vec3(v)), &up));
+        }
+        if let Some(v) = o.get("matrix") {
+            let values: Vec<f64> = v.as_array()
+                .map(|a| a.iter().filter_map(|x| x.as_f64()).collect())
+                .unwrap_or_default();
+            let mut m = Matrix4::identity();
+            match values.len() {
+                12 => m.fixed_view_mut::<3, 4>(0, 0).copy_from(&Matrix3x4::from_row_slice(&values)),
+                16 => m.copy_from(&Matrix4::from_row_slice(&values)),
+                n => panic!("Transform matrix needs 12 or 16 numbers, not {}", n),
+            }
+            if m.fixed_view::<1, 4>(3, 0) != Matrix4::identity().fixed_view::<1, 4>(3, 0) {
+                panic!("Transform matrix {} isn't affine, the last row must be [0, 0, 0, 1]", m);
+            }
+            if m.determinant() == 0. {
+                panic!("Transform matrix {} can't be inverted", m);
+            }
+            return Affine3::from_matrix_unchecked(m);
+        }
+        panic!("Unknown transform {}", o);
+    }
+
+    pub fn parse_rotation(o: &Value, geometries: &Geometries) -> Box<dyn Geometry + Sync + Send> {
+        let a = SceneFile::parse_geometry(&o["item"], geometries).unwrap(); // Panic if fails
         let roll = SceneFile::parse_number(&o["roll"], 0.).to_radians();
         let pitch = SceneFile::parse_number(&o["pitch"], 0.).to_radians();
         let yaw = SceneFile::parse_number(&o["yaw"], 0.).to_radians();
@@ -264,17 +960,91 @@ impl SceneFile {
     }
 
     pub fn parse_mesh(o: &Value) -> Box<dyn Geometry + Sync + Send> {
+        if let Some(poly) = SceneFile::parse_subdivided(o) {
+            return Box::new(poly.to_mesh());
+        }
         Box::new(Mesh::from_obj(
                 SceneFile::parse_string(&o["src"]),
                 SceneFile::parse_vec3_def(o, "scale", Vector3::new(1., 1., 1.))))
     }
 
     pub fn parse_smoothmesh(o: &Value) -> Box<dyn Geometry + Sync + Send> {
+        if let Some(poly) = SceneFile::parse_subdivided(o) {
+            return Box::new(poly.to_smooth_mesh());
+        }
         Box::new(SmoothMesh::from_obj(
                 SceneFile::parse_string(&o["src"]),
                 SceneFile::parse_vec3_def(o, "scale", Vector3::new(1., 1., 1.))))
     }
 
+    /// A mesh subdivided `subdivide` times, if asked, by the `scheme` "loop" or
+    /// "catmull-clark" (Loop if it's all triangles). Edges between faces turning more than
+    /// `crease_angle` degrees stay sharp, as do any `creases`: [a, b] or [a, b, sharpness]
+    /// by OBJ vertex numbers (from 1), with sharpness in levels.
+    fn parse_subdivided(o: &Value) -> Option<PolyMesh> {
+        let levels = o.get("subdivide")?.as_u64()
+            .unwrap_or_else(|| panic!("Subdivision levels must be a whole number: {}", o)) as usize;
+        let mut poly = PolyMesh::from_obj(
+            &SceneFile::parse_string(&o["src"]),
+            SceneFile::parse_vec3_def(o, "scale", Vector3::new(1., 1., 1.)));
+        if let Some(angle) = o["crease_angle"].as_f64() {
+            poly = poly.with_crease_angle(angle);
+        }
+        for c in o["creases"].as_array().into_iter().flatten() {
+            let c = c.as_array().filter(|c| c.len() == 2 || c.len() == 3)
+                .unwrap_or_else(|| panic!("Creases are [a, b] or [a, b, sharpness]: {}", c));
+            let vertex = |v: &Value| match v.as_u64() {
+                Some(i) if i >= 1 && (i as usize) <= poly.positions.len() => i as usize - 1,
+                _ => panic!("Crease vertex {} isn't in the mesh", v),
+            };
+            let (a, b) = (vertex(&c[0]), vertex(&c[1]));
+            let sharpness = c.get(2).map_or(f64::INFINITY, |s| SceneFile::parse_number(s, 1.));
+            poly = poly.with_crease(a, b, sharpness);
+        }
+        let scheme = match o["scheme"].as_str() {
+            None => poly.default_scheme(),
+            Some("loop") if poly.is_triangles() => Scheme::Loop,
+            Some("loop") => {
+                println!("!! [ Warning in scene parse ] Loop subdivision needs triangles, using Catmull-Clark");
+                Scheme::CatmullClark
+            },
+            Some("catmull-clark") => Scheme::CatmullClark,
+            Some(s) => panic!("Unknown subdivision scheme: {}", s),
+        };
+        Some(poly.subdivide(scheme, levels))
+    }
+
+    /// Moves the origin to `base`, and turns +y to point along `axis`
+    fn along_axis(base: Vector3<f64>, axis: Vector3<f64>) -> Affine3<f64> {
+        let turn = Rotation3::rotation_between(&Vector3::y(), &axis)
+            // Only fails pointing straight down
+            .unwrap_or_else(|| Rotation3::from_axis_angle(&Vector3::x_axis(), std::f64::consts::PI));
+        na::convert(Translation3::from(base) * turn)
+    }
+
+    /// A cylinder from `base` to `top`. Cones narrow to `top_radius`, 0 by default, and
+    /// both are closed at the ends unless `"capped": false`.
+    pub fn parse_cylinder(o: &Value) -> Box<dyn Geometry + Sync + Send> {
+        let base = SceneFile::parse_vec3(&o["base"]);
+        let axis = SceneFile::parse_vec3(&o["top"]) - base;
+        let radius = o["radius"].as_f64().unwrap();
+        let capped = o["capped"].as_bool().unwrap_or(true);
+        let shape = if o["type"].as_str() == Some("cone") {
+            Cylinder::cone(radius, SceneFile::parse_number(&o["top_radius"], 0.), axis.norm(), capped)
+        } else {
+            Cylinder::new(radius, axis.norm(), capped)
+        };
+        Box::new(Transform::affine(Box::new(shape), SceneFile::along_axis(base, axis)))
+    }
+
+    /// A torus around `location`, in the plane perpendicular to `axis` (default +y)
+    pub fn parse_torus(o: &Value) -> Box<dyn Geometry + Sync + Send> {
+        let shape = Torus::new(o["radius"].as_f64().unwrap(), o["tube_radius"].as_f64().unwrap());
+        Box::new(Transform::affine(Box::new(shape), SceneFile::along_axis(
+            SceneFile::parse_vec3(&o["location"]),
+            SceneFile::parse_vec3_def(o, "axis", Vector3::new(0., 1., 0.)))))
+    }
+
     pub fn parse_sphere(o: &Value) -> Box<dyn Geometry + Sync + Send> {
         Box::new(Sphere::new(
                 SceneFile::parse_vec3(&o["location"]),
@@ -294,15 +1064,38 @@ impl SceneFile {
                 SceneFile::parse_vec3(&o["v2"])))
     }
 
+    /// A plane through `location` facing `normal`, or ax + by + cz + d = 0 from `abcd`, or
+    /// the horizontal plane at `y`. With `width` and `height` it's cut to a rectangle of that
+    /// size (along the plane's u and v) centred on `location`, or with `radius`, a disk.
     pub fn parse_plane(o: &Value) -> Box<dyn Geometry + Sync + Send> {
-        Box::new(Plane {
-                y: SceneFile::parse_number(&o["y"], 0.),
-            })
+        let plane = if let Some(abcd) = o["abcd"].as_array() {
+            if abcd.len() != 4 {
+                panic!("Plane abcd needs 4 numbers: {:?}", o["abcd"]);
+            }
+            let n: Vec<f64> = abcd.iter().map(|x| x.as_f64().unwrap()).collect();
+            Plane::from_abcd(n[0], n[1], n[2], n[3])
+        } else if o.get("location").is_some() || o.get("normal").is_some() {
+            Plane::new(
+                SceneFile::parse_vec3_def(o, "location", Vector3::zeros()),
+                SceneFile::parse_vec3_def(o, "normal", Vector3::new(0., 1., 0.)))
+        } else {
+            Plane::y(SceneFile::parse_number(&o["y"], 0.))
+        };
+
+        if o.get("radius").is_some() {
+            return Box::new(plane.with_extent(Extent::Disk(SceneFile::parse_number(&o["radius"], 1.))));
+        }
+        if o.get("width").is_some() || o.get("height").is_some() {
+            return Box::new(plane.with_extent(Extent::Rectangle(
+                SceneFile::parse_number(&o["width"], 1.),
+                SceneFile::parse_number(&o["height"], 1.))));
+        }
+        Box::new(plane)
     }
 
     pub fn parse_checkeredplane(o: &Value, m: Box<dyn Medium + Sync + Send>) -> SceneObject {
         SceneObject {
-            geometry: Box::new(Plane { y: o["y"].as_f64().unwrap() }),
+            geometry: SceneFile::parse_plane(o),
             medium: m
         }
     }
@@ -427,6 +1220,15 @@ impl SceneFile {
             }));
         }
         
+        if t == "checkered" {
+            let m1 = SceneFile::parse_material_ref(&o["m1"], materials).unwrap();
+            let m2 = SceneFile::parse_material_ref(&o["m2"], materials).unwrap();
+            let size = SceneFile::parse_number(&o["size"], 1.);
+            let usize = SceneFile::parse_number(&o["usize"], size);
+            let vsize = SceneFile::parse_number(&o["vsize"], size);
+            return Some(Box::new(Checkered { m1, m2, usize, vsize }));
+        }
+
         if t == "noise" {
             // When noise is used as a medium, parse it here
             // Get base material by reference
@@ -512,6 +1314,19 @@ impl SceneFile {
             
             return Some(Box::new(Solid { m: Box::new(noise_texture) }));
         }
+
+        if t == "image" {
+            let base_material = match o.get("base_material") {
+                Some(k) => SceneFile::parse_material_ref(k, materials).unwrap(),
+                None => Box::new(Lambertian { albedo: Color::white() }),
+            };
+            let texture = ImageTexture {
+                base_material,
+                texture: MipMap::from_file(&SceneFile::parse_string(&o["src"])),
+                scale: SceneFile::parse_number(&o["scale"], 1.),
+            };
+            return Some(Box::new(Solid { m: Box::new(texture) }));
+        }
         
         if t == "noise_medium" {
             // Get the two materials to mix between
@@ -636,36 +1451,133 @@ impl SceneFile {
     }
 
 
-    pub fn parse_light(o: &Value) -> Light {
-        Light {
+    pub fn parse_light_sampling(o: &Value) -> LightSampling {
+        match o.get("sampling").and_then(|v| v.as_str()) {
+            Some("uniform") => LightSampling::Uniform,
+            _ => LightSampling::SolidAngle,
+        }
+    }
+
+    pub fn parse_area_light(o: &Value, shape: Box<dyn Geometry + Sync + Send>) -> Box<dyn Light> {
+        Box::new(AreaLight {
+            shape,
+            color: SceneFile::parse_color_def(o, "color", Color::white()),
+            intensity: o["intensity"].as_f64().unwrap(),
+            sampling: SceneFile::parse_light_sampling(o),
+            two_sided: o["two_sided"].as_bool().unwrap_or(false),
+        })
+    }
+
+    pub fn parse_light(o: &Value) -> Box<dyn Light> {
+        let t = o.get("type").and_then(|v| v.as_str()).unwrap_or("point");
+
+        if t == "sphere" {
+            return SceneFile::parse_area_light(o, Box::new(Sphere::new(
+                SceneFile::parse_vec3(&o["location"]),
+                o["radius"].as_f64().unwrap())));
+        }
+
+        if t == "quad" || t == "rectangle" {
+            return SceneFile::parse_area_light(o, Box::new(Rectangle::new(
+                SceneFile::parse_vec3(&o["corner"]),
+                SceneFile::parse_vec3(&o["u"]),
+                SceneFile::parse_vec3(&o["v"]))));
+        }
+
+        if t == "disk" {
+            return SceneFile::parse_area_light(o, Box::new(Disk::new(
+                SceneFile::parse_vec3(&o["location"]),
+                SceneFile::parse_vec3_def(o, "normal", Vector3::new(0., -1., 0.)),
+                o["radius"].as_f64().unwrap())));
+        }
+
+        if t == "spot" {
+            return Box::new(SpotLight {
                 position: SceneFile::parse_vec3(&o["location"]),
-                color: Color::white(),
+                direction: SceneFile::parse_light_direction(o),
+                angle: SceneFile::parse_number(&o["angle"], 30.).to_radians(),
+                penumbra: SceneFile::parse_number(&o["penumbra"], 5.).to_radians(),
+                color: SceneFile::parse_color_def(o, "color", Color::white()),
                 intensity: o["intensity"].as_f64().unwrap(),
-            }
+            });
+        }
+
+        if t == "directional" || t == "sun" {
+            let diameter = if t == "sun" { 0.53 } else { 0. };
+            return Box::new(DirectionalLight {
+                direction: SceneFile::parse_light_direction(o),
+                angular_diameter: SceneFile::parse_number(&o["angular_diameter"], diameter).to_radians(),
+                color: SceneFile::parse_color_def(o, "color", Color::white()),
+                intensity: o["intensity"].as_f64().unwrap(),
+            });
+        }
+
+        if t == "ies" {
+            return Box::new(IesLight {
+                position: SceneFile::parse_vec3(&o["location"]),
+                direction: SceneFile::parse_light_direction(o),
+                profile: IesProfile::from_file(&SceneFile::parse_string(&o["src"])),
+                color: SceneFile::parse_color_def(o, "color", Color::white()),
+                intensity: o["intensity"].as_f64().unwrap(),
+            });
+        }
+
+        Box::new(PointLight {
+                position: SceneFile::parse_vec3(&o["location"]),
+                color: SceneFile::parse_color_def(o, "color", Color::white()),
+                intensity: o["intensity"].as_f64().unwrap(),
+            })
     }
 
-    pub fn parse_lights(lights: &Vec<Value>) -> Vec<Light> {
-        let mut l: Vec<Light> = Vec::new();
+    /// Direction a light points, from `direction` or from `location` towards `lookat`.
+    /// Defaults to straight down.
+    pub fn parse_light_direction(o: &Value) -> Vector3<f64> {
+        if let Some(lookat) = o.get("lookat") {
+            return (SceneFile::parse_vec3(lookat) - SceneFile::parse_vec3(&o["location"])).normalize();
+        }
+        SceneFile::parse_vec3_def(o, "direction", Vector3::new(0., -1., 0.)).normalize()
+    }
+
+    pub fn parse_environment(o: &Value) -> Arc<EnvironmentLight> {
+        Arc::new(EnvironmentLight::from_file(
+            &SceneFile::parse_string(&o["src"]),
+            SceneFile::parse_number(&o["rotation"], 0.).to_radians(),
+            SceneFile::parse_number(&o["intensity"], 1.)))
+    }
+
+    pub fn parse_lights(lights: &Vec<Value>) -> Vec<Box<dyn Light>> {
+        let mut l: Vec<Box<dyn Light>> = Vec::new();
         for light in lights {
             l.push(SceneFile::parse_light(light));
         }
         l
     }
 
-    pub fn from_scenefile(s: SceneFile) -> Scene {
+    /// Build the scene. `width` and `height` override the image size in the file.
+    pub fn from_scenefile(s: SceneFile, width: Option<usize>, height: Option<usize>) -> Scene {
         let max_bounding = BBox::new( // TODO
             Vector3::new(-1000., -1000., -1000.),
             Vector3::new(1000., 1000., 1000.),
         );
 
-        let objects = SceneFile::parse_objects(s.objects, &s.materials, &s.media);
+        let geometries = SceneFile::parse_geometries(&s.geometries);
+        let (objects, mut object_lights) = SceneFile::parse_objects(s.objects, &s.materials, &s.media, &geometries);
         let o = SceneGraph::new(2, objects, max_bounding);
+        let mut lights = SceneFile::parse_lights(&s.lights);
+        lights.append(&mut object_lights);
+        let environment = s.environment.as_ref().map(SceneFile::parse_environment);
+        if let Some(env) = &environment {
+            lights.push(Box::new(env.clone()));
+        }
 		
-        let width = SceneFile::parse_int(&s.width, 640);
-        let height =SceneFile::parse_int(&s.height, 480);
+        let width = width.unwrap_or(SceneFile::parse_int(&s.width, 640));
+        let height = height.unwrap_or(SceneFile::parse_int(&s.height, 480));
+        // Separate stereo files are each the full size, so render them side by side.
+        let stereo_files = s.camera["stereo"]["layout"].as_str() == Some("separate");
+        let width = if stereo_files { width * 2 } else { width };
 
         Scene {
-            image: ImageOpts { width, height },
+            image: ImageOpts { width, height, stereo_files },
             render: RenderOpts {
                 max_depth: SceneFile::parse_int(&s.max_depth, 2),
                 background: SceneFile::parse_color(&s.background),
@@ -673,9 +1585,12 @@ impl SceneFile {
                 supersamples: SceneFile::parse_int(&s.supersamples, 35),  
                 chunk_size: SceneFile::parse_int(&s.chunk_size, 64), 
                 samples_per_chunk: SceneFile::parse_int(&s.samples_per_chunk, 2),
+                light_samples: s.light_samples.as_ref().map(|v| SceneFile::parse_int(v, 4)).unwrap_or(4),
+                shutter: SceneFile::parse_shutter(&s.camera),
             },
-            camera: Box::new(SceneFile::parse_camera(s.camera, width as u32, height as u32)),
-            lights: SceneFile::parse_lights(&s.lights),
+            camera: SceneFile::parse_camera(&s.camera, width as u32, height as u32, &o),
+            lights: LightTree::new(lights),
+            environment,
             objects: o,
             max_bounding,
             black_threshold: SceneFile::parse_number(&s.shadow_bias, 1e-7f64) ,
@@ -683,15 +1598,15 @@ impl SceneFile {
         }
     }
 
-    pub fn from_string(s: String) -> Scene {
+    pub fn from_string(s: String, width: Option<usize>, height: Option<usize>) -> Scene {
         let scene: SceneFile = serde_json::from_str(&s).unwrap();
-        SceneFile::from_scenefile(scene)
+        SceneFile::from_scenefile(scene, width, height)
     }
 
-    pub fn from_file(filename: &str) -> Scene {
+    pub fn from_file(filename: &str, width: Option<usize>, height: Option<usize>) -> Scene {
         let mut scenefile = File::open(filename).unwrap();
         let mut contents = String::new();
         scenefile.read_to_string(&mut contents).unwrap();
-        SceneFile::from_string(contents)
+        SceneFile::from_string(contents, width, height)
     }
 }
@@ -48,6 +48,11 @@ impl SceneGraph {
         &self.items
     }
 
+    /// The partitions of the acceleration structure
+    pub fn partitions(&self) -> Vec<BBox> {
+        self.tree.partitions()
+    }
+
     pub fn len(&self) -> usize {
         self.items.len()
     }
@@ -60,19 +65,8 @@ impl SceneGraph {
     }
 
     pub fn nearest_intersection(&self, r: &Ray, max:f64, min:f64) -> Option<Intersection> {
-        match self.nearest_raw_intersection(r, max, min) {
-            Some(tupl) =>{
-                let scene_obj = tupl.0.clone();
-                Some(
-                            Intersection {
-                              dist: tupl.1.dist, 
-                              point: tupl.1.point,
-                              normal: tupl.1.normal,
-                              object: scene_obj,
-                           })
-            },
-            None => None
-        }
+        self.nearest_raw_intersection(r, max, min)
+            .map(|(object, i)| Intersection::new(&i, object, r.time))
     }
 
 
@@ -109,11 +103,10 @@ impl SceneGraph {
                 None => (),
             }
             */
-            if let Some(x) = o.intersects(r) {
-                if x.dist < cdist && x.dist >= min {
-                    cdist = x.dist;
-                    closest = Some((o.clone(), x));
-                }
+            if let Some(x) = o.intersects(r)
+                && x.dist < cdist && x.dist >= min {
+                cdist = x.dist;
+                closest = Some((o.clone(), x));
             }
         }
         closest
@@ -35,4 +35,12 @@ impl Geometry for SceneObject {
     fn bounds(&self) -> BBox {
         self.geometry.bounds()
     }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        self.geometry.sample_surface()
+    }
+
+    fn area(&self) -> f64 {
+        self.geometry.area()
+    }
 }
@@ -1,9 +1,10 @@
-use crate::na::{Vector3, Point3};
+use crate::na::{Vector2, Vector3, Point3};
 use crate::na::geometry::Transform;
 use std::fmt;
 use crate::ray::Ray;
 use crate::intersection::RawIntersection;
 use crate::shapes::geometry::Geometry;
+use crate::geometry::{rand, cumulative, sample_cumulative};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Box {
@@ -114,6 +115,16 @@ impl BBox {
         true
     }
 
+    /// Distances along a ray (with a unit direction) where it enters and leaves the box,
+    /// from the ray origin if it starts inside.
+    pub fn ray_range(&self, ro: &Vector3<f64>, rd: &Vector3<f64>) -> Option<(f64, f64)> {
+        let (tmin, tmax) = find_min_max(&self.min.coords, &self.max.coords, ro, &vec3_invert(rd));
+        if tmax < 0. || tmin > tmax {
+            return None;
+        }
+        Some((tmin.max(0.), tmax))
+    }
+
     pub fn transform(&self, transform: &na::Transform3<f64>) -> BBox {
         // Transform all vertices and then return bbox of that box.
         let mut ret = BBox::new(
@@ -312,20 +323,30 @@ impl Geometry for BBox {
         if !self.fast_intersects(&r.ro, &invrd) { 
             None
         } else {
-            let (tmin, _tmax) = find_min_max(&self.min.coords, &self.max.coords, &r.ro, &invrd);
-            let dist = tmin;
-            let point =  r.ro + (r.rd * dist);
+            let (tmin, tmax) = find_min_max(&self.min.coords, &self.max.coords, &r.ro, &invrd);
+            // From inside the box, the ray leaves through the far side
+            let entering = tmin >= 0.;
+            let t = if entering { tmin } else { tmax };
+            let point =  r.ro + (r.rd * t);
+            let dist = t * r.rd.norm();
             let center = na::center(&self.min, &self.max);
             let p = (point - center.coords).normalize().component_div(&(self.max - self.min)); 
             let ndir = p.iamax();
             let mut normal = Vector3::new(0.,0.,0.);
             normal[ndir] = if p[ndir].is_sign_positive() { 1. } else { -1. };
 
-            Some(RawIntersection {
-                point,
-                dist,
-                normal
-            })
+            // Each face is parameterised over its other two axes, 0 - 1 across the box
+            let size = self.size();
+            let (ua, va) = ((ndir + 1) % 3, (ndir + 2) % 3);
+            let uv = Vector2::new(
+                (point[ua] - self.min[ua]) / size[ua],
+                (point[va] - self.min[va]) / size[va]);
+            let mut dpdu = Vector3::zeros();
+            let mut dpdv = Vector3::zeros();
+            dpdu[ua] = size[ua];
+            dpdv[va] = size[va];
+
+            Some(RawIntersection::new(dist, point, normal, entering).with_uv(uv, dpdu, dpdv))
         }
     }
 
@@ -340,6 +361,32 @@ impl Geometry for BBox {
     fn inside(&self, p: &Vector3<f64>) -> bool {
         self.contains_point(p)
     }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        // Pick a face (axis and side) weighted by area, then a point on it.
+        let s = self.size();
+        let faces = [s.y * s.z, s.x * s.z, s.x * s.y];
+        let axis = sample_cumulative(&cumulative(faces.iter().cloned()))?;
+        let mut point = Vector3::new(
+            self.min.x + rand() * s.x,
+            self.min.y + rand() * s.y,
+            self.min.z + rand() * s.z,
+        );
+        let mut normal = Vector3::new(0., 0., 0.);
+        if rand() < 0.5 {
+            point[axis] = self.min[axis];
+            normal[axis] = -1.;
+        } else {
+            point[axis] = self.max[axis];
+            normal[axis] = 1.;
+        }
+        Some((point, normal))
+    }
+
+    fn area(&self) -> f64 {
+        let s = self.size();
+        2. * (s.x * s.y + s.y * s.z + s.x * s.z)
+    }
 }
 
 #[cfg(test)]
@@ -354,10 +401,7 @@ mod tests {
             Vector3::new(2., 2., 2.),
         );
 
-        let r = Ray {
-            ro:  Vector3::new(0., 0., 0.),
-            rd:  Vector3::new(1.4, 1., 1.1),
-        };
+        let r = Ray::new(Vector3::new(0., 0., 0.), Vector3::new(1.4, 1., 1.1));
         
 		assert!((b.entry_face(&r).unwrap() == BoxFace::Bottom));
 		assert!((b.exit_face(&r).unwrap() == BoxFace::Right));
@@ -370,10 +414,7 @@ mod tests {
             Vector3::new(2., 2., 2.),
         );
 
-        let r = Ray {
-            ro:  Vector3::new(1.5, 0., 1.5),
-            rd:  Vector3::new(0., 1., 0.),
-        };
+        let r = Ray::new(Vector3::new(1.5, 0., 1.5), Vector3::new(0., 1., 0.));
         
 		assert!((b.entry_face(&r).unwrap() == BoxFace::Bottom));
 		assert!((b.exit_face(&r).unwrap() == BoxFace::Top));
@@ -386,10 +427,7 @@ mod tests {
             Vector3::new(2., 2., 2.),
         );
 
-        let r = Ray {
-            ro:  Vector3::new(1.5, 3.5, 1.5),
-            rd:  Vector3::new(0., -1., 0.),
-        };
+        let r = Ray::new(Vector3::new(1.5, 3.5, 1.5), Vector3::new(0., -1., 0.));
         
 		assert!((b.entry_face(&r).unwrap() == BoxFace::Top));
 		assert!((b.exit_face(&r).unwrap() == BoxFace::Bottom));
@@ -402,25 +440,16 @@ mod tests {
             Vector3::new(2., 2., 2.),
         );
 
-        let r = Ray {
-            ro:  Vector3::new(0., 3.5, 1.5),
-            rd:  (Vector3::new(3.5, 0., 1.5) - Vector3::new(0., 3.5, 1.5)).normalize(),
-        };
+        let r = Ray::new(Vector3::new(0., 3.5, 1.5), (Vector3::new(3.5, 0., 1.5) - Vector3::new(0., 3.5, 1.5)).normalize());
         
 		assert!((b.entry_face(&r).unwrap() == BoxFace::Top));
 		assert!((b.exit_face(&r).unwrap() == BoxFace::Right));
 
-        let r2 = Ray {
-            ro:  Vector3::new(1.5, 3.5, 0.),
-            rd:  (Vector3::new(1.5, 0., 3.5) - Vector3::new(1.5,  3.5, 0.)).normalize(),
-        };
+        let r2 = Ray::new(Vector3::new(1.5, 3.5, 0.), (Vector3::new(1.5, 0., 3.5) - Vector3::new(1.5,  3.5, 0.)).normalize());
 		assert!((b.entry_face(&r2).unwrap() == BoxFace::Top));
 		assert!((b.exit_face(&r2).unwrap() == BoxFace::Back));
 
-        let r3 = Ray {
-            ro:  Vector3::new(1.5, 3.5, 2.5),
-            rd:  (Vector3::new(1.5, 0., 0.) - Vector3::new(1.5,  3.5, 2.5)).normalize(),
-        };
+        let r3 = Ray::new(Vector3::new(1.5, 3.5, 2.5), (Vector3::new(1.5, 0., 0.) - Vector3::new(1.5,  3.5, 2.5)).normalize());
 		assert!((b.entry_face(&r3).unwrap() == BoxFace::Top));
 		assert!((b.exit_face(&r3).unwrap() == BoxFace::Front));
 	}
@@ -5,63 +5,18 @@ use crate::octree::Octree;
 use std::f64;
 use crate::shapes::geometry::Geometry;
 use std::sync::Arc;
+use crate::na::Vector3;
+use crate::geometry::{cumulative, sample_cumulative};
 
 
-struct IntersectionRange(RawIntersection, Option<RawIntersection>);
-type CSGOp = fn(&IntersectionRange, &IntersectionRange) -> Option<RawIntersection>;
-
 const EPSILON:f64 = 0.0000001; // f64::EPSILON is too big...
 
-fn _reflect_normal(i: RawIntersection) -> RawIntersection {
-    let mut j = i;
-    j.normal = i.normal * -1.;
-    j
-}
-
-// A U B
-fn union(a: &IntersectionRange, b: &IntersectionRange) -> Option<RawIntersection> {
-    if a.0.dist < b.0.dist {
-        Some(a.0)
-    } else {
-        Some(b.0)
-    }
-}
-
-// A - B
-fn difference(a: &IntersectionRange, b: &IntersectionRange) -> Option<RawIntersection> {
-    if a.0.dist < b.0.dist {
-        return Some(a.0)
-    }
-
-    match b.1 {
-        Some(bo) => {
-            if bo.dist < a.0.dist {
-                match a.1 {
-                    Some(ao) => {
-                        if ao.dist < bo.dist {
-                            // contained in b
-                            None
-                        } else {
-                            Some(_reflect_normal(bo)) 
-                        }
-                    },
-                    None => {
-                        // Infinite A
-                        Some(_reflect_normal(bo)) 
-                    }
-                }
-            } else {
-                // B is before A
-                Some(a.0)
-            }
-        },
-        None => {
-            // B is infinite
-            None
-        }
-    }
-}
+// Hits on two surfaces closer than this are treated as the same point, so that surfaces
+// shared by both solids don't leave slivers behind.
+const COINCIDENT:f64 = 0.000001;
 
+// Guard against rays that never leave a pathological solid
+const MAX_STEPS:usize = 256;
 
 // Primitive is an extended geometry to allow 'sets' of all intersections. 
 pub struct Primitive {
@@ -74,8 +29,9 @@ impl Primitive {
     // PBRT store both r0 and hitmin in ray, maybe that allows less mutation of mem,
     // but for now let's do it the simpler way.
     pub fn next_intersection(&self, r: &Ray, dist: f64) -> Option<RawIntersection> {
-        let along = (dist + EPSILON) * r.rd.normalize(); 
-        let r2 = Ray { ro: r.ro + along, rd: r.rd}; // TODO scale better (avoids rep) 
+        // Step past the last intersection, further the further away it is as precision drops
+        let along = (dist + EPSILON * dist.max(1.)) * r.rd.normalize();
+        let r2 = Ray { ro: r.ro + along, rd: r.rd, time: r.time};
         match self.item.intersects(&r2) {
         
             Some(mut x) => {
@@ -85,37 +41,6 @@ impl Primitive {
             None => { None}
         }
     }
-
-    fn next_intersection_range(&self, r: &Ray, dist: f64) -> Option<IntersectionRange> {
-        let in_a = self.next_intersection(r, dist);
-        in_a.map(|x| IntersectionRange(x, self.next_intersection(r, x.dist)))
-    }
-    
-
-    fn apply_csg(&self, r: &Ray, op: CSGOp, other: &Primitive) -> Option<RawIntersection> {
-        let mut adist = 0.;
-        let mut bdist = 0.;
-
-        loop {
-            let arange = self.next_intersection_range(r, adist)?;
-            adist += match arange.1 {
-                Some(x) => x.dist,
-                None => arange.0.dist + 1. // Bigger than entry to infinite obj
-            }; 
-
-            while let Some(brange) = other.next_intersection_range(r, bdist) {
-                bdist += match brange.1 {
-                    Some(x) => x.dist,
-                    None => brange.0.dist + 1. // Bigger than entry to infinite obj
-                }; 
-
-                match op(&arange, &brange) {
-                    Some(x) => {return Some(x)}, // TODO BUG - what if b2 | a1 intersects different
-                    None => {} // continue
-                }
-            }
-        }
-    }
 }
 
 impl Geometry for Primitive {
@@ -125,6 +50,18 @@ impl Geometry for Primitive {
     fn bounds(&self) -> BBox {
         self.item.bounds()
     }
+    fn primitives(&self) -> u64 {
+        self.item.primitives()
+    }
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.item.inside(p)
+    }
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        self.item.sample_surface()
+    }
+    fn area(&self) -> f64 {
+        self.item.area()
+    }
 }
 
 
@@ -135,6 +72,7 @@ impl Geometry for Primitive {
 pub struct Union {
     items: Octree<Primitive>,
     primitives: u64,
+    area_cdf: Vec<f64>,
 }
 
 impl Union {
@@ -149,6 +87,7 @@ impl Union {
         let tree = Octree::new(8, bounds, &primitives); 
     
         Union {
+            area_cdf: cumulative(primitives.iter().map(|p| p.area())),
             items: tree,
             primitives: count,
         }
@@ -166,100 +105,233 @@ impl Geometry for Union {
     fn primitives(&self) -> u64 { 
         self.primitives
     }
+
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.items.items().iter().any(|i| i.inside(p))
+    }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        self.items.items()[sample_cumulative(&self.area_cdf)?].sample_surface()
+    }
+
+    fn area(&self) -> f64 {
+        self.area_cdf.last().cloned().unwrap_or(0.)
+    }
 }
 
 
-// A - B
-pub struct Difference {
-    pub a: Primitive,
-    pub b: Primitive
+/// A boolean operation between two solids
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operation {
+    Union,
+    Intersection,
+    Difference,
 }
 
-fn _find_intersect_iter(a: &Primitive, b: &Primitive, r: &Ray, adist: f64, bdist: f64) -> Option<RawIntersection> {
-    match a.next_intersection_range(r, adist) {
-        Some(ia) => {
-            match b.next_intersection_range(r, bdist) {
-                Some(ib) => {
-                    if ia.0.dist < ib.0.dist {
-                        // Enter A first
-                        Some(ia.0)
-                    } else {
-                        _find_intersect_inside_b_iter(a, b, &ia, &ib, r)
-
-                    }
-                }
-                None => {
-                    // No B intersection
-                    Some(ia.0)
-                }
-            }
-        },
-        None => {
-            // Would never have hit A
-            None
+impl Operation {
+    fn apply(&self, in_a: bool, in_b: bool) -> bool {
+        match self {
+            Operation::Union => in_a || in_b,
+            Operation::Intersection => in_a && in_b,
+            Operation::Difference => in_a && !in_b,
         }
     }
 }
 
-fn _find_intersect_inside_b_iter(
-        a: &Primitive,
-        b: &Primitive,
-        arange: &IntersectionRange, 
-        brange: &IntersectionRange,
-        r: &Ray
-    ) -> Option<RawIntersection> {
+// A op B
+//
+// Constructive solid geometry: both items need an inside() (closed solids, or infinite
+// planes as half spaces), and the result has one too, so operations nest.
+//
+// Intersection walks along the ray through the surfaces of both items in order, tracking
+// whether it's inside each, until being inside the result changes.
+pub struct Csg {
+    pub a: Primitive,
+    pub b: Primitive,
+    pub op: Operation,
+}
+
+impl Csg {
+    pub fn new(a: Box<dyn Geometry + Sync + Send>, b: Box<dyn Geometry + Sync + Send>, op: Operation) -> Csg {
+        Csg { a: Primitive { item: a }, b: Primitive { item: b }, op }
+    }
 
-    // We are inside B
-    // last intersections are inda, indb
-    
-    match brange.1 {
-        None => {
-            // That was the last B intersection, B stretches forever.
-            None
-        },
-        Some(nb) => {
-            if nb.dist < arange.0.dist {
-                // We exit b before hitting a => try again
-                return _find_intersect_iter(a, b, r, arange.0.dist - EPSILON, nb.dist); // TODO
-            }
+    // The surface of B faces the other way where it's cut out of A
+    fn b_surface(&self, i: RawIntersection) -> RawIntersection {
+        if self.op == Operation::Difference { i.flipped() } else { i }
+    }
+}
 
-            match arange.1 {
-                Some (exit_a) => {
-                    // We enter A inside B:
-                    if exit_a.dist < nb.dist {
-                        // If we exit A before exiting B
-                        match a.next_intersection_range(r, exit_a.dist + EPSILON){
-                            Some(narange) => {
-                                // There is another A intersection.
-                                print!("!!");
-                                _find_intersect_inside_b_iter(a, b, &narange, brange, r)
-                            },
-                            None => { None }
-                        }
-                    } else {
-                        // We're inside a inside b, the b exit is smaller than a exit
-                        // Therefore as soon as we leave B we are still in A.
-                        // Therefore B exit is intersection
-                        Some(_reflect_normal(nb))
-                    }
-                },
-                None => {
-                    // last A intersection, A is infinite, intersect at exit of B
-                    Some(_reflect_normal(nb))
-                }
+impl Geometry for Csg {
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        let r = Ray { ro: r.ro, rd: r.rd.normalize(), time: r.time };
+        let mut in_a = self.a.item.inside(&r.ro);
+        let mut in_b = self.b.item.inside(&r.ro);
+        let mut next_a = self.a.item.intersects(&r);
+        let mut next_b = self.b.item.intersects(&r);
+
+        for _ in 0..MAX_STEPS {
+            let da = next_a.map_or(f64::INFINITY, |i| i.dist);
+            let db = next_b.map_or(f64::INFINITY, |i| i.dist);
+            let dist = da.min(db);
+            if !dist.is_finite() {
+                return None;
             }
+            let hit_a = next_a.filter(|_| da - dist < COINCIDENT);
+            let hit_b = next_b.filter(|_| db - dist < COINCIDENT);
+
+            // Entering a surface (a front face) means being inside it afterwards. This
+            // corrects itself if inside() was unsure at the start of the ray.
+            let was_inside = self.op.apply(in_a, in_b);
+            if let Some(i) = hit_a { in_a = i.front_face; }
+            if let Some(i) = hit_b { in_b = i.front_face; }
+            let now_inside = self.op.apply(in_a, in_b);
+
+            if was_inside != now_inside {
+                // Of coincident surfaces, prefer the one facing the way the result does
+                let candidates = [hit_a, hit_b.map(|i| self.b_surface(i))];
+                return candidates.iter().flatten()
+                    .find(|i| i.front_face == now_inside)
+                    .or(candidates.iter().flatten().next())
+                    .cloned();
+            }
+
+            if hit_a.is_some() { next_a = self.a.next_intersection(&r, da); }
+            if hit_b.is_some() { next_b = self.b.next_intersection(&r, db); }
+        }
+        None
+    }
+
+    fn bounds(&self) -> BBox {
+        let (a, b) = (self.a.bounds(), self.b.bounds());
+        match self.op {
+            Operation::Union => a.union(&b),
+            Operation::Intersection => BBox {
+                min: a.min.sup(&b.min),
+                max: a.max.inf(&b.max),
+            },
+            Operation::Difference => a, // Can never be bigger than A
         }
     }
+
+    fn primitives(&self) -> u64 {
+        self.a.item.primitives() + self.b.item.primitives()
+    }
+
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.op.apply(self.a.item.inside(p), self.b.item.inside(p))
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::sphere::Sphere;
+    use crate::shapes::mesh::Mesh;
+    use crate::shapes::triangle::Triangle;
 
-impl Geometry for Difference {
-    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
-        _find_intersect_iter(&self.a, &self.b, r, 0., 0.)
-        //return self.a.apply_csg(r, difference, &self.b);
+    fn sphere(x: f64, radius: f64) -> Box<dyn Geometry + Sync + Send> {
+        Box::new(Sphere::new(Vector3::new(x, 0., 0.), radius))
     }
 
-    fn bounds(&self) -> BBox {
-        self.a.bounds()// Can never be bigger than A
+    fn cube(min: Vector3<f64>, max: Vector3<f64>) -> Box<dyn Geometry + Sync + Send> {
+        Box::new(BBox::new(min, max))
+    }
+
+    // A unit cube of triangles, wound to face out
+    fn cube_mesh() -> Mesh {
+        let v = |x: f64, y: f64, z: f64| Vector3::new(x, y, z);
+        let quads = [
+            [v(0., 0., 0.), v(0., 1., 0.), v(1., 1., 0.), v(1., 0., 0.)], // -z
+            [v(0., 0., 1.), v(1., 0., 1.), v(1., 1., 1.), v(0., 1., 1.)], // +z
+            [v(0., 0., 0.), v(0., 0., 1.), v(0., 1., 1.), v(0., 1., 0.)], // -x
+            [v(1., 0., 0.), v(1., 1., 0.), v(1., 1., 1.), v(1., 0., 1.)], // +x
+            [v(0., 0., 0.), v(1., 0., 0.), v(1., 0., 1.), v(0., 0., 1.)], // -y
+            [v(0., 1., 0.), v(0., 1., 1.), v(1., 1., 1.), v(1., 1., 0.)], // +y
+        ];
+        Mesh::new(quads.iter().flat_map(|q| [
+            Arc::new(Triangle::new(q[0], q[1], q[2])),
+            Arc::new(Triangle::new(q[0], q[2], q[3])),
+        ]).collect())
+    }
+
+    #[test]
+    fn test_sphere_operations() {
+        let along_x = Ray::new(Vector3::new(-10., 0., 0.), Vector3::new(1., 0., 0.));
+        // Overlapping spheres from -1 to 1 and 0 to 2
+        let union = Csg::new(sphere(0., 1.), sphere(1., 1.), Operation::Union);
+        assert!((union.intersects(&along_x).unwrap().dist - 9.).abs() < 1e-6);
+        assert!(union.inside(&Vector3::new(1.5, 0., 0.)));
+
+        let intersection = Csg::new(sphere(0., 1.), sphere(1., 1.), Operation::Intersection);
+        let i = intersection.intersects(&along_x).unwrap();
+        assert!((i.dist - 10.).abs() < 1e-6);
+        // On the surface of the second sphere, facing out of it
+        assert!((i.normal - Vector3::new(-1., 0., 0.)).norm() < 1e-6);
+        assert!(i.front_face);
+
+        // The bite taken out of the first sphere faces into the bite
+        let difference = Csg::new(sphere(0., 1.), sphere(1., 1.), Operation::Difference);
+        let from_right = Ray::new(Vector3::new(0.5, 0., 10.), Vector3::new(0., 0., -1.));
+        assert!(difference.intersects(&from_right).is_none());
+        let d = difference.intersects(&Ray::new(Vector3::new(10., 0., 0.), Vector3::new(-1., 0., 0.))).unwrap();
+        assert!((d.point - Vector3::new(0., 0., 0.)).norm() < 1e-6);
+        assert!((d.normal - Vector3::new(1., 0., 0.)).norm() < 1e-6);
+        assert!(d.front_face);
+        assert!(!difference.inside(&Vector3::new(0.5, 0., 0.)));
+    }
+
+    #[test]
+    fn test_nested_from_inside() {
+        // A shell: a sphere with a hollow middle, started from within the solid part
+        let shell = Csg::new(sphere(0., 2.), sphere(0., 1.), Operation::Difference);
+        let out = shell.intersects(&Ray::new(Vector3::new(-1.5, 0., 0.), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((out.dist - 0.5).abs() < 1e-6);
+        // Leaving the shell into the hollow, which is outside of it
+        assert!(!out.front_face);
+        assert!((out.normal - Vector3::new(1., 0., 0.)).norm() < 1e-6);
+
+        // Cutting the shell in half, nested
+        let half = Csg::new(Box::new(shell), cube(Vector3::new(0., -3., -3.), Vector3::new(3., 3., 3.)), Operation::Difference);
+        let i = half.intersects(&Ray::new(Vector3::new(10., 1.5, 0.), Vector3::new(-1., 0., 0.))).unwrap();
+        assert!((i.point.x - 0.).abs() < 1e-6);
+        assert!((i.normal - Vector3::new(1., 0., 0.)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn test_coincident_faces() {
+        // Taking away a box that shares a face doesn't leave that face behind
+        let a = cube(Vector3::new(0., 0., 0.), Vector3::new(2., 2., 2.));
+        let b = cube(Vector3::new(0., 0., 0.), Vector3::new(1., 2., 2.));
+        let notch = Csg::new(a, b, Operation::Difference);
+        let i = notch.intersects(&Ray::new(Vector3::new(-5., 1., 1.), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((i.point.x - 1.).abs() < 1e-6);
+        assert!((i.normal - Vector3::new(-1., 0., 0.)).norm() < 1e-6);
+
+        // Boxes side by side have no wall between them
+        let a = cube(Vector3::new(0., 0., 0.), Vector3::new(1., 1., 1.));
+        let b = cube(Vector3::new(1., 0., 0.), Vector3::new(2., 1., 1.));
+        let joined = Csg::new(a, b, Operation::Union);
+        let i = joined.intersects(&Ray::new(Vector3::new(0.5, 0.5, 0.5), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((i.point.x - 2.).abs() < 1e-6);
+        assert!(!i.front_face);
+    }
+
+    #[test]
+    fn test_mesh_operations() {
+        let mesh = cube_mesh();
+        assert!(mesh.inside(&Vector3::new(0.5, 0.5, 0.5)));
+        assert!(!mesh.inside(&Vector3::new(1.5, 0.5, 0.5)));
+
+        // Scoop a sphere out of a corner of the cube
+        let corner = Box::new(Sphere::new(Vector3::new(1., 1., 1.), 0.5));
+        let scooped = Csg::new(Box::new(mesh), corner, Operation::Difference);
+        let i = scooped.intersects(&Ray::new(Vector3::new(0.9, 0.8, 5.), Vector3::new(0., 0., -1.))).unwrap();
+        assert!((i.point.z - (1. - 0.2f64.sqrt())).abs() < 1e-6);
+        assert!(i.front_face);
+        let miss = scooped.intersects(&Ray::new(Vector3::new(1.2, 1.2, 5.), Vector3::new(0., 0., -1.)));
+        assert!(miss.is_none());
+        let face = scooped.intersects(&Ray::new(Vector3::new(0.3, 0.45, 5.), Vector3::new(0., 0., -1.))).unwrap();
+        assert!((face.dist - 4.).abs() < 1e-6);
     }
 }
@@ -0,0 +1,222 @@
+use crate::na::{Vector2, Vector3};
+use crate::ray::Ray;
+use crate::intersection::RawIntersection;
+use crate::shapes::bbox::BBox;
+use crate::shapes::geometry::Geometry;
+use crate::geometry::{rand, random_point_on_disc, cumulative, sample_cumulative};
+use std::f64;
+
+/// A cylinder standing on the origin along +y, from y = 0 to `height`. With a different
+/// radius at the top it's a cone (or with a non-zero top radius, a truncated cone).
+///
+/// Capped cylinders are closed solids, with normals facing out. Uncapped ones are open tubes,
+/// whose normals face the ray like other surfaces without an inside.
+///
+/// Place it in the scene with a `Transform`.
+pub struct Cylinder {
+    pub radius: f64,
+    pub top_radius: f64,
+    pub height: f64,
+    pub capped: bool,
+}
+
+/// Which part of a cylinder was hit
+enum Part {
+    Side,
+    Bottom,
+    Top,
+}
+
+impl Cylinder {
+    pub fn new(radius: f64, height: f64, capped: bool) -> Cylinder {
+        Cylinder { radius, top_radius: radius, height, capped }
+    }
+
+    pub fn cone(radius: f64, top_radius: f64, height: f64, capped: bool) -> Cylinder {
+        Cylinder { radius, top_radius, height, capped }
+    }
+
+    /// Change in radius per unit of height
+    fn slope(&self) -> f64 {
+        (self.top_radius - self.radius) / self.height
+    }
+
+    fn radius_at(&self, y: f64) -> f64 {
+        self.radius + self.slope() * y
+    }
+
+    /// Nearest hit in front of the ray, and the part it's on
+    fn nearest(&self, ro: &Vector3<f64>, rd: &Vector3<f64>) -> Option<(f64, Part)> {
+        let k = self.slope();
+        let mut best: Option<(f64, Part)> = None;
+        let mut consider = |dist: f64, part: Part| {
+            if dist > 0. && best.as_ref().is_none_or(|(d, _)| dist < *d) {
+                best = Some((dist, part));
+            }
+        };
+
+        // The side is x^2 + z^2 = (r + k y)^2
+        let base = self.radius + k * ro.y;
+        let a = rd.x * rd.x + rd.z * rd.z - k * k * rd.y * rd.y;
+        let b = ro.x * rd.x + ro.z * rd.z - k * rd.y * base;
+        let c = ro.x * ro.x + ro.z * ro.z - base * base;
+        let d = b * b - a * c;
+        if a.abs() > f64::EPSILON && d >= 0. {
+            for dist in [(-b - d.sqrt()) / a, (-b + d.sqrt()) / a] {
+                let y = ro.y + rd.y * dist;
+                if (0. ..= self.height).contains(&y) {
+                    consider(dist, Part::Side);
+                }
+            }
+        }
+
+        if self.capped && rd.y.abs() > f64::EPSILON {
+            for (y, radius, part) in [(0., self.radius, Part::Bottom), (self.height, self.top_radius, Part::Top)] {
+                let dist = (y - ro.y) / rd.y;
+                let p = ro + rd * dist;
+                if p.x * p.x + p.z * p.z <= radius * radius {
+                    consider(dist, part);
+                }
+            }
+        }
+        best
+    }
+
+    fn side_area(&self) -> f64 {
+        let slant = ((self.radius - self.top_radius).powi(2) + self.height * self.height).sqrt();
+        f64::consts::PI * (self.radius + self.top_radius) * slant
+    }
+}
+
+impl Geometry for Cylinder {
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        let rdn = r.rd.normalize();
+        let (dist, part) = self.nearest(&r.ro, &rdn)?;
+        let point = r.ro + rdn * dist;
+
+        let i = match part {
+            Part::Side => {
+                let k = self.slope();
+                let rho = (point.x * point.x + point.z * point.z).sqrt().max(f64::EPSILON);
+                let phi = point.z.atan2(point.x);
+                let u = phi / (2. * f64::consts::PI) + if phi < 0. { 1. } else { 0. };
+                let uv = Vector2::new(u, point.y / self.height);
+                let normal = Vector3::new(point.x, -k * rho, point.z).normalize();
+                let dpdu = Vector3::new(-point.z, 0., point.x) * 2. * f64::consts::PI;
+                let dpdv = Vector3::new(point.x / rho * k, 1., point.z / rho * k) * self.height;
+                // The normal turns around the axis, and is constant along the side
+                let dndu = dpdu / (rho * (1. + k * k).sqrt());
+                RawIntersection::new(dist, point, normal, rdn.dot(&normal) < 0.)
+                    .with_uv(uv, dpdu, dpdv)
+                    .with_normal_derivatives(dndu, Vector3::zeros())
+            },
+            Part::Bottom | Part::Top => {
+                let (normal, radius) = match part {
+                    Part::Bottom => (Vector3::new(0., -1., 0.), self.radius),
+                    _ => (Vector3::new(0., 1., 0.), self.top_radius),
+                };
+                // Planar coordinates, 0 - 1 across the cap
+                let size = 2. * radius;
+                let uv = Vector2::new(0.5 + point.x / size, 0.5 + point.z / size);
+                RawIntersection::new(dist, point, normal, rdn.dot(&normal) < 0.)
+                    .with_uv(uv, Vector3::new(size, 0., 0.), Vector3::new(0., 0., size))
+            },
+        };
+
+        if !self.capped && !i.front_face {
+            // Inside an open tube, which has no inside to face
+            let mut flipped = i.flipped();
+            flipped.front_face = false;
+            return Some(flipped);
+        }
+        Some(i)
+    }
+
+    fn bounds(&self) -> BBox {
+        let r = self.radius.max(self.top_radius);
+        BBox::new(Vector3::new(-r, 0., -r), Vector3::new(r, self.height, r))
+    }
+
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.capped && p.y > 0. && p.y < self.height &&
+            (p.x * p.x + p.z * p.z).sqrt() < self.radius_at(p.y)
+    }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let caps = if self.capped { f64::consts::PI } else { 0. };
+        let areas = [self.side_area(), caps * self.radius.powi(2), caps * self.top_radius.powi(2)];
+        match sample_cumulative(&cumulative(areas.iter().cloned()))? {
+            0 => {
+                // Wider parts of a cone have more area, so pick the height in proportion
+                let widest = self.radius.max(self.top_radius);
+                let y = loop {
+                    let y = rand() * self.height;
+                    if rand() * widest <= self.radius_at(y) {
+                        break y;
+                    }
+                };
+                let phi = rand() * 2. * f64::consts::PI;
+                let rho = self.radius_at(y);
+                let normal = Vector3::new(phi.cos(), -self.slope(), phi.sin()).normalize();
+                Some((Vector3::new(rho * phi.cos(), y, rho * phi.sin()), normal))
+            },
+            1 => {
+                let p = random_point_on_disc(self.radius);
+                Some((Vector3::new(p.x, 0., p.y), Vector3::new(0., -1., 0.)))
+            },
+            _ => {
+                let p = random_point_on_disc(self.top_radius);
+                Some((Vector3::new(p.x, self.height, p.y), Vector3::new(0., 1., 0.)))
+            },
+        }
+    }
+
+    fn area(&self) -> f64 {
+        let caps = if self.capped {
+            f64::consts::PI * (self.radius.powi(2) + self.top_radius.powi(2))
+        } else {
+            0.
+        };
+        self.side_area() + caps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cylinder() {
+        let c = Cylinder::new(1., 2., true);
+        let side = c.intersects(&Ray::new(Vector3::new(-5., 1., 0.), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((side.dist - 4.).abs() < 1e-9);
+        assert!((side.normal - Vector3::new(-1., 0., 0.)).norm() < 1e-9);
+        assert!((side.uv.y - 0.5).abs() < 1e-9);
+        let top = c.intersects(&Ray::new(Vector3::new(0.5, 5., 0.), Vector3::new(0., -1., 0.))).unwrap();
+        assert!((top.point - Vector3::new(0.5, 2., 0.)).norm() < 1e-9);
+        assert_eq!(top.normal, Vector3::new(0., 1., 0.));
+        assert!(c.inside(&Vector3::new(0.5, 1., 0.5)));
+        assert!(!c.inside(&Vector3::new(0.5, 2.5, 0.5)));
+
+        // Without caps, rays pass down the middle, and the inside of the tube faces them
+        let open = Cylinder::new(1., 2., false);
+        assert!(open.intersects(&Ray::new(Vector3::new(0., 5., 0.), Vector3::new(0., -1., 0.))).is_none());
+        let inner = open.intersects(&Ray::new(Vector3::new(0., 1., 0.), Vector3::new(1., 0., 0.))).unwrap();
+        assert!(!inner.front_face);
+        assert!((inner.normal - Vector3::new(-1., 0., 0.)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_cone() {
+        let c = Cylinder::cone(1., 0., 1., true);
+        // Half way up, the cone is half as wide, and its side faces 45 degrees up
+        let side = c.intersects(&Ray::new(Vector3::new(-5., 0.5, 0.), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((side.point - Vector3::new(-0.5, 0.5, 0.)).norm() < 1e-9);
+        assert!((side.normal - Vector3::new(-1., 1., 0.).normalize()).norm() < 1e-9);
+        assert!(side.dpdu.dot(&side.normal).abs() < 1e-9);
+        assert!(side.dpdv.dot(&side.normal).abs() < 1e-9);
+        // Not the mirrored cone above the apex
+        assert!(c.intersects(&Ray::new(Vector3::new(-5., 1.5, 0.), Vector3::new(1., 0., 0.))).is_none());
+        assert!((c.area() - f64::consts::PI * (1. + 2f64.sqrt())).abs() < 1e-9);
+    }
+}
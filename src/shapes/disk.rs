@@ -0,0 +1,69 @@
+use crate::na::{Vector2, Vector3};
+use crate::ray::Ray;
+use crate::intersection::RawIntersection;
+use crate::shapes::bbox::BBox;
+use crate::shapes::geometry::Geometry;
+use crate::geometry::{random_point_on_disc, tangents};
+use std::f64;
+
+/// A flat circular disk facing along its normal.
+pub struct Disk {
+    pub center: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub radius: f64,
+}
+
+impl Disk {
+    pub fn new(center: Vector3<f64>, normal: Vector3<f64>, radius: f64) -> Disk {
+        Disk { center, normal: normal.normalize(), radius }
+    }
+}
+
+impl Geometry for Disk {
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        let rdn = r.rd.normalize();
+        let denom = self.normal.dot(&rdn);
+        if denom.abs() < f64::EPSILON {
+            return None;
+        }
+
+        let dist = (self.center - r.ro).dot(&self.normal) / denom;
+        if dist <= 0. {
+            return None;
+        }
+
+        let point = r.ro + rdn * dist;
+        if (point - self.center).norm_squared() > self.radius * self.radius {
+            return None;
+        }
+
+        // Planar coordinates, 0 - 1 across the disk
+        let (t, b) = tangents(&self.normal);
+        let d = point - self.center;
+        let size = 2. * self.radius;
+        let uv = Vector2::new(0.5 + d.dot(&t) / size, 0.5 + d.dot(&b) / size);
+        let normal = if denom > 0. { -self.normal } else { self.normal };
+        Some(RawIntersection::new(dist, point, normal, denom < 0.).with_uv(uv, t * size, b * size))
+    }
+
+    fn bounds(&self) -> BBox {
+        // Extent of a disk along each axis is radius * sin(angle between axis and normal)
+        let n = self.normal;
+        let e = Vector3::new(
+            (1. - n.x * n.x).max(0.).sqrt(),
+            (1. - n.y * n.y).max(0.).sqrt(),
+            (1. - n.z * n.z).max(0.).sqrt(),
+        ) * self.radius;
+        BBox::new(self.center - e, self.center + e)
+    }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let (t, b) = tangents(&self.normal);
+        let p = random_point_on_disc(self.radius);
+        Some((self.center + t * p.x + b * p.y, self.normal))
+    }
+
+    fn area(&self) -> f64 {
+        f64::consts::PI * self.radius * self.radius
+    }
+}
@@ -39,5 +39,12 @@ pub trait Geometry: Sync{
     // NB. We ignore 'ON' for points that are on the surface, as floating comparison
     // is problematic. 
     fn inside(&self, _: &Vector3<f64>) -> bool { false }
+
+    // Uniformly sample a point on the surface, returning (point, normal). Used to treat
+    // geometry as an area light, None if the shape can't be sampled.
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> { None }
+
+    // Surface area, used alongside sample_surface. Zero for shapes that can't be sampled.
+    fn area(&self) -> f64 { 0. }
 }
 
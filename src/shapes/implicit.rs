@@ -0,0 +1,241 @@
+use crate::na::Vector3;
+use crate::ray::Ray;
+use crate::intersection::RawIntersection;
+use crate::noise::{PerlinNoise, WorleyNoise, combined_noise};
+use crate::shapes::bbox::BBox;
+use crate::shapes::geometry::Geometry;
+use std::f64;
+
+/// A blob of field around `center`, falling to nothing at `radius`
+#[derive(Clone, Debug)]
+pub struct Metaball {
+    pub center: Vector3<f64>,
+    pub radius: f64,
+    pub strength: f64,
+}
+
+impl Metaball {
+    /// Steepest slope of the kernel, strength · max of 6x(1 - x²)², at x = 1/√5
+    fn lipschitz(&self) -> f64 {
+        self.strength.abs() * 96. / (25. * 5f64.sqrt() * self.radius)
+    }
+}
+
+/// A scalar field, whose isosurface is the surface
+pub enum Field {
+    /// Sum of "soft object" kernels, strength · (1 - r²/R²)³
+    ///
+    /// ## References
+    /// 1. Wyvill, G., McPheeters, C., Wyvill, B. (1986). Data structure for soft objects.
+    Metaballs(Vec<Metaball>),
+    /// `combined_noise::density_field`, Perlin shape less Worley detail
+    Density { perlin: Box<PerlinNoise>, worley: WorleyNoise, scale: f64, falloff: f64 },
+}
+
+impl Field {
+    pub fn value(&self, p: &Vector3<f64>) -> f64 {
+        match self {
+            Field::Metaballs(balls) => balls.iter()
+                .map(|b| {
+                    let x2 = (p - b.center).norm_squared() / (b.radius * b.radius);
+                    if x2 < 1. { b.strength * (1. - x2).powi(3) } else { 0. }
+                })
+                .sum(),
+            Field::Density { perlin, worley, scale, falloff } =>
+                combined_noise::density_field(*p, perlin, worley, *scale, *falloff),
+        }
+    }
+
+    pub fn gradient(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        match self {
+            Field::Metaballs(balls) => balls.iter()
+                .map(|b| {
+                    let d = p - b.center;
+                    let r2 = b.radius * b.radius;
+                    let x2 = d.norm_squared() / r2;
+                    if x2 < 1. { d * (-6. * b.strength * (1. - x2).powi(2) / r2) } else { Vector3::zeros() }
+                })
+                .sum(),
+            Field::Density { scale, .. } => {
+                let h = 1e-4 / scale;
+                Vector3::from_fn(|axis, _| {
+                    let mut e = Vector3::zeros();
+                    e[axis] = h;
+                    (self.value(&(p + e)) - self.value(&(p - e))) / (2. * h)
+                })
+            },
+        }
+    }
+
+    /// Most the field changes over a unit of distance, if known
+    pub fn lipschitz(&self) -> Option<f64> {
+        match self {
+            Field::Metaballs(balls) => Some(balls.iter().map(Metaball::lipschitz).sum()),
+            Field::Density { .. } => None,
+        }
+    }
+
+    /// Size of the smallest features, if known
+    pub fn feature_size(&self) -> Option<f64> {
+        match self {
+            Field::Metaballs(balls) => Some(balls.iter().map(|b| b.radius).fold(f64::INFINITY, f64::min)),
+            Field::Density { .. } => None,
+        }
+    }
+
+    /// Where the field can be more than nothing, if it's limited
+    pub fn bounds(&self) -> Option<BBox> {
+        match self {
+            Field::Metaballs(balls) => balls.iter()
+                .map(|b| {
+                    let r = Vector3::new(b.radius, b.radius, b.radius);
+                    BBox::new(b.center - r, b.center + r)
+                })
+                .reduce(|a, b| a.union(&b)),
+            Field::Density { .. } => None,
+        }
+    }
+}
+
+/// The surface where a scalar field crosses `threshold`, with the field above it inside.
+///
+/// Rays step along within `bounds` looking for the field to cross the threshold, then
+/// bisect to the crossing. Where the field's Lipschitz bound is known, steps are as long as
+/// they can be without passing through the surface; otherwise, or closer than that, they
+/// are `step` long, so features thinner than a step may be missed.
+///
+/// ## References
+/// 1. Kalra, D., Barr, A. H. (1989). Guaranteed ray intersections with implicit surfaces.
+pub struct Implicit {
+    pub field: Field,
+    pub threshold: f64,
+    pub bounds: BBox,
+    /// Shortest step along the ray
+    pub step: f64,
+    /// Crossings are found to within this distance
+    pub epsilon: f64,
+    lipschitz: Option<f64>,
+}
+
+impl Implicit {
+    pub fn new(field: Field, threshold: f64, bounds: BBox, step: f64, epsilon: f64) -> Implicit {
+        let lipschitz = field.lipschitz();
+        Implicit { field, threshold, bounds, step, epsilon, lipschitz }
+    }
+
+    /// Metaballs, bounded by their extent, stepping by a tenth of the smallest
+    pub fn metaballs(balls: Vec<Metaball>, threshold: f64) -> Implicit {
+        assert!(!balls.is_empty(), "Metaballs need at least one ball");
+        let field = Field::Metaballs(balls);
+        let step = field.feature_size().unwrap() / 10.;
+        let bounds = field.bounds().unwrap();
+        Implicit::new(field, threshold, bounds, step, 1e-6)
+    }
+
+    fn excess(&self, r: &Ray, t: f64) -> f64 {
+        self.field.value(&(r.ro + r.rd * t)) - self.threshold
+    }
+}
+
+impl Geometry for Implicit {
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        let r = Ray { ro: r.ro, rd: r.rd.normalize(), time: r.time };
+        let (mut t, end) = self.bounds.ray_range(&r.ro, &r.rd)?;
+        let mut g = self.excess(&r, t);
+
+        while t < end {
+            let stride = self.lipschitz.map_or(0., |l| g.abs() / l).max(self.step);
+            let next = (t + stride).min(end);
+            let gn = self.excess(&r, next);
+            // Rays leaving the surface start on it, so skip crossing it right at the origin
+            if (g > 0.) != (gn > 0.) && next > 4. * self.epsilon {
+                let (mut a, mut b) = (t, next);
+                while b - a > self.epsilon {
+                    let mid = (a + b) / 2.;
+                    if (self.excess(&r, mid) > 0.) == (g > 0.) { a = mid } else { b = mid }
+                }
+                if b > 4. * self.epsilon {
+                    let point = r.ro + r.rd * b;
+                    let normal = -self.field.gradient(&point).normalize();
+                    if normal.iter().all(|x| x.is_finite()) {
+                        return Some(RawIntersection::new(b, point, normal, g <= 0.));
+                    }
+                }
+            }
+            t = next;
+            g = gn;
+        }
+        None
+    }
+
+    fn bounds(&self) -> BBox {
+        self.bounds
+    }
+
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.bounds.contains_point(p) && self.field.value(p) > self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ball(x: f64) -> Metaball {
+        Metaball { center: Vector3::new(x, 0., 0.), radius: 1., strength: 1. }
+    }
+
+    #[test]
+    fn test_single_metaball() {
+        // (1 - r²)³ = 0.125 at r² = 0.5
+        let blob = Implicit::metaballs(vec![ball(0.)], 0.125);
+        let i = blob.intersects(&Ray::new(Vector3::new(0., 0., -5.), Vector3::new(0., 0., 2.))).unwrap();
+        assert!((i.dist - (5. - 0.5f64.sqrt())).abs() < 1e-5);
+        assert!((i.normal - Vector3::new(0., 0., -1.)).norm() < 1e-6);
+        assert!(i.front_face);
+        // And out again from inside
+        let out = blob.intersects(&Ray::new(Vector3::zeros(), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((out.dist - 0.5f64.sqrt()).abs() < 1e-5);
+        assert!(!out.front_face);
+        assert!(out.normal.x > 0.99);
+        // Leaving the surface doesn't hit it again
+        assert!(blob.intersects(&Ray::new(i.point, Vector3::new(0., 0., -1.))).is_none());
+        assert!(blob.inside(&Vector3::new(0.5, 0., 0.)));
+        assert!(!blob.inside(&Vector3::new(0.8, 0., 0.)));
+    }
+
+    #[test]
+    fn test_metaballs_merge() {
+        // Apart, neither reaches the midpoint; together they join there
+        let pair = Implicit::metaballs(vec![ball(-0.7), ball(0.7)], 0.2);
+        let d = Vector3::new(0., -1., 0.);
+        assert!(pair.field.value(&Vector3::zeros()) > 0.2);
+        assert!(Implicit::metaballs(vec![ball(-0.7)], 0.2).intersects(&Ray::new(Vector3::new(0., 5., 0.), d)).is_none());
+        let i = pair.intersects(&Ray::new(Vector3::new(0., 5., 0.), d)).unwrap();
+        assert!((pair.field.value(&i.point) - 0.2).abs() < 1e-5);
+        assert!(i.normal.y > 0.99);
+    }
+
+    #[test]
+    fn test_density_field() {
+        let field = Field::Density {
+            perlin: Box::new(PerlinNoise::with_seed(3)),
+            worley: WorleyNoise::new(1., 42),
+            scale: 2.,
+            falloff: 0.5,
+        };
+        let bounds = BBox::new(Vector3::new(-5., -5., -5.), Vector3::new(5., 5., 5.));
+        let rock = Implicit::new(field, 0.15, bounds, 0.01, 1e-6);
+        let mut hits = 0;
+        for k in 0..50 {
+            let a = k as f64 * 0.4;
+            let ro = Vector3::new(8. * a.cos(), (k % 5) as f64 - 2., 8. * a.sin());
+            if let Some(i) = rock.intersects(&Ray::new(ro, -ro)) {
+                assert!((rock.field.value(&i.point) - 0.15).abs() < 1e-3);
+                assert!(i.front_face);
+                hits += 1;
+            }
+        }
+        assert!(hits > 0);
+    }
+}
@@ -8,11 +8,7 @@ pub struct Infinite {}
 
 impl Geometry for Infinite{
     fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
-        Some(RawIntersection {
-            dist: f64::MAX,
-            point: r.ro + r.rd * f64::MAX,
-            normal: r.rd * -1.
-        })
+        Some(RawIntersection::new(f64::MAX, r.ro + r.rd * f64::MAX, r.rd * -1., false))
     }
 
     fn bounds(&self) -> BBox {
@@ -0,0 +1,60 @@
+use crate::ray::Ray;
+use crate::intersection::RawIntersection;
+use crate::shapes::bbox::BBox;
+use crate::shapes::geometry::Geometry;
+use crate::na::Vector3;
+use std::sync::Arc;
+
+/// Geometry shared between many objects, eg. a mesh placed many times over. The geometry,
+/// and its acceleration structure, is only stored once however many instances there are.
+/// Each instance is placed by wrapping it in a `Transform`.
+pub struct Instance {
+    pub geometry: Arc<dyn Geometry + Sync + Send>,
+}
+
+impl Geometry for Instance {
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        self.geometry.intersects(r)
+    }
+    fn bounds(&self) -> BBox {
+        self.geometry.bounds()
+    }
+    fn fast_intersects(&self, r: &Ray) -> bool {
+        self.geometry.fast_intersects(r)
+    }
+    fn primitives(&self) -> u64 {
+        self.geometry.primitives()
+    }
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.geometry.inside(p)
+    }
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        self.geometry.sample_surface()
+    }
+    fn area(&self) -> f64 {
+        self.geometry.area()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::sphere::Sphere;
+    use crate::shapes::transform::Transform;
+    use crate::na::{Affine3, Translation3};
+
+    #[test]
+    fn test_instances_share_geometry() {
+        let sphere: Arc<dyn Geometry + Sync + Send> = Arc::new(Sphere::new(Vector3::new(0., 0., 0.), 1.));
+        let place = |x: f64| Transform::affine(
+            Box::new(Instance { geometry: sphere.clone() }),
+            na::convert::<_, Affine3<f64>>(Translation3::new(x, 0., 0.)));
+        let (a, b) = (place(-5.), place(5.));
+        assert_eq!(Arc::strong_count(&sphere), 3);
+
+        let down = |x: f64| Ray::new(Vector3::new(x, 10., 0.), Vector3::new(0., -1., 0.));
+        assert!(a.intersects(&down(-5.)).is_some());
+        assert!(a.intersects(&down(5.)).is_none());
+        assert!((b.intersects(&down(5.)).unwrap().point - Vector3::new(5., 1., 0.)).norm() < 1e-9);
+    }
+}
@@ -1,29 +1,31 @@
-/// Meshes
-///
-/// For simplicity, we'll limit the scope to triangular meshes (rather than including arbitrary
-/// polygonal meshes) and limit that scope to meshes that are manifold.
-///
-/// In order to use a mesh in a ray-tracing context, we will need to use an acceleration structure
-/// to performantly intersect with the appropriate triangle. Because of this, at render time, the
-/// mesh will need the repetition of vertices necessary to store the triangles in an Octree.
-///
-/// (POSSIBLE ENHANCEMENT: Is there a way to use the octree with indices to dynamically reconstruct
-/// triangles?)
-///
-/// Most other Mesh datastructures are optimisations to reduce the memory requirements by removing
-/// repetition of positional data using indexes of vertices. This makes a lot of sense when you
-/// care about a lot of serialisation (for example streaming to a GPU) but in our context of
-/// in-memory tracing, doesn't provide a lot of advantages.
-///
-/// ## References:
-/// 1. Fundamentals of Computer Graphics (4th Ed.) - Marschner, Steve
-
+//! Meshes
+//!
+//! For simplicity, we'll limit the scope to triangular meshes (rather than including arbitrary
+//! polygonal meshes) and limit that scope to meshes that are manifold.
+//!
+//! In order to use a mesh in a ray-tracing context, we will need to use an acceleration structure
+//! to performantly intersect with the appropriate triangle. Because of this, at render time, the
+//! mesh will need the repetition of vertices necessary to store the triangles in an Octree.
+//!
+//! (POSSIBLE ENHANCEMENT: Is there a way to use the octree with indices to dynamically reconstruct
+//! triangles?)
+//!
+//! Most other Mesh datastructures are optimisations to reduce the memory requirements by removing
+//! repetition of positional data using indexes of vertices. This makes a lot of sense when you
+//! care about a lot of serialisation (for example streaming to a GPU) but in our context of
+//! in-memory tracing, doesn't provide a lot of advantages.
+//!
+//! ## References:
+//! 1. Fundamentals of Computer Graphics (4th Ed.) - Marschner, Steve
 
 use std::path::Path;
+use std::fs::File;
+use std::io::BufReader;
+use std::collections::HashMap;
 use tobj;
 use std::f64;
 use crate::shapes::geometry::Geometry;
-use crate::na::{Vector3};
+use crate::na::{Vector2, Vector3};
 use crate::ray::Ray;
 use crate::intersection::RawIntersection;
 use crate::shapes::bbox::BBox;
@@ -31,51 +33,121 @@ use crate::shapes::triangle::{Triangle, SmoothTriangle};
 use std::vec::Vec;
 use std::sync::Arc;
 use crate::octree::Octree;
+use crate::geometry::{cumulative, sample_cumulative};
 
 pub struct Mesh {
     triangles: Octree<Triangle>,
     bounds: BBox,
     triangle_count: usize,
+    area_cdf: Vec<f64>,
+}
+
+/// Load an obj, returning the models and the MTL materials they reference.
+/// If the .mtl can't be read we fall back to loading the geometry alone.
+fn load_obj(pth: &str) -> (Vec<tobj::Model>, Vec<tobj::Material>) {
+    match tobj::load_obj(Path::new(pth)) {
+        Ok(x) => x,
+        Err(tobj::LoadError::OpenFileFailed) if Path::new(pth).exists() => {
+            println!("!! [ Warning in OBJ parse ] Could not load materials for {}", pth);
+            let mut reader = BufReader::new(File::open(pth).unwrap());
+            tobj::load_obj_buf(&mut reader, |_| Ok((Vec::new(), HashMap::new()))).unwrap()
+        },
+        Err(e) => panic!("Could not load {}: {:?}", pth, e),
+    }
+}
+
+/// Whether an obj assigns any MTL materials to its faces
+pub fn uses_mtl(pth: &str) -> bool {
+    std::fs::read_to_string(pth)
+        .map(|s| s.lines().any(|l| l.trim_start().starts_with("usemtl")))
+        .unwrap_or(false)
+}
+
+/// Split models into groups of faces sharing an MTL material, in order of first use.
+fn group_by_material(models: Vec<tobj::Model>) -> Vec<(Option<usize>, Vec<tobj::Model>)> {
+    let mut groups: Vec<(Option<usize>, Vec<tobj::Model>)> = Vec::new();
+    for m in models {
+        match groups.iter_mut().find(|g| g.0 == m.mesh.material_id) {
+            Some(g) => g.1.push(m),
+            None => groups.push((m.mesh.material_id, vec![m])),
+        }
+    }
+    groups
+}
+
+fn positions_of(mesh: &tobj::Mesh, scale: &Vector3<f64>) -> Vec<Vector3<f64>> {
+    mesh.positions
+        .chunks(3)
+        .map(|i| Vector3::new(i[0] as f64, i[1] as f64, i[2] as f64))
+        .map(|i| i.component_mul(scale))
+        .collect()
+}
+
+/// Texture coordinates of a face's vertices, if the obj has them
+fn texcoords_of(mesh: &tobj::Mesh, face: &[u32]) -> Option<[Vector2<f64>; 3]> {
+    if mesh.texcoords.is_empty() {
+        return None;
+    }
+    let t = |i: u32| Vector2::new(mesh.texcoords[2 * i as usize] as f64, mesh.texcoords[2 * i as usize + 1] as f64);
+    Some([t(face[0]), t(face[1]), t(face[2])])
+}
+
+/// Load an obj as one mesh per MTL material. Faces without a material are
+/// grouped together with a material of None.
+pub fn load_obj_groups<T, F>(pth: String, build: F) -> Vec<(Option<tobj::Material>, T)>
+    where F: Fn(&[tobj::Model]) -> T {
+    let (models, materials) = load_obj(&pth);
+    group_by_material(models)
+        .into_iter()
+        .map(|(material_id, models)| (
+            material_id.and_then(|i| materials.get(i).cloned()),
+            build(&models),
+        ))
+        .collect()
 }
 
 // A simple triangle collection mesh. 
 impl Mesh {
     pub fn from_obj(pth: String, scale: Vector3<f64>) -> Mesh {
-        let obj = tobj::load_obj(Path::new(&pth));
-        assert!(obj.is_ok());
-        let (models, _materials) = obj.unwrap();
-        //println!("# of models: {}", models.len());
-        //println!("# of materials: {}", materials.len());
+        let (models, _materials) = load_obj(&pth);
+        Mesh::from_models(&models, scale)
+    }
+
+    /// Load an obj, splitting it into a mesh for each MTL material.
+    pub fn from_obj_groups(pth: String, scale: Vector3<f64>) -> Vec<(Option<tobj::Material>, Mesh)> {
+        load_obj_groups(pth, |models| Mesh::from_models(models, scale))
+    }
 
+    fn from_models(models: &[tobj::Model], scale: Vector3<f64>) -> Mesh {
         let mut triangles = Vec::new();
         for m in models.iter() {
             let mesh = &m.mesh;
-            let positions: Arc<Vec<Vector3<f64>>> = Arc::new(
-                mesh.positions
-                    .chunks(3)
-                    .map(|i| Vector3::new(i[0] as f64, i[1] as f64, i[2] as f64))
-                    .map(|i| i.component_mul(&scale))
-                    .collect()
-            );
+            let positions = positions_of(mesh, &scale);
             let mut tris: Vec<Arc<Triangle>> = mesh.indices.chunks(3).map(|i| {
-                Arc::new(
-                    Triangle::new(
+                let t = Triangle::new(
                         positions[i[0] as usize],
                         positions[i[1] as usize],
-                        positions[i[2] as usize])
-                    )
+                        positions[i[2] as usize]);
+                Arc::new(match texcoords_of(mesh, i) {
+                    Some(uv) => t.with_texcoords(uv),
+                    None => t,
+                })
             }).collect();
             triangles.append(&mut tris);
         }
 
         //println!("# of triangles: {}", triangles.len());
+        Mesh::new(triangles)
+    }
 
+    pub fn new(triangles: Vec<Arc<Triangle>>) -> Mesh {
         let bounds = Mesh::bounds_of(&triangles);
         let tree = Octree::new(8, bounds, &triangles); 
         Mesh {
             triangles: tree,
             bounds,
-            triangle_count: triangles.len()
+            triangle_count: triangles.len(),
+            area_cdf: cumulative(triangles.iter().map(|t| t.area())),
         }
     }
 
@@ -90,11 +162,26 @@ impl Mesh {
     }
 }
 
+/// Whether a point is inside a closed mesh: the nearest surface in any direction is the back
+/// of a face. Used as the inside of meshes for CSG.
+fn inside_closed_mesh(mesh: &dyn Geometry, p: &Vector3<f64>) -> bool {
+    if !mesh.bounds().contains_point(p) {
+        return false;
+    }
+    // Askew, to avoid running along edges of axis aligned meshes
+    let rd = Vector3::new(0.5773, 0.5801, 0.5749);
+    mesh.intersects(&Ray::new(*p, rd)).is_some_and(|i| !i.front_face)
+}
+
 impl Geometry for Mesh {
     fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
         self.triangles.raw_intersection(r, f64::INFINITY, 0f64)
     }
 
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        inside_closed_mesh(self, p)
+    }
+
     fn bounds(&self) -> BBox {
         self.bounds
     }
@@ -102,6 +189,14 @@ impl Geometry for Mesh {
     fn primitives(&self) -> u64 {
         self.triangle_count as u64
     }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        self.triangles.items()[sample_cumulative(&self.area_cdf)?].sample_surface()
+    }
+
+    fn area(&self) -> f64 {
+        self.area_cdf.last().cloned().unwrap_or(0.)
+    }
 }
 
 
@@ -109,16 +204,21 @@ pub struct SmoothMesh {
     triangles: Octree<SmoothTriangle>,
     bounds: BBox,
     triangle_count: usize,
+    area_cdf: Vec<f64>,
 }
 
 impl SmoothMesh {
     pub fn from_obj(pth: String, scale: Vector3<f64>) -> SmoothMesh {
-        let obj = tobj::load_obj(Path::new(&pth));
-        assert!(obj.is_ok());
-        let (models, _materials) = obj.unwrap();
-        //println!("# of models: {}", models.len());
-        //println!("# of materials: {}", materials.len());
+        let (models, _materials) = load_obj(&pth);
+        SmoothMesh::from_models(&models, scale)
+    }
+
+    /// Load an obj, splitting it into a mesh for each MTL material.
+    pub fn from_obj_groups(pth: String, scale: Vector3<f64>) -> Vec<(Option<tobj::Material>, SmoothMesh)> {
+        load_obj_groups(pth, |models| SmoothMesh::from_models(models, scale))
+    }
 
+    fn from_models(models: &[tobj::Model], scale: Vector3<f64>) -> SmoothMesh {
         let mut triangles = Vec::new();
         for m in models.iter() {
             let mesh = &m.mesh;
@@ -128,43 +228,40 @@ impl SmoothMesh {
             }
             //println!("> model {:?}", mesh.normals.len());
 
-            let positions: Arc<Vec<Vector3<f64>>> = Arc::new(
-                mesh.positions
+            let positions = positions_of(mesh, &scale);
+            let normals: Vec<Vector3<f64>> = mesh.normals
                     .chunks(3)
                     .map(|i| Vector3::new(i[0] as f64, i[1] as f64, i[2] as f64))
-                    .map(|i| i.component_mul(&scale))
-                    .collect()
-            );
-            let normals: Arc<Vec<Vector3<f64>>> = Arc::new(
-                mesh.normals
-                    .chunks(3)
-                    .map(|i| Vector3::new(i[0] as f64, i[1] as f64, i[2] as f64))
-                    .collect()
-            );
+                    .collect();
             let mut tris: Vec<Arc<SmoothTriangle>> = mesh.indices.chunks(3).map(|i| {
-                let _n = positions[i[0] as usize];
-                Arc::new(
-                    SmoothTriangle::new(
+                let t = SmoothTriangle::new(
                         positions[i[0] as usize],
                         positions[i[1] as usize],
                         positions[i[2] as usize],
                         normals[i[0] as usize],
                         normals[i[1] as usize],
                         normals[i[2] as usize],
-                    )
-                    )
+                    );
+                Arc::new(match texcoords_of(mesh, i) {
+                    Some(uv) => t.with_texcoords(uv),
+                    None => t,
+                })
             }).collect();
             triangles.append(&mut tris);
         }
 
         println!("# of triangles: {}", triangles.len());
+        SmoothMesh::new(triangles)
+    }
 
+    pub fn new(triangles: Vec<Arc<SmoothTriangle>>) -> SmoothMesh {
         let bounds = SmoothMesh::bounds_of(&triangles);
         let tree = Octree::new(8, bounds, &triangles); 
         SmoothMesh {
             triangles: tree,
             bounds,
-            triangle_count: triangles.len()
+            triangle_count: triangles.len(),
+            area_cdf: cumulative(triangles.iter().map(|t| t.area())),
         }
     }
 
@@ -184,6 +281,10 @@ impl Geometry for SmoothMesh {
         self.triangles.raw_intersection(r, f64::INFINITY, 0f64)
     }
 
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        inside_closed_mesh(self, p)
+    }
+
     fn bounds(&self) -> BBox {
         self.bounds
     }
@@ -191,5 +292,40 @@ impl Geometry for SmoothMesh {
     fn primitives(&self) -> u64 {
         self.triangle_count as u64
     }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        self.triangles.items()[sample_cumulative(&self.area_cdf)?].sample_surface()
+    }
+
+    fn area(&self) -> f64 {
+        self.area_cdf.last().cloned().unwrap_or(0.)
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::na::Vector2;
+
+    #[test]
+    fn test_obj_texcoords() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n";
+        let (models, _) = tobj::load_obj_buf(&mut obj.as_bytes(), |_| Ok((Vec::new(), HashMap::new()))).unwrap();
+        let mesh = Mesh::from_models(&models, Vector3::new(2., 2., 2.));
+        let r = Ray::new(Vector3::new(0.6, 1.4, 1.), Vector3::new(0., 0., -1.));
+        let i = mesh.intersects(&r).unwrap();
+        assert!((i.uv - Vector2::new(0.3, 0.7)).norm() < 1e-6);
+        assert!((i.dpdu - Vector3::new(2., 0., 0.)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn test_uses_mtl() {
+        let pth = std::env::temp_dir().join("rays-uses-mtl.obj");
+        std::fs::write(&pth, "mtllib a.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\n  usemtl red\nf 1 2 3\n").unwrap();
+        assert!(uses_mtl(pth.to_str().unwrap()));
+        std::fs::write(&pth, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        assert!(!uses_mtl(pth.to_str().unwrap()));
+        assert!(!uses_mtl("missing.obj"));
+    }
+}
@@ -1,32 +1,100 @@
-use crate::na::{Vector3};
+use crate::na::{Vector2, Vector3};
 use crate::ray::Ray;
 use crate::intersection::RawIntersection;
 use crate::shapes::bbox::BBox;
 use crate::shapes::geometry::Geometry;
+use crate::shapes::rectangle::Rectangle;
+use crate::shapes::disk::Disk;
+use std::f64;
 
+/// How much of a plane there is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Extent {
+    Infinite,
+    /// Width along u by height along v, centred on the plane's point
+    Rectangle(f64, f64),
+    /// Radius around the plane's point
+    Disk(f64),
+}
+
+/// A plane through `point`, facing `normal`, either infinite or cut to a finite extent, as
+/// the Rectangle or Disk that covers it.
+///
+/// Surface coordinates are distances along `u` and `v` from `point`, so textures keep the
+/// plane's own orientation, and are the same whatever its extent. A plane facing +y has u
+/// along x and v along z.
+///
+/// As a solid (eg. in CSG), everything behind an infinite plane is inside it.
 pub struct Plane {
-    pub y: f64
+    pub point: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub u: Vector3<f64>,
+    pub v: Vector3<f64>,
+    finite: Option<Box<dyn Geometry + Sync + Send>>,
+}
+
+impl Plane {
+    pub fn new(point: Vector3<f64>, normal: Vector3<f64>) -> Plane {
+        let normal = normal.normalize();
+        // Project x into the plane, or z if the plane faces along x
+        let across = if normal.x.abs() > 0.9 { Vector3::new(0., 0., 1.) } else { Vector3::new(1., 0., 0.) };
+        let u = (across - normal * normal.dot(&across)).normalize();
+        let v = u.cross(&normal);
+        Plane { point, normal, u, v, finite: None }
+    }
+
+    pub fn with_extent(mut self, extent: Extent) -> Plane {
+        self.finite = match extent {
+            Extent::Infinite => None,
+            // Edges along v then u, so that it faces along the normal
+            Extent::Rectangle(w, h) => Some(Box::new(Rectangle::new(
+                self.point - self.u * (w / 2.) - self.v * (h / 2.), self.v * h, self.u * w))),
+            Extent::Disk(radius) => Some(Box::new(Disk::new(self.point, self.normal, radius))),
+        };
+        self
+    }
+
+    /// Distances along u and v from the plane's point
+    fn uv(&self, point: &Vector3<f64>) -> Vector2<f64> {
+        let local = point - self.point;
+        Vector2::new(local.dot(&self.u), local.dot(&self.v))
+    }
+
+    /// The horizontal plane at height `y`
+    pub fn y(y: f64) -> Plane {
+        Plane::new(Vector3::new(0., y, 0.), Vector3::new(0., 1., 0.))
+    }
+
+    /// The plane ax + by + cz + d = 0, facing (a, b, c)
+    pub fn from_abcd(a: f64, b: f64, c: f64, d: f64) -> Plane {
+        let n = Vector3::new(a, b, c);
+        let len2 = n.norm_squared();
+        assert!(len2 > 0., "Plane needs a non-zero normal");
+        Plane::new(n * (-d / len2), n)
+    }
 }
 
 impl Geometry for Plane {
     fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        if let Some(shape) = &self.finite {
+            return shape.intersects(r).map(|i| {
+                let uv = self.uv(&i.point);
+                i.with_uv(uv, self.u, self.v)
+            });
+        }
+
         let rdn = r.rd.normalize();
-        let mut norm = Vector3::new(0., 1., 0.);
-        let denom = norm.dot(&rdn);
+        let denom = self.normal.dot(&rdn);
 
         if denom.abs() > 0. {
-            let dist = -(norm.dot(&r.ro) - self.y) / denom;
+            let dist = (self.point - r.ro).dot(&self.normal) / denom;
             if dist > 0. {
-                if denom > 0. {
-                    norm = -norm
-                }
-
+                let point = r.ro + (rdn * dist);
+                let uv = self.uv(&point);
+                let norm = if denom > 0. { -self.normal } else { self.normal };
                 return Some(
-                    RawIntersection {
-                        dist, 
-                        point: r.ro + (rdn * dist),
-                        normal: norm,
-                    })
+                    RawIntersection::new(dist, point, norm, denom < 0.)
+                        .with_uv(uv, self.u, self.v))
             }
         }
 
@@ -34,9 +102,82 @@ impl Geometry for Plane {
     }
 
     fn bounds(&self) -> BBox {
-        BBox::new(
-            Vector3::new(f64::MIN, 0., f64::MIN),
-            Vector3::new(f64::MAX, f64::MIN_POSITIVE, f64::MAX),
-          )
+        if let Some(shape) = &self.finite {
+            return shape.bounds();
+        }
+        // Thin along an axis it faces, otherwise it fills all of space. Either way it's too
+        // big for the scene graph, which intersects it separately.
+        let mut min = Vector3::new(f64::MIN, f64::MIN, f64::MIN);
+        let mut max = Vector3::new(f64::MAX, f64::MAX, f64::MAX);
+        for axis in 0..3 {
+            if self.normal[axis].abs() == 1. {
+                min[axis] = self.point[axis];
+                max[axis] = self.point[axis] + f64::MIN_POSITIVE;
+            }
+        }
+        BBox::new(min, max)
+    }
+
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.finite.is_none() && (p - self.point).dot(&self.normal) < 0.
+    }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        self.finite.as_ref()?.sample_surface()
+    }
+
+    fn area(&self) -> f64 {
+        self.finite.as_ref().map_or(0., |shape| shape.area())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_y_plane_uv() {
+        let p = Plane::y(1.);
+        let i = p.intersects(&Ray::new(Vector3::new(2., 5., 3.), Vector3::new(0., -1., 0.))).unwrap();
+        assert!((i.dist - 4.).abs() < 1e-9);
+        assert_eq!(i.uv, Vector2::new(2., 3.));
+        assert!(i.front_face);
+        // From below, facing the ray
+        let below = p.intersects(&Ray::new(Vector3::new(0., -1., 0.), Vector3::new(0., 1., 0.))).unwrap();
+        assert_eq!(below.normal, Vector3::new(0., -1., 0.));
+        assert!(!below.front_face);
+    }
+
+    #[test]
+    fn test_tilted_plane() {
+        // x + y = 2
+        let p = Plane::from_abcd(1., 1., 0., -2.);
+        let i = p.intersects(&Ray::new(Vector3::new(0., 0., 0.), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((i.point - Vector3::new(2., 0., 0.)).norm() < 1e-9);
+        assert!((i.normal - Vector3::new(-1., -1., 0.).normalize()).norm() < 1e-9);
+        assert!(i.dpdu.dot(&p.normal).abs() < 1e-9 && i.dpdv.dot(&p.normal).abs() < 1e-9);
+        assert!(p.inside(&Vector3::zeros()));
+        assert!(!p.inside(&Vector3::new(2., 2., 0.)));
+        // Not axis aligned, so it's unbounded
+        assert_eq!(p.bounds().max.y, f64::MAX);
+    }
+
+    #[test]
+    fn test_finite_plane() {
+        let square = Plane::y(0.).with_extent(Extent::Rectangle(2., 4.));
+        let down = Vector3::new(0., -1., 0.);
+        let i = square.intersects(&Ray::new(Vector3::new(0.5, 1., 1.5), down)).unwrap();
+        // Same surface coordinates as the infinite plane
+        assert!((i.uv - Vector2::new(0.5, 1.5)).norm() < 1e-9);
+        assert_eq!(i.normal, Vector3::new(0., 1., 0.));
+        assert!(i.front_face);
+        assert!(square.intersects(&Ray::new(Vector3::new(1.5, 1., 0.), down)).is_none());
+        assert_eq!(square.area(), 8.);
+        assert_eq!(square.sample_surface().unwrap().1, Vector3::new(0., 1., 0.));
+        assert_eq!(square.bounds().max.z, 2.);
+
+        let disk = Plane::y(0.).with_extent(Extent::Disk(1.));
+        assert!(disk.intersects(&Ray::new(Vector3::new(0.6, 1., 0.6), down)).is_some());
+        assert!(disk.intersects(&Ray::new(Vector3::new(0.8, 1., 0.8), down)).is_none());
     }
 }
@@ -0,0 +1,69 @@
+use crate::na::{Vector2, Vector3};
+use crate::ray::Ray;
+use crate::intersection::RawIntersection;
+use crate::shapes::bbox::BBox;
+use crate::shapes::geometry::Geometry;
+use crate::geometry::rand;
+
+/// A parallelogram spanned by two edges from a corner:
+///     corner, corner + u, corner + u + v, corner + v
+/// With perpendicular edges this is a rectangle.
+pub struct Rectangle {
+    pub corner: Vector3<f64>,
+    pub u: Vector3<f64>,
+    pub v: Vector3<f64>,
+    pub normal: Vector3<f64>,
+}
+
+impl Rectangle {
+    pub fn new(corner: Vector3<f64>, u: Vector3<f64>, v: Vector3<f64>) -> Rectangle {
+        Rectangle {
+            corner, u, v,
+            normal: u.cross(&v).normalize(),
+        }
+    }
+}
+
+impl Geometry for Rectangle {
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        let rdn = r.rd.normalize();
+        let denom = self.normal.dot(&rdn);
+        if denom.abs() < f64::EPSILON {
+            return None;
+        }
+
+        let dist = (self.corner - r.ro).dot(&self.normal) / denom;
+        if dist <= 0. {
+            return None;
+        }
+
+        let point = r.ro + rdn * dist;
+        let d = point - self.corner;
+        // Solve d = a.u + b.v in the plane via the dual basis
+        let n = self.u.cross(&self.v);
+        let nn = n.dot(&n);
+        let a = d.cross(&self.v).dot(&n) / nn;
+        let b = self.u.cross(&d).dot(&n) / nn;
+        if !(0. ..=1.).contains(&a) || !(0. ..=1.).contains(&b) {
+            return None;
+        }
+
+        let normal = if denom > 0. { -self.normal } else { self.normal };
+        Some(RawIntersection::new(dist, point, normal, denom < 0.).with_uv(Vector2::new(a, b), self.u, self.v))
+    }
+
+    fn bounds(&self) -> BBox {
+        BBox::new(self.corner, self.corner)
+            .union_point(&(self.corner + self.u))
+            .union_point(&(self.corner + self.v))
+            .union_point(&(self.corner + self.u + self.v))
+    }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        Some((self.corner + self.u * rand() + self.v * rand(), self.normal))
+    }
+
+    fn area(&self) -> f64 {
+        self.u.cross(&self.v).norm()
+    }
+}
@@ -79,6 +79,7 @@ impl RepeatingMesh {
         let transformed_ray = Ray {
             ro: r.ro - transform,
             rd: r.rd,
+            time: r.time,
         };
         //println!(" 2. ... {:?} {}", self.tile.bounds().intersects(&transformed_ray), transformed_ray);
 
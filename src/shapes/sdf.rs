@@ -0,0 +1,312 @@
+use crate::na::{Vector2, Vector3};
+use crate::ray::Ray;
+use crate::intersection::RawIntersection;
+use crate::shapes::bbox::BBox;
+use crate::shapes::geometry::Geometry;
+
+/// A signed distance function: the distance from a point to the nearest surface, negative
+/// inside. Built as an expression tree of primitives and the operations on them.
+///
+/// ## References
+/// 1. https://iquilezles.org/articles/distfunctions/
+pub enum Sdf {
+    Sphere { radius: f64 },
+    /// Centred on the origin, `half_size` from the middle to each side
+    Box { half_size: Vector3<f64> },
+    /// Around the y axis
+    Torus { radius: f64, tube_radius: f64 },
+    /// A line from `a` to `b`, thickened by `radius`
+    Capsule { a: Vector3<f64>, b: Vector3<f64>, radius: f64 },
+    /// With a `smoothness` of 0 these are the plain boolean operations, larger values blend
+    /// the surfaces together over about that distance.
+    Union { a: Box<Sdf>, b: Box<Sdf>, smoothness: f64 },
+    Intersection { a: Box<Sdf>, b: Box<Sdf>, smoothness: f64 },
+    /// `a` with `b` taken away
+    Difference { a: Box<Sdf>, b: Box<Sdf>, smoothness: f64 },
+    Translate { offset: Vector3<f64>, item: Box<Sdf> },
+    Scale { factor: f64, item: Box<Sdf> },
+    /// Infinitely many copies, every `period` along each axis (or none where it's 0)
+    Repeat { period: Vector3<f64>, item: Box<Sdf> },
+    /// Turns `rate` radians around the y axis for every unit up it
+    Twist { rate: f64, item: Box<Sdf> },
+    /// Power 8 is the classic bulb. Fits in a sphere of radius about 1.2.
+    Mandelbulb { power: f64, iterations: usize },
+    /// Fills the cube from -1 to 1
+    Menger { iterations: usize },
+}
+
+fn mix(a: f64, b: f64, t: f64) -> f64 {
+    a * (1. - t) + b * t
+}
+
+impl Sdf {
+    pub fn distance(&self, p: &Vector3<f64>) -> f64 {
+        match self {
+            Sdf::Sphere { radius } => p.norm() - radius,
+            Sdf::Box { half_size } => box_distance(p, half_size),
+            Sdf::Torus { radius, tube_radius } => {
+                Vector2::new(Vector2::new(p.x, p.z).norm() - radius, p.y).norm() - tube_radius
+            },
+            Sdf::Capsule { a, b, radius } => {
+                let (pa, ba) = (p - a, b - a);
+                let h = (pa.dot(&ba) / ba.dot(&ba)).clamp(0., 1.);
+                (pa - ba * h).norm() - radius
+            },
+            Sdf::Union { a, b, smoothness: k } => {
+                let (d1, d2) = (a.distance(p), b.distance(p));
+                if *k <= 0. {
+                    return d1.min(d2);
+                }
+                let h = (0.5 + 0.5 * (d2 - d1) / k).clamp(0., 1.);
+                mix(d2, d1, h) - k * h * (1. - h)
+            },
+            Sdf::Intersection { a, b, smoothness: k } => {
+                let (d1, d2) = (a.distance(p), b.distance(p));
+                if *k <= 0. {
+                    return d1.max(d2);
+                }
+                let h = (0.5 - 0.5 * (d2 - d1) / k).clamp(0., 1.);
+                mix(d2, d1, h) + k * h * (1. - h)
+            },
+            Sdf::Difference { a, b, smoothness: k } => {
+                let (d1, d2) = (a.distance(p), b.distance(p));
+                if *k <= 0. {
+                    return d1.max(-d2);
+                }
+                let h = (0.5 - 0.5 * (d1 + d2) / k).clamp(0., 1.);
+                mix(d1, -d2, h) + k * h * (1. - h)
+            },
+            Sdf::Translate { offset, item } => item.distance(&(p - offset)),
+            Sdf::Scale { factor, item } => item.distance(&(p / *factor)) * factor,
+            Sdf::Repeat { period, item } => {
+                let q = p.zip_map(period, |x, c| if c > 0. { x - c * (x / c).round() } else { x });
+                item.distance(&q)
+            },
+            Sdf::Twist { rate, item } => {
+                let (s, c) = (rate * p.y).sin_cos();
+                item.distance(&Vector3::new(c * p.x + s * p.z, p.y, -s * p.x + c * p.z))
+            },
+            Sdf::Mandelbulb { power, iterations } => mandelbulb_distance(p, *power, *iterations),
+            Sdf::Menger { iterations } => menger_distance(p, *iterations),
+        }
+    }
+
+    /// How much faster than distance the function can change, within `extent` of the origin.
+    /// Most are exact distances (1), but twisting stretches space further from the axis.
+    /// Sphere tracing steps are shortened by this so that they don't overshoot.
+    pub fn lipschitz(&self, extent: f64) -> f64 {
+        match self {
+            Sdf::Union { a, b, .. } | Sdf::Intersection { a, b, .. } | Sdf::Difference { a, b, .. } => {
+                a.lipschitz(extent).max(b.lipschitz(extent))
+            },
+            Sdf::Translate { offset, item } => item.lipschitz(extent + offset.norm()),
+            Sdf::Scale { factor, item } => item.lipschitz(extent / factor),
+            Sdf::Repeat { period, item } => item.lipschitz(extent.min(period.norm())),
+            Sdf::Twist { rate, item } => (1. + (rate * extent).powi(2)).sqrt() * item.lipschitz(extent),
+            _ => 1.,
+        }
+    }
+}
+
+fn box_distance(p: &Vector3<f64>, half_size: &Vector3<f64>) -> f64 {
+    let q = p.abs() - half_size;
+    q.sup(&Vector3::zeros()).norm() + q.max().min(0.)
+}
+
+/// Distance estimate from the derivative of the escape time iteration
+///
+/// ## References
+/// 1. http://blog.hvidtfeldts.net/index.php/2011/09/distance-estimated-3d-fractals-v-the-mandelbulb-different-de-approximations/
+fn mandelbulb_distance(p: &Vector3<f64>, power: f64, iterations: usize) -> f64 {
+    let mut z = *p;
+    let mut dr = 1.;
+    let mut r = z.norm();
+    for _ in 0..iterations {
+        r = z.norm();
+        if r > 2. {
+            break;
+        }
+        // Raise to the power in spherical coordinates, with y up
+        let theta = (z.y / r.max(f64::MIN_POSITIVE)).clamp(-1., 1.).acos() * power;
+        let phi = z.z.atan2(z.x) * power;
+        dr = r.powf(power - 1.) * power * dr + 1.;
+        z = Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()) * r.powf(power) + p;
+    }
+    if r <= f64::MIN_POSITIVE {
+        return 0.;
+    }
+    0.5 * r.ln() * r / dr
+}
+
+/// A cube with crosses cut through it, then through each of the 20 cubes left, and so on.
+///
+/// ## References
+/// 1. https://iquilezles.org/articles/menger/
+fn menger_distance(p: &Vector3<f64>, iterations: usize) -> f64 {
+    let mut d = box_distance(p, &Vector3::new(1., 1., 1.));
+    let mut s = 1.;
+    for _ in 0..iterations {
+        let a = (p * s).map(|x| x.rem_euclid(2.) - 1.);
+        s *= 3.;
+        let r = a.map(|x| (1. - 3. * x.abs()).abs());
+        let (da, db, dc) = (r.x.max(r.y), r.y.max(r.z), r.z.max(r.x));
+        let c = (da.min(db).min(dc) - 1.) / s;
+        d = d.max(c);
+    }
+    d
+}
+
+/// Geometry found by sphere tracing a distance function: stepping along the ray by the
+/// distance to the nearest surface, which can't pass through one, until close enough.
+///
+/// Distance functions can describe infinite shapes, so the tracing is limited to `bounds`,
+/// which also place it in the octree.
+///
+/// ## References
+/// 1. Hart, J. C. (1996). Sphere tracing: a geometric method for the antialiased ray tracing
+///    of implicit surfaces.
+pub struct SdfGeometry {
+    pub sdf: Sdf,
+    pub bounds: BBox,
+    /// Closer than this is on the surface
+    pub epsilon: f64,
+    pub max_steps: usize,
+    /// Fraction of the distance to step, to stay behind the surface where the function
+    /// isn't an exact distance
+    step: f64,
+}
+
+impl SdfGeometry {
+    pub fn new(sdf: Sdf, bounds: BBox, epsilon: f64, max_steps: usize) -> SdfGeometry {
+        let extent = bounds.min.coords.abs().sup(&bounds.max.coords.abs()).norm();
+        let step = 1. / sdf.lipschitz(extent);
+        SdfGeometry { sdf, bounds, epsilon, max_steps, step }
+    }
+
+    /// Outward normal from the gradient of the distance, by the tetrahedron technique
+    ///
+    /// ## References
+    /// 1. https://iquilezles.org/articles/normalsSDF/
+    fn normal(&self, p: &Vector3<f64>) -> Vector3<f64> {
+        let h = self.epsilon;
+        [
+            Vector3::new(1., -1., -1.),
+            Vector3::new(-1., -1., 1.),
+            Vector3::new(-1., 1., -1.),
+            Vector3::new(1., 1., 1.),
+        ].iter()
+            .map(|k| k * self.sdf.distance(&(p + k * h)))
+            .sum::<Vector3<f64>>()
+            .normalize()
+    }
+}
+
+impl Geometry for SdfGeometry {
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        let rdn = r.rd.normalize();
+        let (start, end) = self.bounds.ray_range(&r.ro, &rdn)?;
+
+        // Rays leaving a surface start on it, so step off it first
+        let mut t = start;
+        let mut d = self.sdf.distance(&(r.ro + rdn * t));
+        let mut steps = 0;
+        while d.abs() < self.epsilon && t < end && steps < self.max_steps {
+            t += self.epsilon;
+            d = self.sdf.distance(&(r.ro + rdn * t));
+            steps += 1;
+        }
+        // Inside, the distance is negative; stepping by its size leads to the surface either way
+        let outside = d > 0.;
+
+        while t <= end && steps < self.max_steps {
+            if d.abs() < self.epsilon {
+                let point = r.ro + rdn * t;
+                let normal = self.normal(&point);
+                if !normal.iter().all(|x| x.is_finite()) {
+                    return None;
+                }
+                return Some(RawIntersection::new(t, point, normal, outside));
+            }
+            t += d.abs() * self.step;
+            d = self.sdf.distance(&(r.ro + rdn * t));
+            steps += 1;
+        }
+        None
+    }
+
+    fn bounds(&self) -> BBox {
+        self.bounds
+    }
+
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.bounds.contains_point(p) && self.sdf.distance(p) < 0.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(radius: f64) -> Box<Sdf> {
+        Box::new(Sdf::Sphere { radius })
+    }
+
+    #[test]
+    fn test_primitive_distances() {
+        let p = Vector3::new(3., 0., 0.);
+        assert_eq!(Sdf::Sphere { radius: 1. }.distance(&p), 2.);
+        assert_eq!(Sdf::Box { half_size: Vector3::new(1., 1., 1.) }.distance(&p), 2.);
+        assert!((Sdf::Box { half_size: Vector3::new(1., 1., 1.) }.distance(&Vector3::new(2., 2., 1.)) - 2f64.sqrt()).abs() < 1e-12);
+        assert_eq!(Sdf::Torus { radius: 2., tube_radius: 0.5 }.distance(&p), 0.5);
+        let capsule = Sdf::Capsule { a: Vector3::zeros(), b: Vector3::new(0., 2., 0.), radius: 0.5 };
+        assert_eq!(capsule.distance(&Vector3::new(0., 4., 0.)), 1.5);
+        assert_eq!(capsule.distance(&Vector3::new(1., 1., 0.)), 0.5);
+        // The middle of a menger sponge is hollow, its corners are solid
+        let menger = Sdf::Menger { iterations: 3 };
+        assert!(menger.distance(&Vector3::zeros()) > 0.);
+        assert!(menger.distance(&Vector3::new(0.95, 0.95, 0.95)) < 0.);
+    }
+
+    #[test]
+    fn test_operations() {
+        let p = Vector3::new(1.5, 0., 0.);
+        let apart = |smoothness| Sdf::Union {
+            a: sphere(1.),
+            b: Box::new(Sdf::Translate { offset: Vector3::new(3., 0., 0.), item: sphere(1.) }),
+            smoothness,
+        };
+        assert_eq!(apart(0.).distance(&p), 0.5);
+        // Smoothing fills in the gap between them
+        assert!(apart(1.).distance(&p) < 0.5);
+
+        let bite = Sdf::Difference { a: sphere(1.), b: Box::new(Sdf::Translate { offset: p, item: sphere(1.) }), smoothness: 0. };
+        assert!(bite.distance(&Vector3::new(0.75, 0., 0.)) > 0.);
+        assert!(bite.distance(&Vector3::new(-0.5, 0., 0.)) < 0.);
+
+        let repeated = Sdf::Repeat { period: Vector3::new(4., 0., 0.), item: sphere(1.) };
+        assert_eq!(repeated.distance(&Vector3::new(8.5, 0., 0.)), -0.5);
+        assert_eq!(repeated.distance(&Vector3::new(0., 3., 0.)), 2.);
+
+        let twisted = Sdf::Twist { rate: 1., item: Box::new(Sdf::Box { half_size: Vector3::new(1., 1., 1.) }) };
+        assert!(twisted.lipschitz(2.) > 2.);
+    }
+
+    #[test]
+    fn test_sphere_tracing() {
+        let bounds = BBox::new(Vector3::new(-2., -2., -2.), Vector3::new(2., 2., 2.));
+        let g = SdfGeometry::new(Sdf::Sphere { radius: 1. }, bounds, 1e-6, 256);
+        let i = g.intersects(&Ray::new(Vector3::new(-10., 0., 0.), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((i.dist - 9.).abs() < 1e-5);
+        assert!((i.normal - Vector3::new(-1., 0., 0.)).norm() < 1e-4);
+        assert!(i.front_face);
+        // Leaving from that point, through the inside
+        let out = g.intersects(&Ray::new(i.point, Vector3::new(1., 0., 0.))).unwrap();
+        assert!((out.point.x - 1.).abs() < 1e-5);
+        assert!(!out.front_face);
+        assert!(g.intersects(&Ray::new(Vector3::new(-10., 1.5, 0.), Vector3::new(1., 0., 0.))).is_none());
+
+        let bulb = SdfGeometry::new(Sdf::Mandelbulb { power: 8., iterations: 8 }, bounds, 1e-4, 512);
+        let i = bulb.intersects(&Ray::new(Vector3::new(0., 0., -5.), Vector3::new(0., 0., 1.))).unwrap();
+        assert!(i.point.z > -1.3 && i.point.z < -0.5);
+    }
+}
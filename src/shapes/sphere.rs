@@ -1,8 +1,10 @@
 use crate::shapes::geometry::Geometry;
-use crate::na::{Vector3};
+use crate::na::{Vector2, Vector3};
 use crate::ray::Ray;
 use crate::intersection::RawIntersection;
 use crate::shapes::bbox::BBox;
+use crate::geometry::random_unit_vector;
+use std::f64;
 
 #[derive(PartialEq, Clone)]
 pub struct Sphere {
@@ -17,6 +19,21 @@ impl Sphere{
             radius,
         }
     }
+
+    /// Longitude (u) and latitude (v, 0 at the bottom) of a point on the sphere, with the
+    /// derivatives of the point with respect to them.
+    fn parameterise(&self, point: &Vector3<f64>) -> (Vector2<f64>, Vector3<f64>, Vector3<f64>) {
+        let p = point - self.center;
+        let phi = p.z.atan2(p.x);
+        let theta = (p.y / self.radius).clamp(-1., 1.).acos();
+        let u = phi / (2. * f64::consts::PI) + if phi < 0. { 1. } else { 0. };
+        let v = 1. - theta / f64::consts::PI;
+        // Distance from the axis
+        let rho = (p.x * p.x + p.z * p.z).sqrt();
+        let dpdu = Vector3::new(-p.z, 0., p.x) * 2. * f64::consts::PI;
+        let dpdv = Vector3::new(-p.y * phi.cos(), rho, -p.y * phi.sin()) * f64::consts::PI;
+        (Vector2::new(u, v), dpdu, dpdv)
+    }
 }
 
 
@@ -41,22 +58,23 @@ impl Geometry for Sphere {
         }
 
         let mut dist = (-b - d.sqrt()) / a;
+        let mut outside = true;
 
         if dist.is_sign_negative() {
             // If dist is negative, ray started inside sphere so find other root 
             dist = (-b + d.sqrt()) / a;
+            outside = false;
         }
 	
 		if dist < 0. { return None; }
 
         let point = r.ro + (r.rd.normalize() * dist);
+        let (uv, dpdu, dpdv) = self.parameterise(&point);
 
-        Some(
-            RawIntersection {
-                dist, 
-                point,
-                normal: (point - self.center).normalize()
-            })
+        // The normal is the point scaled down to a unit sphere, so turns as it moves
+        Some(RawIntersection::new(dist, point, (point - self.center).normalize(), outside)
+             .with_uv(uv, dpdu, dpdv)
+             .with_normal_derivatives(dpdu / self.radius, dpdv / self.radius))
     }
 
     fn bounds(&self) -> BBox {
@@ -71,4 +89,44 @@ impl Geometry for Sphere {
                       ),
           )
     }
+
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        (p - self.center).norm() < self.radius
+    }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let n = random_unit_vector();
+        Some((self.center + n * self.radius, n))
+    }
+
+    fn area(&self) -> f64 {
+        4. * f64::consts::PI * self.radius * self.radius
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sphere_differential_geometry() {
+        let s = Sphere::new(Vector3::new(0., 0., 0.), 2.);
+        let outside = s.intersects(&Ray::new(Vector3::new(-10., 0., 0.), Vector3::new(1., 0., 0.))).unwrap();
+        assert!(outside.front_face);
+        // Halfway round, on the equator
+        assert!((outside.uv - Vector2::new(0.5, 0.5)).norm() < 1e-9);
+        assert!(outside.dpdu.dot(&outside.normal).abs() < 1e-9);
+        assert!(outside.dpdv.dot(&outside.normal).abs() < 1e-9);
+        // Moving up the sphere increases v
+        assert!(outside.dpdv.y > 0.);
+        // The point moves with u as the derivative says
+        let step = Ray::new(Vector3::new(-10., 0., -0.01), Vector3::new(1., 0., 0.));
+        let next = s.intersects(&step).unwrap();
+        let predicted = outside.point + outside.dpdu * (next.uv.x - outside.uv.x);
+        assert!((predicted - next.point).norm() < 1e-4);
+
+        let inside = s.intersects(&Ray::new(Vector3::new(0., 0., 0.), Vector3::new(0., 1., 0.))).unwrap();
+        assert!(!inside.front_face);
+        assert!((inside.uv.y - 1.).abs() < 1e-9);
+    }
 }
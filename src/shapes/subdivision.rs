@@ -0,0 +1,477 @@
+//! Subdivision surfaces
+//!
+//! Low-poly meshes are refined at load time into many small faces approaching a smooth limit
+//! surface, which are then traced as an ordinary mesh. Triangle meshes use Loop's scheme,
+//! and any others Catmull–Clark's, which turns every face into quads.
+//!
+//! Edges can be creased to keep them sharp: infinitely (boundaries, and edges sharper than a
+//! `crease_angle`), or for a number of levels, with a fractional sharpness blending the
+//! sharp and smooth rules, so they round off only a little.
+//!
+//! tobj triangulates faces as it loads them, which would make Catmull–Clark subdivide the
+//! triangles rather than the quads of the model, so faces are read from the OBJ here.
+//!
+//! ## References
+//! 1. Loop, C. (1987). Smooth subdivision surfaces based on triangles.
+//! 2. Catmull, E., Clark, J. (1978). Recursively generated B-spline surfaces on arbitrary
+//!    topological meshes.
+//! 3. DeRose, T., Kass, M., Truong, T. (1998). Subdivision surfaces in character animation.
+
+use std::collections::HashMap;
+use std::f64;
+use std::sync::Arc;
+use crate::na::Vector3;
+use crate::shapes::mesh::{Mesh, SmoothMesh};
+use crate::shapes::triangle::{Triangle, SmoothTriangle};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    /// Triangles into four triangles
+    Loop,
+    /// Faces of n sides into n quads
+    CatmullClark,
+}
+
+/// An edge, by its vertices, lowest first
+type Edge = (usize, usize);
+
+fn edge(a: usize, b: usize) -> Edge {
+    (a.min(b), a.max(b))
+}
+
+/// A mesh of polygons, of any number of sides
+#[derive(Clone, Debug, Default)]
+pub struct PolyMesh {
+    pub positions: Vec<Vector3<f64>>,
+    /// Vertex indices, anticlockwise seen from outside
+    pub faces: Vec<Vec<usize>>,
+    /// Sharpness of creased edges, in levels of subdivision
+    pub creases: HashMap<Edge, f64>,
+}
+
+/// Which faces and vertices meet
+struct Adjacency {
+    edge_faces: HashMap<Edge, Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+    vertex_edges: Vec<Vec<Edge>>,
+}
+
+impl Adjacency {
+    fn new(mesh: &PolyMesh) -> Adjacency {
+        let mut edge_faces: HashMap<Edge, Vec<usize>> = HashMap::new();
+        let mut vertex_faces = vec![Vec::new(); mesh.positions.len()];
+        let mut vertex_edges = vec![Vec::new(); mesh.positions.len()];
+        for (f, face) in mesh.faces.iter().enumerate() {
+            for (i, &v) in face.iter().enumerate() {
+                vertex_faces[v].push(f);
+                let e = edge(v, face[(i + 1) % face.len()]);
+                let faces = edge_faces.entry(e).or_default();
+                if faces.is_empty() {
+                    vertex_edges[e.0].push(e);
+                    vertex_edges[e.1].push(e);
+                }
+                faces.push(f);
+            }
+        }
+        Adjacency { edge_faces, vertex_faces, vertex_edges }
+    }
+}
+
+/// Blend of the smooth and sharp rule, for edges and vertices of a sharpness
+fn blend(smooth: Vector3<f64>, sharp: Vector3<f64>, sharpness: f64) -> Vector3<f64> {
+    if sharpness >= 1. {
+        sharp
+    } else if sharpness <= 0. {
+        smooth
+    } else {
+        smooth.lerp(&sharp, sharpness)
+    }
+}
+
+fn other(e: &Edge, v: usize) -> usize {
+    if e.0 == v { e.1 } else { e.0 }
+}
+
+/// The first of a set of joined items (union–find)
+fn root(set: &mut [usize], mut i: usize) -> usize {
+    while set[i] != i {
+        set[i] = set[set[i]];
+        i = set[i];
+    }
+    i
+}
+
+impl PolyMesh {
+    /// The vertices and faces of an OBJ, keeping polygons whole. Everything else is ignored.
+    pub fn from_obj(pth: &str, scale: Vector3<f64>) -> PolyMesh {
+        match std::fs::read_to_string(pth) {
+            Ok(text) => PolyMesh::parse_obj(&text, scale),
+            Err(e) => panic!("Could not load {}: {:?}", pth, e),
+        }
+    }
+
+    pub fn parse_obj(text: &str, scale: Vector3<f64>) -> PolyMesh {
+        let mut mesh = PolyMesh::default();
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("v") => {
+                    let c: Vec<f64> = words.take(3)
+                        .map(|w| w.parse().unwrap_or_else(|_| panic!("Bad OBJ vertex: {}", line)))
+                        .collect();
+                    if c.len() != 3 {
+                        panic!("Bad OBJ vertex: {}", line);
+                    }
+                    mesh.positions.push(Vector3::new(c[0], c[1], c[2]).component_mul(&scale));
+                },
+                Some("f") => {
+                    // Vertex index first, before any texture coordinate and normal. Counting
+                    // from 1, or back from the latest vertex if negative.
+                    let n = mesh.positions.len() as i64;
+                    let face: Vec<usize> = words
+                        .map(|w| match w.split('/').next().and_then(|i| i.parse::<i64>().ok()) {
+                            Some(i) if i > 0 && i <= n => (i - 1) as usize,
+                            Some(i) if i < 0 && -i <= n => (n + i) as usize,
+                            _ => panic!("Bad OBJ face: {}", line),
+                        })
+                        .collect();
+                    if face.len() >= 3 {
+                        mesh.faces.push(face);
+                    }
+                },
+                _ => {},
+            }
+        }
+        mesh
+    }
+
+    /// Crease edges between faces turning by more than `degrees`, infinitely
+    pub fn with_crease_angle(mut self, degrees: f64) -> PolyMesh {
+        let normals: Vec<Vector3<f64>> = (0..self.faces.len()).map(|f| self.face_normal(f).normalize()).collect();
+        let limit = degrees.to_radians().cos();
+        for (e, faces) in Adjacency::new(&self).edge_faces {
+            if faces.len() == 2 && normals[faces[0]].dot(&normals[faces[1]]) < limit {
+                self.creases.insert(e, f64::INFINITY);
+            }
+        }
+        self
+    }
+
+    /// Crease the edge between vertices `a` and `b`, for `sharpness` levels
+    pub fn with_crease(mut self, a: usize, b: usize, sharpness: f64) -> PolyMesh {
+        self.creases.insert(edge(a, b), sharpness);
+        self
+    }
+
+    pub fn is_triangles(&self) -> bool {
+        self.faces.iter().all(|f| f.len() == 3)
+    }
+
+    /// Loop for triangle meshes, otherwise Catmull–Clark
+    pub fn default_scheme(&self) -> Scheme {
+        if self.is_triangles() { Scheme::Loop } else { Scheme::CatmullClark }
+    }
+
+    pub fn subdivide(&self, scheme: Scheme, levels: usize) -> PolyMesh {
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = match scheme {
+                Scheme::Loop => mesh.loop_step(),
+                Scheme::CatmullClark => mesh.catmull_clark_step(),
+            };
+        }
+        mesh
+    }
+
+    /// Boundaries, and edges shared by more than two faces, are always sharp
+    fn sharpness(&self, adj: &Adjacency, e: &Edge) -> f64 {
+        if adj.edge_faces[e].len() != 2 {
+            return f64::INFINITY;
+        }
+        self.creases.get(e).cloned().unwrap_or(0.)
+    }
+
+    /// Sharp edges at a vertex, and how sharp the vertex is (their mean sharpness)
+    fn sharp_edges(&self, adj: &Adjacency, v: usize) -> (Vec<Edge>, f64) {
+        let sharp: Vec<(Edge, f64)> = adj.vertex_edges[v].iter()
+            .map(|e| (*e, self.sharpness(adj, e)))
+            .filter(|(_, s)| *s > 0.)
+            .collect();
+        let mean = sharp.iter().map(|(_, s)| s).sum::<f64>() / sharp.len().max(1) as f64;
+        (sharp.into_iter().map(|(e, _)| e).collect(), mean)
+    }
+
+    /// A vertex moved by the smooth rule, or along a crease or held at a corner
+    fn vertex_point(&self, adj: &Adjacency, v: usize, smooth: Vector3<f64>) -> Vector3<f64> {
+        let p = self.positions[v];
+        let (sharp, sharpness) = self.sharp_edges(adj, v);
+        match sharp.len() {
+            // Smooth, or a dart ending a crease
+            0 | 1 => smooth,
+            2 => {
+                let ends = self.positions[other(&sharp[0], v)] + self.positions[other(&sharp[1], v)];
+                blend(smooth, p * 0.75 + ends * 0.125, sharpness)
+            },
+            _ => blend(smooth, p, sharpness),
+        }
+    }
+
+    /// Creases of the two halves of each edge, one level less sharp
+    fn child_creases(&self, adj: &Adjacency, midpoint: &HashMap<Edge, usize>) -> HashMap<Edge, f64> {
+        let mut creases = HashMap::new();
+        for (e, s) in &self.creases {
+            if *s > 1. && adj.edge_faces.contains_key(e) {
+                let m = midpoint[e];
+                creases.insert(edge(e.0, m), s - 1.);
+                creases.insert(edge(m, e.1), s - 1.);
+            }
+        }
+        creases
+    }
+
+    /// Edges in a fixed order, numbered from `first`
+    fn number_edges(adj: &Adjacency, first: usize) -> (Vec<Edge>, HashMap<Edge, usize>) {
+        let mut edges: Vec<Edge> = adj.edge_faces.keys().cloned().collect();
+        edges.sort();
+        let index = edges.iter().enumerate().map(|(i, e)| (*e, first + i)).collect();
+        (edges, index)
+    }
+
+    fn centroid(&self, f: usize) -> Vector3<f64> {
+        let face = &self.faces[f];
+        face.iter().map(|&v| self.positions[v]).sum::<Vector3<f64>>() / face.len() as f64
+    }
+
+    fn catmull_clark_step(&self) -> PolyMesh {
+        let adj = Adjacency::new(self);
+        let nv = self.positions.len();
+        let (edges, edge_index) = PolyMesh::number_edges(&adj, nv);
+        let face_points: Vec<Vector3<f64>> = (0..self.faces.len()).map(|f| self.centroid(f)).collect();
+
+        let mut positions: Vec<Vector3<f64>> = (0..nv).map(|v| {
+            let n = adj.vertex_edges[v].len();
+            if n == 0 {
+                return self.positions[v];
+            }
+            let faces = &adj.vertex_faces[v];
+            let q = faces.iter().map(|&f| face_points[f]).sum::<Vector3<f64>>() / faces.len().max(1) as f64;
+            let r = adj.vertex_edges[v].iter()
+                .map(|e| (self.positions[e.0] + self.positions[e.1]) / 2.)
+                .sum::<Vector3<f64>>() / n as f64;
+            let n = n as f64;
+            let smooth = (q + r * 2. + self.positions[v] * (n - 3.)) / n;
+            self.vertex_point(&adj, v, smooth)
+        }).collect();
+
+        positions.extend(edges.iter().map(|e| {
+            let mid = (self.positions[e.0] + self.positions[e.1]) / 2.;
+            let faces = &adj.edge_faces[e];
+            if faces.len() != 2 {
+                return mid;
+            }
+            let smooth = (mid + (face_points[faces[0]] + face_points[faces[1]]) / 2.) / 2.;
+            blend(smooth, mid, self.sharpness(&adj, e))
+        }));
+        positions.extend(face_points.iter());
+
+        let first_face_point = nv + edges.len();
+        let faces = self.faces.iter().enumerate().flat_map(|(f, face)| {
+            let n = face.len();
+            let edge_index = &edge_index;
+            (0..n).map(move |i| {
+                let (prev, v, next) = (face[(i + n - 1) % n], face[i], face[(i + 1) % n]);
+                vec![v, edge_index[&edge(v, next)], first_face_point + f, edge_index[&edge(prev, v)]]
+            })
+        }).collect();
+
+        PolyMesh { positions, faces, creases: self.child_creases(&adj, &edge_index) }
+    }
+
+    fn loop_step(&self) -> PolyMesh {
+        assert!(self.is_triangles(), "Loop subdivision needs a mesh of triangles");
+        let adj = Adjacency::new(self);
+        let nv = self.positions.len();
+        let (edges, edge_index) = PolyMesh::number_edges(&adj, nv);
+
+        let mut positions: Vec<Vector3<f64>> = (0..nv).map(|v| {
+            let n = adj.vertex_edges[v].len();
+            if n == 0 {
+                return self.positions[v];
+            }
+            let ring = adj.vertex_edges[v].iter()
+                .map(|e| self.positions[other(e, v)])
+                .sum::<Vector3<f64>>();
+            let n = n as f64;
+            let beta = (0.625 - (0.375 + 0.25 * (2. * f64::consts::PI / n).cos()).powi(2)) / n;
+            let smooth = self.positions[v] * (1. - n * beta) + ring * beta;
+            self.vertex_point(&adj, v, smooth)
+        }).collect();
+
+        positions.extend(edges.iter().map(|e| {
+            let (a, b) = (self.positions[e.0], self.positions[e.1]);
+            let faces = &adj.edge_faces[e];
+            if faces.len() != 2 {
+                return (a + b) / 2.;
+            }
+            // The vertices of the two triangles across from the edge
+            let opposite = faces.iter()
+                .map(|&f| self.faces[f].iter().find(|&&v| v != e.0 && v != e.1).unwrap())
+                .map(|&v| self.positions[v])
+                .sum::<Vector3<f64>>();
+            let smooth = (a + b) * 0.375 + opposite * 0.125;
+            blend(smooth, (a + b) / 2., self.sharpness(&adj, e))
+        }));
+
+        let faces = self.faces.iter().flat_map(|face| {
+            let [a, b, c] = [face[0], face[1], face[2]];
+            let (ab, bc, ca) = (edge_index[&edge(a, b)], edge_index[&edge(b, c)], edge_index[&edge(c, a)]);
+            [vec![a, ab, ca], vec![b, bc, ab], vec![c, ca, bc], vec![ab, bc, ca]]
+        }).collect();
+
+        PolyMesh { positions, faces, creases: self.child_creases(&adj, &edge_index) }
+    }
+
+    /// Normal scaled by area, by Newell's method, for faces that aren't flat
+    fn face_normal(&self, f: usize) -> Vector3<f64> {
+        let face = &self.faces[f];
+        (0..face.len())
+            .map(|i| self.positions[face[i]].cross(&self.positions[face[(i + 1) % face.len()]]))
+            .sum::<Vector3<f64>>() / 2.
+    }
+
+    /// A normal at each corner of each face: the mean of the faces around the vertex that
+    /// join this one without crossing a crease.
+    fn corner_normals(&self) -> Vec<Vec<Vector3<f64>>> {
+        let adj = Adjacency::new(self);
+        let face_normals: Vec<Vector3<f64>> = (0..self.faces.len()).map(|f| self.face_normal(f)).collect();
+        let mut corners: Vec<Vec<Vector3<f64>>> = self.faces.iter().map(|f| vec![Vector3::zeros(); f.len()]).collect();
+
+        for v in 0..self.positions.len() {
+            let faces = &adj.vertex_faces[v];
+            // Join the faces around the vertex into fans between creases
+            let mut fan: Vec<usize> = (0..faces.len()).collect();
+            for e in &adj.vertex_edges[v] {
+                if self.sharpness(&adj, e) > 0. {
+                    continue;
+                }
+                let across = &adj.edge_faces[e];
+                let (a, b) = (faces.iter().position(|f| *f == across[0]), faces.iter().position(|f| *f == across[1]));
+                if let (Some(a), Some(b)) = (a, b) {
+                    let (ra, rb) = (root(&mut fan, a), root(&mut fan, b));
+                    fan[ra] = rb;
+                }
+            }
+            let mut sums: HashMap<usize, Vector3<f64>> = HashMap::new();
+            for (i, f) in faces.iter().enumerate() {
+                *sums.entry(root(&mut fan, i)).or_insert_with(Vector3::zeros) += face_normals[*f];
+            }
+            for (i, f) in faces.iter().enumerate() {
+                let n = sums[&root(&mut fan, i)].normalize();
+                for (k, _) in self.faces[*f].iter().enumerate().filter(|(_, u)| **u == v) {
+                    corners[*f][k] = n;
+                }
+            }
+        }
+        corners
+    }
+
+    /// Faces split into fans of triangles, as vertex indices into each face
+    fn triangle_corners(&self) -> impl Iterator<Item = (usize, [usize; 3])> + '_ {
+        self.faces.iter().enumerate()
+            .flat_map(|(f, face)| (1..face.len() - 1).map(move |i| (f, [0, i, i + 1])))
+    }
+
+    pub fn to_mesh(&self) -> Mesh {
+        Mesh::new(self.triangle_corners()
+            .map(|(f, c)| {
+                let [a, b, c] = c.map(|k| self.positions[self.faces[f][k]]);
+                Arc::new(Triangle::new(a, b, c))
+            })
+            .collect())
+    }
+
+    /// Smoothly shaded, except across creases
+    pub fn to_smooth_mesh(&self) -> SmoothMesh {
+        let normals = self.corner_normals();
+        SmoothMesh::new(self.triangle_corners()
+            .map(|(f, c)| {
+                let [a, b, c2] = c.map(|k| self.positions[self.faces[f][k]]);
+                let [na, nb, nc] = c.map(|k| normals[f][k]);
+                Arc::new(SmoothTriangle::new(a, b, c2, na, nb, nc))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::geometry::Geometry;
+
+    fn cube() -> PolyMesh {
+        PolyMesh::parse_obj("
+            v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1
+            v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1
+            f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8
+        ", Vector3::new(1., 1., 1.))
+    }
+
+    #[test]
+    fn test_parse_obj() {
+        let m = PolyMesh::parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1/1/1 2/2/2 -2 -1\n", Vector3::new(2., 1., 1.));
+        assert_eq!(m.faces, vec![vec![0, 1, 2, 3]]);
+        assert_eq!(m.positions[2], Vector3::new(2., 1., 0.));
+        assert_eq!(cube().default_scheme(), Scheme::CatmullClark);
+    }
+
+    #[test]
+    fn test_catmull_clark_cube() {
+        let once = cube().subdivide(Scheme::CatmullClark, 1);
+        assert_eq!((once.positions.len(), once.faces.len()), (26, 24));
+        // A corner: (Q + 2R) / 3, from face points at 1/3 and edge midpoints at 2/3
+        assert!((once.positions[6] - Vector3::new(5., 5., 5.) / 9.).norm() < 1e-12);
+
+        // Rounds towards a sphere, with faces still facing out
+        let smooth = cube().subdivide(Scheme::CatmullClark, 3);
+        let radii: Vec<f64> = smooth.positions.iter().map(|p| p.norm()).collect();
+        let (lo, hi) = radii.iter().fold((f64::INFINITY, 0f64), |(lo, hi), r| (lo.min(*r), hi.max(*r)));
+        assert!(hi - lo < 0.15, "{} {}", lo, hi);
+        assert!((0..smooth.faces.len()).all(|f| smooth.face_normal(f).dot(&smooth.centroid(f)) > 0.));
+    }
+
+    #[test]
+    fn test_creases() {
+        // Every edge sharp keeps the cube
+        let sharp = cube().with_crease_angle(45.).subdivide(Scheme::CatmullClark, 2);
+        assert!(sharp.positions.iter().all(|p| (p.abs().max() - 1.).abs() < 1e-12));
+        let normals = sharp.to_smooth_mesh();
+        assert_eq!(normals.area(), 24.);
+
+        // One level of sharpness rounds the edge less than none
+        let corner_of = |m: PolyMesh| m.subdivide(Scheme::CatmullClark, 3).positions[6].x;
+        let soft = cube().with_crease(6, 7, 1.).with_crease(6, 2, 1.).with_crease(6, 5, 1.);
+        assert!(corner_of(soft) > corner_of(cube()));
+    }
+
+    #[test]
+    fn test_loop() {
+        let octahedron = PolyMesh::parse_obj("
+            v 1 0 0\nv -1 0 0\nv 0 1 0\nv 0 -1 0\nv 0 0 1\nv 0 0 -1
+            f 1 3 5\nf 3 2 5\nf 2 4 5\nf 4 1 5\nf 3 1 6\nf 2 3 6\nf 4 2 6\nf 1 4 6
+        ", Vector3::new(1., 1., 1.));
+        let once = octahedron.subdivide(Scheme::Loop, 1);
+        assert_eq!((once.positions.len(), once.faces.len()), (18, 32));
+        // Valence 4: β = 31/256, and the neighbours cancel out
+        assert!((once.positions[0] - Vector3::new(132. / 256., 0., 0.)).norm() < 1e-12);
+        // Edge (1, 3): 3/8 of each end, 1/8 of each across
+        let mid = once.positions.iter().find(|p| p.x > 0. && p.y > 0. && p.z.abs() < 1e-12).unwrap();
+        assert!((mid - Vector3::new(0.375, 0.375, 0.)).norm() < 1e-12);
+
+        // An open sheet keeps its boundary on the line it was on
+        let sheet = PolyMesh::parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0.5 0.5 1\nf 1 2 5\nf 2 3 5\nf 3 4 5\nf 4 1 5\n", Vector3::new(1., 1., 1.))
+            .subdivide(Scheme::Loop, 2);
+        let boundary = sheet.positions.iter().filter(|p| p.z == 0.).count();
+        assert_eq!(boundary, 16);
+        assert!(sheet.positions.iter().all(|p| p.z >= 0.));
+    }
+}
@@ -0,0 +1,222 @@
+use crate::na::{Vector2, Vector3};
+use crate::ray::Ray;
+use crate::intersection::RawIntersection;
+use crate::shapes::bbox::BBox;
+use crate::shapes::geometry::Geometry;
+use crate::geometry::rand;
+use std::f64;
+
+/// A ring around the y axis through the origin: a tube of `tube_radius` swept around a
+/// circle of `radius`. Place it in the scene with a `Transform`.
+pub struct Torus {
+    pub radius: f64,
+    pub tube_radius: f64,
+}
+
+impl Torus {
+    pub fn new(radius: f64, tube_radius: f64) -> Torus {
+        Torus { radius, tube_radius }
+    }
+
+    /// Coefficients (highest power first, the first being 1) of the quartic whose roots are
+    /// where a ray with a unit direction meets the torus:
+    ///     (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + z^2)
+    fn quartic(&self, ro: &Vector3<f64>, rd: &Vector3<f64>) -> [f64; 5] {
+        let r2 = self.radius * self.radius;
+        let m = ro.dot(rd);
+        let q = ro.dot(ro) + r2 - self.tube_radius * self.tube_radius;
+        [
+            1.,
+            4. * m,
+            4. * m * m + 2. * q - 4. * r2 * (rd.x * rd.x + rd.z * rd.z),
+            4. * m * q - 8. * r2 * (ro.x * rd.x + ro.z * rd.z),
+            q * q - 4. * r2 * (ro.x * ro.x + ro.z * ro.z),
+        ]
+    }
+}
+
+fn evaluate(c: &[f64; 5], t: f64) -> f64 {
+    (((c[0] * t + c[1]) * t + c[2]) * t + c[3]) * t + c[4]
+}
+
+fn derivative(c: &[f64; 5], t: f64) -> f64 {
+    ((4. * c[0] * t + 3. * c[1]) * t + 2. * c[2]) * t + c[3]
+}
+
+/// Real roots of x^2 + b x + c
+fn solve_quadratic(b: f64, c: f64) -> Vec<f64> {
+    let d = b * b - 4. * c;
+    if d < 0. {
+        return vec![];
+    }
+    let d = d.sqrt();
+    vec![(-b - d) / 2., (-b + d) / 2.]
+}
+
+/// Largest real root of x^3 + a x^2 + b x + c
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // Depressed to s^3 + p s + q with x = s - a/3
+    let p = b - a * a / 3.;
+    let q = 2. * a * a * a / 27. - a * b / 3. + c;
+    let d = q * q / 4. + p * p * p / 27.;
+    let s = if d >= 0. {
+        (-q / 2. + d.sqrt()).cbrt() + (-q / 2. - d.sqrt()).cbrt()
+    } else {
+        // Three real roots, the largest of which is at k = 0
+        let m = 2. * (-p / 3.).sqrt();
+        m * ((3. * q / (p * m)).clamp(-1., 1.).acos() / 3.).cos()
+    };
+    s - a / 3.
+}
+
+/// Real roots of a quartic with c[0] = 1, by Ferrari's method
+///
+/// ## References
+/// 1. https://en.wikipedia.org/wiki/Quartic_function#Ferrari's_solution
+fn solve_quartic(c: &[f64; 5]) -> Vec<f64> {
+    let (a, b, cc, d) = (c[1], c[2], c[3], c[4]);
+    // Depressed to y^4 + p y^2 + q y + r with x = y - a/4
+    let a2 = a * a;
+    let p = b - 3. * a2 / 8.;
+    let q = cc - a * b / 2. + a2 * a / 8.;
+    let r = d - a * cc / 4. + a2 * b / 16. - 3. * a2 * a2 / 256.;
+
+    let ys = if q.abs() < 1e-12 {
+        // Biquadratic, a quadratic in y^2
+        solve_quadratic(p, r).into_iter()
+            .filter(|z| *z >= 0.)
+            .flat_map(|z| [-z.sqrt(), z.sqrt()])
+            .collect()
+    } else {
+        // (y^2 + p/2 + m)^2 = (s y - q / 2s)^2 where s = sqrt(2m), for a root m of the
+        // resolvent cubic. When q != 0 it has a positive root.
+        let m = largest_cubic_root(p, p * p / 4. - r, -q * q / 8.);
+        if m <= 0. {
+            return vec![];
+        }
+        let s = (2. * m).sqrt();
+        let mut ys = solve_quadratic(-s, p / 2. + m + q / (2. * s));
+        ys.append(&mut solve_quadratic(s, p / 2. + m - q / (2. * s)));
+        ys
+    };
+    ys.into_iter().map(|y| y - a / 4.).collect()
+}
+
+impl Geometry for Torus {
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        let rdn = r.rd.normalize();
+        // The closed form roots lose precision far from the origin, so start the ray near
+        // where it enters the bounding sphere (but not on it, where the root would be 0).
+        let bound = self.radius + self.tube_radius;
+        let b = r.ro.dot(&rdn);
+        let d = b * b - (r.ro.dot(&r.ro) - bound * bound);
+        if d < 0. || -b + d.sqrt() < 0. {
+            return None;
+        }
+        let start = (-b - d.sqrt() - bound).max(0.);
+        let ro = r.ro + rdn * start;
+
+        let coefficients = self.quartic(&ro, &rdn);
+        let dist = solve_quartic(&coefficients).into_iter()
+            .map(|mut t| {
+                // Polish the root
+                for _ in 0..2 {
+                    let dt = derivative(&coefficients, t);
+                    if dt.abs() > f64::EPSILON {
+                        t -= evaluate(&coefficients, t) / dt;
+                    }
+                }
+                t
+            })
+            .filter(|t| *t > 1e-9)
+            .fold(f64::INFINITY, f64::min);
+        if !dist.is_finite() {
+            return None;
+        }
+        let dist = start + dist;
+        let point = r.ro + rdn * dist;
+
+        // Angle around the ring (u) and around the tube (v)
+        let rho = (point.x * point.x + point.z * point.z).sqrt().max(f64::EPSILON);
+        let phi = point.z.atan2(point.x);
+        let theta = point.y.atan2(rho - self.radius);
+        let turn = |a: f64| a / (2. * f64::consts::PI) + if a < 0. { 1. } else { 0. };
+        let uv = Vector2::new(turn(phi), turn(theta));
+        // Out from the middle of the tube
+        let centre = Vector3::new(point.x, 0., point.z) * (self.radius / rho);
+        let normal = (point - centre).normalize();
+        let dpdu = Vector3::new(-point.z, 0., point.x) * 2. * f64::consts::PI;
+        let dpdv = Vector3::new(
+            -theta.sin() * phi.cos(), theta.cos(), -theta.sin() * phi.sin()
+        ) * self.tube_radius * 2. * f64::consts::PI;
+        let dndu = Vector3::new(-normal.z, 0., normal.x) * 2. * f64::consts::PI;
+        let dndv = dpdv / self.tube_radius;
+
+        Some(RawIntersection::new(dist, point, normal, rdn.dot(&normal) < 0.)
+             .with_uv(uv, dpdu, dpdv)
+             .with_normal_derivatives(dndu, dndv))
+    }
+
+    fn bounds(&self) -> BBox {
+        let w = self.radius + self.tube_radius;
+        BBox::new(Vector3::new(-w, -self.tube_radius, -w), Vector3::new(w, self.tube_radius, w))
+    }
+
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        let rho = (p.x * p.x + p.z * p.z).sqrt();
+        (rho - self.radius).powi(2) + p.y * p.y < self.tube_radius * self.tube_radius
+    }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        // The outside of the ring has more area than the inside, so pick the angle around
+        // the tube in proportion
+        let theta = loop {
+            let theta = rand() * 2. * f64::consts::PI;
+            if rand() * (self.radius + self.tube_radius) <= self.radius + self.tube_radius * theta.cos() {
+                break theta;
+            }
+        };
+        let phi = rand() * 2. * f64::consts::PI;
+        let normal = Vector3::new(theta.cos() * phi.cos(), theta.sin(), theta.cos() * phi.sin());
+        let centre = Vector3::new(phi.cos(), 0., phi.sin()) * self.radius;
+        Some((centre + normal * self.tube_radius, normal))
+    }
+
+    fn area(&self) -> f64 {
+        4. * f64::consts::PI * f64::consts::PI * self.radius * self.tube_radius
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quartic_roots() {
+        // (x - 1)(x - 2)(x + 3)(x - 0.5)
+        let mut roots = solve_quartic(&[1., -0.5, -7., 9.5, -3.]);
+        roots.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (r, expected) in roots.iter().zip([-3., 0.5, 1., 2.]) {
+            assert!((r - expected).abs() < 1e-6, "{:?}", roots);
+        }
+    }
+
+    #[test]
+    fn test_torus() {
+        let t = Torus::new(2., 0.5);
+        // Through the hole
+        assert!(t.intersects(&Ray::new(Vector3::new(0., 10., 0.), Vector3::new(0., -1., 0.))).is_none());
+        // Across the ring, from far away
+        let i = t.intersects(&Ray::new(Vector3::new(-1000., 0., 0.), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((i.dist - 997.5).abs() < 1e-6);
+        assert!((i.normal - Vector3::new(-1., 0., 0.)).norm() < 1e-6);
+        assert!(i.front_face);
+        // Down onto the top of the tube
+        let top = t.intersects(&Ray::new(Vector3::new(0., 10., 2.), Vector3::new(0., -1., 0.))).unwrap();
+        assert!((top.point - Vector3::new(0., 0.5, 2.)).norm() < 1e-6);
+        assert!((top.uv.y - 0.25).abs() < 1e-6);
+        assert!(top.dpdv.dot(&top.normal).abs() < 1e-6);
+        assert!(t.inside(&Vector3::new(2.2, 0.1, 0.)));
+        assert!(!t.inside(&Vector3::new(0., 0., 0.)));
+    }
+}
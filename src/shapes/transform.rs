@@ -6,37 +6,241 @@ use std::f64;
 use crate::shapes::geometry::Geometry;
 use std::sync::Arc;
 use crate::na::geometry::{Affine3, Rotation3};
+use crate::na::{Vector3, Point3, Matrix3, Matrix4, UnitQuaternion};
 
+/// A transform split into translation, rotation and stretch (the polar decomposition), which
+/// unlike the matrix itself can be interpolated without shearing or shrinking in between.
+#[derive(Clone, Copy)]
+struct Decomposed {
+    translation: Vector3<f64>,
+    rotation: UnitQuaternion<f64>,
+    stretch: Matrix3<f64>,
+}
+
+impl Decomposed {
+    fn new(t: &Affine3<f64>) -> Decomposed {
+        let m = t.matrix();
+        let linear: Matrix3<f64> = m.fixed_view::<3, 3>(0, 0).into();
+        let rotation = Rotation3::from_matrix(&linear);
+        Decomposed {
+            translation: m.fixed_view::<3, 1>(0, 3).into(),
+            rotation: UnitQuaternion::from_rotation_matrix(&rotation),
+            stretch: rotation.matrix().transpose() * linear,
+        }
+    }
+
+    fn lerp(&self, b: &Decomposed, t: f64) -> Affine3<f64> {
+        let rotation = self.rotation.slerp(&b.rotation, t);
+        let linear = rotation.to_rotation_matrix().matrix() * (self.stretch * (1. - t) + b.stretch * t);
+        let translation = self.translation * (1. - t) + b.translation * t;
+        let mut m = Matrix4::identity();
+        m.fixed_view_mut::<3, 3>(0, 0).copy_from(&linear);
+        m.fixed_view_mut::<3, 1>(0, 3).copy_from(&translation);
+        Affine3::from_matrix_unchecked(m)
+    }
+}
+
+/// Places an item in the scene with an affine transform (any combination of translation,
+/// rotation, scale and shear). Points are mapped by the matrix, normals by its inverse
+/// transpose. Transforms nest, so hierarchies are transforms of transforms.
 pub struct Transform {
     pub item: Box<dyn Geometry + Sync + Send>,
     pub transform: Affine3<f64>,
-} 
+    /// For moving objects, the transforms at the start and end of the motion, which replace
+    /// `transform` for rays cast in between.
+    motion: Option<(Decomposed, Decomposed)>,
+    /// Ratio of the transformed surface area to the item's
+    area_scale: f64,
+}
 
 // Affine transformation (invertible)
 impl Transform {
     pub fn new(item: Box<dyn Geometry + Sync + Send>) -> Transform {
-        Transform {
-            item,
-            transform: Affine3::identity()
-        }
+        Transform::affine(item, Affine3::identity())
     }
 
     pub fn rotate(
         item:  Box<dyn Geometry + Sync + Send>,
         roll: f64, pitch: f64, yaw: f64
     ) -> Transform {
+        Transform::affine(item, na::convert(Rotation3::from_euler_angles(roll, pitch, yaw)))
+    }
+
+    pub fn affine(item: Box<dyn Geometry + Sync + Send>, transform: Affine3<f64>) -> Transform {
+        let area_scale = area_scale(&*item, &transform);
         Transform {
             item,
-            transform: na::convert(Rotation3::from_euler_angles(roll, pitch, yaw))
+            transform,
+            motion: None,
+            area_scale,
         }
-    } 
+    }
+
+    /// An item that moves from `start` at time 0 to `end` at time 1.
+    pub fn moving(item: Box<dyn Geometry + Sync + Send>, start: Affine3<f64>, end: Affine3<f64>) -> Transform {
+        let area_scale = area_scale(&*item, &start);
+        Transform {
+            item,
+            transform: start,
+            motion: Some((Decomposed::new(&start), Decomposed::new(&end))),
+            area_scale,
+        }
+    }
+
+    /// The transform at a time (0 - 1)
+    pub fn at(&self, time: f64) -> Affine3<f64> {
+        match &self.motion {
+            Some((start, end)) => start.lerp(end, time.clamp(0., 1.)),
+            None => self.transform,
+        }
+    }
+}
+
+/// Matrix carrying normals through a transform: the inverse transpose, so that they stay
+/// perpendicular to the surface when it's scaled or sheared.
+fn normal_matrix(t: &Affine3<f64>) -> Matrix3<f64> {
+    t.inverse().matrix().fixed_view::<3, 3>(0, 0).transpose()
+}
+
+/// How much a transform scales the surface area of an item. A similarity (rotation and
+/// uniform scale) scales every patch alike; otherwise it depends on which way the surface
+/// faces, so average over points sampled on the surface.
+fn area_scale(item: &(dyn Geometry + Sync + Send), t: &Affine3<f64>) -> f64 {
+    let linear: Matrix3<f64> = t.matrix().fixed_view::<3, 3>(0, 0).into();
+    let det = linear.determinant().abs();
+    let gram = linear.transpose() * linear;
+    let k = gram.trace() / 3.;
+    if (gram - Matrix3::identity() * k).amax() <= k * 1e-9 {
+        return k;
+    }
+    // A patch with normal n and area dA becomes |det| |M^-T n| dA
+    let normals = normal_matrix(t);
+    let samples = 256;
+    let total: f64 = (0 .. samples)
+        .filter_map(|_| item.sample_surface())
+        .map(|(_, n)| det * (normals * n.normalize()).norm())
+        .sum();
+    total / samples as f64
 }
 
 impl Geometry for Transform {
     fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
-        self.item.intersects(&r.inverse_transform(&self.transform))
+        let transform = self.at(r.time);
+        // Shapes measure distance along a unit direction, which scaling doesn't preserve, so
+        // intersect along the unit direction and measure the distance again in the scene.
+        let local = r.inverse_transform(&transform);
+        let local = Ray { rd: local.rd.normalize(), ..local };
+        let mut i = self.item.intersects(&local)?;
+        let normals = normal_matrix(&transform);
+        i.point = transform.transform_point(&Point3::from(i.point)).coords;
+        i.dist = (i.point - r.ro).norm();
+        i.normal = (normals * i.normal).normalize();
+        i.geometric_normal = (normals * i.geometric_normal).normalize();
+        i.dpdu = transform.transform_vector(&i.dpdu);
+        i.dpdv = transform.transform_vector(&i.dpdv);
+        i.dndu = normals * i.dndu;
+        i.dndv = normals * i.dndv;
+        Some(i)
     }
     fn bounds(&self) -> BBox {
-        self.item.bounds().transform(&na::convert(self.transform))
+        let b = self.item.bounds();
+        if self.motion.is_none() {
+            return b.transform(&na::convert(self.transform));
+        }
+        // Cover the whole motion. Rotating corners bulge out between the steps, but by at
+        // most 0.01% of the size for a full turn.
+        let steps = 64;
+        let mut bounds = b.transform(&na::convert(self.at(0.)));
+        for i in 1 ..= steps {
+            bounds = bounds.union(&b.transform(&na::convert(self.at(i as f64 / steps as f64))));
+        }
+        let pad = bounds.size() * 1e-4;
+        BBox::new(bounds.min.coords - pad, bounds.max.coords + pad)
+    }
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.item.inside(&self.transform.inverse_transform_point(&Point3::from(*p)).coords)
+    }
+    fn primitives(&self) -> u64 {
+        self.item.primitives()
+    }
+    // NB. Samples are uniform over the item, so only uniform over the transformed surface
+    // for similarities.
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let (p, n) = self.item.sample_surface()?;
+        let normal = normal_matrix(&self.transform) * n;
+        Some((self.transform.transform_point(&Point3::from(p)).coords, normal.normalize()))
+    }
+    fn area(&self) -> f64 {
+        self.item.area() * self.area_scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::sphere::Sphere;
+    use crate::na::Translation3;
+
+    fn ray_at(x: f64, time: f64) -> Ray {
+        Ray { ro: Vector3::new(x, 0., -10.), rd: Vector3::new(0., 0., 1.), time }
+    }
+
+    #[test]
+    fn test_moving_sphere() {
+        let t = Transform::moving(
+            Box::new(Sphere::new(Vector3::new(0., 0., 0.), 1.)),
+            Affine3::identity(),
+            na::convert(Translation3::new(4., 0., 0.)));
+        assert!(t.intersects(&ray_at(0., 0.)).is_some());
+        assert!(t.intersects(&ray_at(0., 1.)).is_none());
+        let mid = t.intersects(&ray_at(2., 0.5)).unwrap();
+        assert!((mid.point - Vector3::new(2., 0., -1.)).norm() < 1e-9);
+        assert!((mid.dist - 9.).abs() < 1e-9);
+        // The bounds cover the whole path
+        let b = t.bounds();
+        assert!(b.min.x <= -1. && b.max.x >= 5.);
+    }
+
+    #[test]
+    fn test_scaled_sphere() {
+        // An ellipsoid 4 across in x, 1 in y and z, moved to x = 10
+        let scale: Affine3<f64> = Affine3::from_matrix_unchecked(Matrix4::new_nonuniform_scaling(&Vector3::new(2., 0.5, 0.5)));
+        let t = Transform::affine(
+            Box::new(Transform::affine(Box::new(Sphere::new(Vector3::new(0., 0., 0.), 1.)), scale)),
+            na::convert(Translation3::new(10., 0., 0.)));
+        let r = Ray::new(Vector3::new(0., 0., 0.), Vector3::new(1., 0., 0.));
+        let i = t.intersects(&r).unwrap();
+        assert!((i.point - Vector3::new(8., 0., 0.)).norm() < 1e-9);
+        assert!((i.dist - 8.).abs() < 1e-9);
+        assert!(t.inside(&Vector3::new(11.5, 0., 0.)));
+        assert!(!t.inside(&Vector3::new(10., 0.75, 0.)));
+        // Off axis, the normal is steeper than the sphere's would be
+        let r = Ray::new(Vector3::new(11., 10., 0.), Vector3::new(0., -1., 0.));
+        let i = t.intersects(&r).unwrap();
+        // The gradient of (x - 10)^2 / 4 + y^2 / 0.25 + z^2 / 0.25
+        let expected = Vector3::new((i.point.x - 10.) / 2., i.point.y * 8., 0.).normalize();
+        assert!((i.normal - expected).norm() < 1e-9);
+        assert!(i.normal.dot(&i.dpdu).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_scaled_area() {
+        let sphere = || Box::new(Sphere::new(Vector3::new(0., 0., 0.), 1.));
+        let uniform = Transform::affine(sphere(), Affine3::from_matrix_unchecked(Matrix4::new_scaling(3.)));
+        assert!((uniform.area() - 36. * f64::consts::PI).abs() < 1e-9);
+        // A very flat ellipsoid is nearly two discs
+        let flat = Transform::affine(sphere(), Affine3::from_matrix_unchecked(
+            Matrix4::new_nonuniform_scaling(&Vector3::new(1., 0.01, 1.))));
+        assert!((flat.area() / (2. * f64::consts::PI) - 1.).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_interpolated_rotation_is_rigid() {
+        let end: Affine3<f64> = na::convert(Rotation3::from_euler_angles(0., f64::consts::PI / 2., 0.));
+        let t = Transform::moving(Box::new(Sphere::new(Vector3::new(2., 0., 0.), 0.5)), Affine3::identity(), end);
+        let half = t.at(0.5);
+        let p = half.transform_point(&Point3::new(2., 0., 0.));
+        // Halfway round, not cut across the corner
+        assert!((p.coords.norm() - 2.).abs() < 1e-9);
     }
 }
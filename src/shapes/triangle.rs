@@ -3,10 +3,11 @@
 ///
 /// 1. https://www.scratchapixel.com/lessons/3d-basic-rendering/ray-tracing-rendering-a-triangle/barycentric-coordinates
 use crate::shapes::geometry::Geometry;
-use crate::na::{Vector3};
+use crate::na::{Matrix2, Vector2, Vector3};
 use crate::ray::Ray;
 use crate::intersection::RawIntersection;
 use crate::shapes::bbox::BBox;
+use crate::geometry::random_point_on_triangle;
 
 #[derive(Clone, Debug)]
 pub struct Triangle {
@@ -14,6 +15,8 @@ pub struct Triangle {
     pub v1: Vector3<f64>,
     pub v2: Vector3<f64>,
     pub normal: Vector3<f64>,
+    /// Texture coordinates of the vertices; without them uv is barycentric
+    pub texcoords: Option<[Vector2<f64>; 3]>,
 }
 
 fn panic_if_nan(v: Vector3<f64>) {
@@ -36,6 +39,7 @@ impl Triangle {
             v1,
             v2,
             normal,
+            texcoords: None,
         }
     }
     pub fn new_with_normal(v0: Vector3<f64>, v1: Vector3<f64>, v2: Vector3<f64>, normal: Vector3<f64>) -> Triangle{
@@ -44,15 +48,22 @@ impl Triangle {
             v1,
             v2,
             normal,
+            texcoords: None,
         }
     }
 
+    pub fn with_texcoords(mut self, texcoords: [Vector2<f64>; 3]) -> Triangle {
+        self.texcoords = Some(texcoords);
+        self
+    }
+
     pub fn translate_vec3(&self, v: Vector3<f64>) -> Triangle {
         Triangle {
             v0: self.v0 - v,
             v1: self.v1 - v,
             v2: self.v2 - v,
             normal: self.normal,
+            texcoords: self.texcoords,
         }
     }
 }
@@ -62,6 +73,8 @@ const SMALL: f64 = 0.0000001;
 struct IntersectionPoint {
     dist: f64, 
     point: Vector3<f64>,
+    /// Barycentric coordinates: how far the point is towards v1 and v2
+    uv: Vector2<f64>,
 }
 
 fn intersects_dist(v0: Vector3<f64>, v1: Vector3<f64>, v2: Vector3<f64>, r: &Ray) -> Option<IntersectionPoint> {
@@ -90,19 +103,50 @@ fn intersects_dist(v0: Vector3<f64>, v1: Vector3<f64>, v2: Vector3<f64>, r: &Ray
                      
         if dist > 0. {
             let point = r.ro + (r.rd.normalize() * dist);
-            return Some(IntersectionPoint { dist, point })
+            return Some(IntersectionPoint { dist, point, uv: Vector2::new(u, v) })
         }
         None
 }
 
+/// Barycentric derivatives (along v0v1 and v0v2) as derivatives in u and v of the texture
+/// coordinates, or as they are if there are none or they're degenerate.
+///
+/// ## References
+/// 1. PBRT - 3.6.2 Triangle Intersection
+fn texture_space(texcoords: &Option<[Vector2<f64>; 3]>, d1: Vector3<f64>, d2: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let inverse = texcoords.and_then(|[t0, t1, t2]| Matrix2::from_columns(&[t1 - t0, t2 - t0]).try_inverse());
+    match inverse {
+        Some(inv) => (d1 * inv[(0, 0)] + d2 * inv[(1, 0)], d1 * inv[(0, 1)] + d2 * inv[(1, 1)]),
+        None => (d1, d2),
+    }
+}
+
+/// The texture coordinate at barycentric `uv`, and the surface derivatives along it
+fn surface_uv(v0: Vector3<f64>, v1: Vector3<f64>, v2: Vector3<f64>, texcoords: &Option<[Vector2<f64>; 3]>, uv: Vector2<f64>)
+    -> (Vector2<f64>, Vector3<f64>, Vector3<f64>) {
+    let (dpdu, dpdv) = texture_space(texcoords, v1 - v0, v2 - v0);
+    match texcoords {
+        Some([t0, t1, t2]) => (t0 + (t1 - t0) * uv.x + (t2 - t0) * uv.y, dpdu, dpdv),
+        None => (uv, dpdu, dpdv),
+    }
+}
+
 
 impl Geometry for Triangle {
     fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
-        intersects_dist(self.v0, self.v1, self.v2, r).map(|x| RawIntersection {
-                dist: x.dist, 
-                point: x.point,
-                normal: self.normal 
-            })
+        intersects_dist(self.v0, self.v1, self.v2, r).map(|x| {
+            let (uv, dpdu, dpdv) = surface_uv(self.v0, self.v1, self.v2, &self.texcoords, x.uv);
+            RawIntersection::new(x.dist, x.point, self.normal, r.rd.dot(&self.normal) < 0.)
+                .with_uv(uv, dpdu, dpdv)
+        })
+    }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        Some((random_point_on_triangle(self.v0, self.v1, self.v2), self.normal))
+    }
+
+    fn area(&self) -> f64 {
+        triangle_area(self.v0, self.v1, self.v2)
     }
 
     fn bounds(&self) -> BBox {
@@ -138,6 +182,8 @@ pub struct SmoothTriangle {
     pub normalv0: Vector3<f64>,
     pub normalv1: Vector3<f64>,
     pub normalv2: Vector3<f64>,
+    /// Texture coordinates of the vertices; without them uv is barycentric
+    pub texcoords: Option<[Vector2<f64>; 3]>,
 }
 
 
@@ -150,7 +196,12 @@ impl SmoothTriangle {
         normalv1: Vector3<f64>,
         normalv2: Vector3<f64>
     ) -> SmoothTriangle{
-        SmoothTriangle { v0, v1, v2, normalv0, normalv1, normalv2 }
+        SmoothTriangle { v0, v1, v2, normalv0, normalv1, normalv2, texcoords: None }
+    }
+
+    pub fn with_texcoords(mut self, texcoords: [Vector2<f64>; 3]) -> SmoothTriangle {
+        self.texcoords = Some(texcoords);
+        self
     }
 
     /*
@@ -178,11 +229,30 @@ impl SmoothTriangle {
 
 impl Geometry for SmoothTriangle {
     fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
-        intersects_dist(self.v0, self.v1, self.v2, r).map(|x| RawIntersection {
-                dist: x.dist, 
-                point: x.point,
-                normal: self.interpolate_normal(&x) 
-            })
+        intersects_dist(self.v0, self.v1, self.v2, r).map(|x| {
+            let shading = self.interpolate_normal(&x);
+            // Wound to face the same way as the vertex normals
+            let mut normal = (self.v1 - self.v0).cross(&(self.v2 - self.v0)).normalize();
+            if normal.dot(&shading) < 0. {
+                normal = -normal;
+            }
+            let (uv, dpdu, dpdv) = surface_uv(self.v0, self.v1, self.v2, &self.texcoords, x.uv);
+            let (dndu, dndv) = texture_space(&self.texcoords, self.normalv1 - self.normalv0, self.normalv2 - self.normalv0);
+            RawIntersection::new(x.dist, x.point, normal, r.rd.dot(&normal) < 0.)
+                .with_uv(uv, dpdu, dpdv)
+                .with_shading_normal(shading)
+                .with_normal_derivatives(dndu, dndv)
+        })
+    }
+
+    fn sample_surface(&self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        let point = random_point_on_triangle(self.v0, self.v1, self.v2);
+        let normal = self.interpolate_normal(&IntersectionPoint { dist: 0., point, uv: Vector2::zeros() });
+        Some((point, normal.normalize()))
+    }
+
+    fn area(&self) -> f64 {
+        triangle_area(self.v0, self.v1, self.v2)
     }
 
     // Copy of Triangle
@@ -201,3 +271,46 @@ impl Geometry for SmoothTriangle {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triangle_barycentric_uv() {
+        let v0 = Vector3::new(0., 0., 0.);
+        let v1 = Vector3::new(1., 0., 0.);
+        let v2 = Vector3::new(0., 1., 0.);
+        let r = Ray::new(Vector3::new(0.25, 0.5, -1.), Vector3::new(0., 0., 1.));
+        let i = Triangle::new(v0, v1, v2).intersects(&r).unwrap();
+        assert!((i.uv - Vector2::new(0.25, 0.5)).norm() < 1e-9);
+        assert!((v0 + i.dpdu * i.uv.x + i.dpdv * i.uv.y - i.point).norm() < 1e-9);
+        // The normal faces +z, away from the ray
+        assert!(!i.front_face);
+
+        // Vertex normals wound the other way flip the geometric normal to match them
+        let down = Vector3::new(0.1, 0., -1.).normalize();
+        let smooth = SmoothTriangle::new(v0, v1, v2, down, down, down).intersects(&r).unwrap();
+        assert!(smooth.geometric_normal.z < 0.);
+        assert!(smooth.front_face);
+        assert!((smooth.normal - smooth.geometric_normal).norm() > 0.05);
+    }
+
+    #[test]
+    fn test_triangle_texcoords() {
+        let v0 = Vector3::new(0., 0., 0.);
+        let v1 = Vector3::new(2., 0., 0.);
+        let v2 = Vector3::new(0., 2., 0.);
+        // Mirrored, a quarter of the texture
+        let t = [Vector2::new(0.5, 0.), Vector2::new(0., 0.), Vector2::new(0.5, 0.5)];
+        let r = Ray::new(Vector3::new(0.5, 1., -1.), Vector3::new(0., 0., 1.));
+        for i in [
+            Triangle::new(v0, v1, v2).with_texcoords(t).intersects(&r).unwrap(),
+            SmoothTriangle::new(v0, v1, v2, -Vector3::z(), -Vector3::z(), -Vector3::z()).with_texcoords(t).intersects(&r).unwrap(),
+        ] {
+            assert!((i.uv - Vector2::new(0.375, 0.25)).norm() < 1e-9);
+            assert!((i.dpdu - Vector3::new(-4., 0., 0.)).norm() < 1e-9);
+            assert!((i.dpdv - Vector3::new(0., 4., 0.)).norm() < 1e-9);
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use crate::na::{Vector2, Vector3};
+use crate::ray::Ray;
+use crate::intersection::RawIntersection;
+use crate::shapes::bbox::BBox;
+use crate::shapes::geometry::Geometry;
+use crate::color::Color;
+use std::f64;
+use std::fs;
+
+/// Voxels along each side of a brick
+const BRICK: usize = 8;
+
+type Brick = [u8; BRICK * BRICK * BRICK];
+
+/// A grid of cubes, each either empty (0) or holding an index into a palette of materials.
+///
+/// The grid is split into bricks of 8x8x8 voxels, which are only stored where they aren't
+/// empty. Rays step through the bricks, and then through the voxels of bricks that have any,
+/// so large empty spaces are skipped quickly.
+///
+/// ## References
+/// 1. Amanatides, J., Woo, A. (1987). A fast voxel traversal algorithm for ray tracing.
+pub struct VoxelGrid {
+    /// Voxels along x, y and z
+    dims: [usize; 3],
+    /// Bricks along x, y and z
+    brick_dims: [usize; 3],
+    bricks: Vec<Option<Box<Brick>>>,
+    /// Lowest corner of the grid
+    corner: Vector3<f64>,
+    voxel_size: f64,
+    count: usize,
+}
+
+impl VoxelGrid {
+    pub fn new(dims: [usize; 3], corner: Vector3<f64>, voxel_size: f64) -> VoxelGrid {
+        let brick_dims = dims.map(|d| d.div_ceil(BRICK));
+        VoxelGrid {
+            dims, brick_dims, corner, voxel_size,
+            bricks: vec![None; brick_dims.iter().product()],
+            count: 0,
+        }
+    }
+
+    fn brick_index(&self, b: [usize; 3]) -> usize {
+        (b[2] * self.brick_dims[1] + b[1]) * self.brick_dims[0] + b[0]
+    }
+
+    fn voxel_index(v: [usize; 3]) -> usize {
+        (v[2] * BRICK + v[1]) * BRICK + v[0]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: u8) {
+        assert!(x < self.dims[0] && y < self.dims[1] && z < self.dims[2], "Voxel outside of grid");
+        let b = self.brick_index([x / BRICK, y / BRICK, z / BRICK]);
+        let brick = self.bricks[b].get_or_insert_with(|| Box::new([0; BRICK * BRICK * BRICK]));
+        let v = &mut brick[VoxelGrid::voxel_index([x % BRICK, y % BRICK, z % BRICK])];
+        self.count = self.count + (value != 0) as usize - (*v != 0) as usize;
+        *v = value;
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> u8 {
+        if x >= self.dims[0] || y >= self.dims[1] || z >= self.dims[2] {
+            return 0;
+        }
+        match &self.bricks[self.brick_index([x / BRICK, y / BRICK, z / BRICK])] {
+            Some(brick) => brick[VoxelGrid::voxel_index([x % BRICK, y % BRICK, z % BRICK])],
+            None => 0,
+        }
+    }
+
+    /// Palette index of the voxel containing a point
+    pub fn value_at(&self, p: &Vector3<f64>) -> u8 {
+        let v = (p - self.corner) / self.voxel_size;
+        if v.iter().any(|x| *x < 0.) {
+            return 0;
+        }
+        self.get(v.x as usize, v.y as usize, v.z as usize)
+    }
+
+    /// Number of voxels that aren't empty
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The same voxels, with the lowest corner at `corner`
+    pub fn moved_to(mut self, corner: Vector3<f64>) -> VoxelGrid {
+        self.corner = corner;
+        self
+    }
+
+    pub fn voxel_size(&self) -> f64 {
+        self.voxel_size
+    }
+
+    /// Hit where the ray crosses the face of a voxel, along `axis`, into (`entering`) or out of
+    /// a filled voxel. Normals face out of the filled voxel.
+    fn hit(&self, r: &Ray, t: f64, axis: usize, entering: bool) -> RawIntersection {
+        let point = r.ro + r.rd * t;
+        let mut normal = Vector3::zeros();
+        normal[axis] = if entering { -r.rd[axis].signum() } else { r.rd[axis].signum() };
+        // Each face is parameterised over its other two axes, 0 - 1 across the voxel
+        let (ua, va) = ((axis + 1) % 3, (axis + 2) % 3);
+        let local = (point - self.corner) / self.voxel_size;
+        let uv = Vector2::new(local[ua] - local[ua].floor(), local[va] - local[va].floor());
+        let mut dpdu = Vector3::zeros();
+        let mut dpdv = Vector3::zeros();
+        dpdu[ua] = self.voxel_size;
+        dpdv[va] = self.voxel_size;
+        RawIntersection::new(t, point, normal, entering).with_uv(uv, dpdu, dpdv)
+    }
+}
+
+/// Step through the cells of a grid in the order a ray (with a unit direction) passes
+/// through them, between `t0` and `t1`, until `visit` finds something.
+///
+/// `visit` is given the cell, the distance the ray enters it and the axis it entered along.
+#[allow(clippy::too_many_arguments)]
+fn traverse<T, F>(corner: Vector3<f64>, cell: f64, dims: [usize; 3], r: &Ray, t0: f64, t1: f64, axis: usize, mut visit: F) -> Option<T>
+    where F: FnMut([usize; 3], f64, usize) -> Option<T> {
+    let start = (r.ro + r.rd * t0 - corner) / cell;
+    let mut c = [0; 3];
+    let mut next = [f64::INFINITY; 3];
+    let mut delta = [f64::INFINITY; 3];
+    for a in 0..3 {
+        c[a] = (start[a].floor().max(0.) as usize).min(dims[a] - 1);
+        if r.rd[a] != 0. {
+            let boundary = corner[a] + (c[a] + (r.rd[a] > 0.) as usize) as f64 * cell;
+            next[a] = (boundary - r.ro[a]) / r.rd[a];
+            delta[a] = (cell / r.rd[a]).abs();
+        }
+    }
+
+    let (mut t, mut axis) = (t0, axis);
+    loop {
+        if let Some(found) = visit(c, t, axis) {
+            return Some(found);
+        }
+        axis = if next[0] < next[1] {
+            if next[0] < next[2] { 0 } else { 2 }
+        } else if next[1] < next[2] { 1 } else { 2 };
+        t = next[axis];
+        if t >= t1 {
+            return None;
+        }
+        if r.rd[axis] > 0. {
+            c[axis] += 1;
+            if c[axis] >= dims[axis] {
+                return None;
+            }
+        } else {
+            if c[axis] == 0 {
+                return None;
+            }
+            c[axis] -= 1;
+        }
+        next[axis] += delta[axis];
+    }
+}
+
+/// Axes of the sides of a box that a ray enters and leaves through
+fn slab_axes(b: &BBox, r: &Ray) -> (usize, usize) {
+    let (mut near, mut far) = ((0, f64::NEG_INFINITY), (0, f64::INFINITY));
+    for a in 0..3 {
+        if r.rd[a] == 0. {
+            continue;
+        }
+        let (t1, t2) = ((b.min[a] - r.ro[a]) / r.rd[a], (b.max[a] - r.ro[a]) / r.rd[a]);
+        if t1.min(t2) > near.1 { near = (a, t1.min(t2)); }
+        if t1.max(t2) < far.1 { far = (a, t1.max(t2)); }
+    }
+    (near.0, far.0)
+}
+
+impl Geometry for VoxelGrid {
+    fn intersects(&self, r: &Ray) -> Option<RawIntersection> {
+        let r = Ray { ro: r.ro, rd: r.rd.normalize(), time: r.time };
+        let bounds = self.bounds();
+        let (t0, t1) = bounds.ray_range(&r.ro, &r.rd)?;
+        let (entry_axis, exit_axis) = slab_axes(&bounds, &r);
+
+        // From inside a filled voxel, look for the way out instead
+        let filled = self.inside(&r.ro);
+        let brick_size = self.voxel_size * BRICK as f64;
+        traverse(self.corner, brick_size, self.brick_dims, &r, t0, t1, entry_axis, |b, bt, baxis| {
+            match &self.bricks[self.brick_index(b)] {
+                None if filled => Some(self.hit(&r, bt, baxis, false)),
+                None => None,
+                Some(brick) => {
+                    let brick_corner = self.corner + Vector3::new(b[0] as f64, b[1] as f64, b[2] as f64) * brick_size;
+                    traverse(brick_corner, self.voxel_size, [BRICK; 3], &r, bt, t1, baxis, |v, vt, vaxis| {
+                        // Voxels in the part of the last bricks outside the grid are empty
+                        let (x, y, z) = (b[0] * BRICK + v[0], b[1] * BRICK + v[1], b[2] * BRICK + v[2]);
+                        let empty = x >= self.dims[0] || y >= self.dims[1] || z >= self.dims[2]
+                            || brick[VoxelGrid::voxel_index(v)] == 0;
+                        if empty == filled { Some(self.hit(&r, vt, vaxis, !filled)) } else { None }
+                    })
+                },
+            }
+        }).or_else(|| if filled { Some(self.hit(&r, t1, exit_axis, false)) } else { None })
+    }
+
+    fn bounds(&self) -> BBox {
+        let size = Vector3::new(self.dims[0] as f64, self.dims[1] as f64, self.dims[2] as f64) * self.voxel_size;
+        BBox::new(self.corner, self.corner + size)
+    }
+
+    fn primitives(&self) -> u64 {
+        self.count as u64
+    }
+
+    fn inside(&self, p: &Vector3<f64>) -> bool {
+        self.value_at(p) != 0
+    }
+}
+
+/// Read a MagicaVoxel .vox file: the first model in it, and its palette (indexed by voxel
+/// value, so entry 0 is unused). MagicaVoxel is z up, so its y becomes -z here.
+///
+/// ## References
+/// 1. https://github.com/ephtracy/voxel-model/blob/master/MagicaVoxel-file-format-vox.txt
+pub fn load_vox(pth: &str, corner: Vector3<f64>, voxel_size: f64) -> (VoxelGrid, Vec<Color>) {
+    let data = match fs::read(pth) {
+        Ok(d) => d,
+        Err(e) => panic!("Could not load {}: {:?}", pth, e),
+    };
+    parse_vox(&data, corner, voxel_size).unwrap_or_else(|e| panic!("Could not parse {}: {}", pth, e))
+}
+
+fn parse_vox(data: &[u8], corner: Vector3<f64>, voxel_size: f64) -> Result<(VoxelGrid, Vec<Color>), String> {
+    let int = |at: usize| -> Result<usize, String> {
+        data.get(at .. at + 4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(|| "Unexpected end of file".to_string())
+    };
+    if data.get(0..4) != Some(b"VOX ") {
+        return Err("Not a vox file".to_string());
+    }
+
+    let mut size = None;
+    let mut grid = None;
+    // Files without a palette use MagicaVoxel's default, which isn't included here, so fall
+    // back to shades of grey
+    let mut palette: Vec<Color> = (0..256).map(|i| Color::new(1., 1., 1.) * (i as f64 / 255.)).collect();
+    let mut at = 8;
+    while at + 12 <= data.len() {
+        let id = &data[at .. at + 4];
+        let content = int(at + 4)?;
+        let body = at + 12;
+        match id {
+            // The other chunks are all children of the main chunk, so step into it
+            b"MAIN" => { at = body; continue; },
+            b"SIZE" if size.is_none() => {
+                size = Some([int(body)?, int(body + 4)?, int(body + 8)?]);
+            },
+            b"XYZI" => {
+                match (&grid, size) {
+                    (None, Some([sx, sy, sz])) => {
+                        let mut g = VoxelGrid::new([sx, sz, sy], corner, voxel_size);
+                        let n = int(body)?;
+                        for k in 0..n {
+                            let v = data.get(body + 4 + k * 4 .. body + 8 + k * 4).ok_or("Unexpected end of file")?;
+                            let (x, y, z) = (v[0] as usize, v[1] as usize, v[2] as usize);
+                            if x < sx && y < sy && z < sz {
+                                g.set(x, z, sy - 1 - y, v[3]);
+                            }
+                        }
+                        grid = Some(g);
+                    },
+                    (Some(_), _) => println!("!! [ Warning in VOX parse ] Only the first model is used"),
+                    (None, None) => return Err("Voxels before their size".to_string()),
+                }
+            },
+            b"RGBA" => {
+                for i in 0..255 {
+                    let c = data.get(body + i * 4 .. body + i * 4 + 3).ok_or("Unexpected end of file")?;
+                    palette[i + 1] = Color::new(c[0] as f64, c[1] as f64, c[2] as f64) / 255.;
+                }
+            },
+            _ => {},
+        }
+        at = body + content + int(at + 8)?;
+    }
+    grid.map(|g| (g, palette)).ok_or_else(|| "No voxels".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voxel_hits() {
+        let mut g = VoxelGrid::new([20, 20, 20], Vector3::zeros(), 0.5);
+        g.set(15, 2, 3, 7);
+        g.set(16, 2, 3, 7);
+        assert_eq!(g.len(), 2);
+        // Across several empty bricks, into the side of the first voxel
+        let i = g.intersects(&Ray::new(Vector3::new(-1., 1.25, 1.75), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((i.point.x - 7.5).abs() < 1e-9);
+        assert_eq!(i.normal, Vector3::new(-1., 0., 0.));
+        assert!(i.front_face);
+        assert_eq!(g.value_at(&(i.point - i.normal * 0.25)), 7);
+        // From inside, out the far side of the second
+        let out = g.intersects(&Ray::new(Vector3::new(7.75, 1.25, 1.75), Vector3::new(1., 0., 0.))).unwrap();
+        assert!((out.point.x - 8.5).abs() < 1e-9);
+        assert_eq!(out.normal, Vector3::new(1., 0., 0.));
+        assert!(!out.front_face);
+        // Diagonally down onto the top
+        let top = g.intersects(&Ray::new(Vector3::new(6.25, 3., 0.25), Vector3::new(1., -1., 1.))).unwrap();
+        assert_eq!(top.normal, Vector3::new(0., 1., 0.));
+        assert!(g.intersects(&Ray::new(Vector3::new(-1., 5., 1.75), Vector3::new(1., 0., 0.))).is_none());
+    }
+
+    #[test]
+    fn test_parse_vox() {
+        let chunk = |id: &[u8], content: Vec<u8>| {
+            let mut c = id.to_vec();
+            c.extend((content.len() as i32).to_le_bytes());
+            c.extend(0i32.to_le_bytes());
+            c.extend(content);
+            c
+        };
+        let ints = |v: &[i32]| v.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<u8>>();
+        let mut children = chunk(b"SIZE", ints(&[2, 3, 4]));
+        children.extend(chunk(b"XYZI", [ints(&[1]), vec![1, 0, 3, 2]].concat()));
+        let mut rgba = vec![0; 1024];
+        rgba[4..8].copy_from_slice(&[255, 0, 0, 255]);
+        children.extend(chunk(b"RGBA", rgba));
+
+        let mut data = b"VOX ".to_vec();
+        data.extend(150i32.to_le_bytes());
+        data.extend(b"MAIN");
+        data.extend(0i32.to_le_bytes());
+        data.extend((children.len() as i32).to_le_bytes());
+        data.extend(children);
+
+        let (grid, palette) = parse_vox(&data, Vector3::zeros(), 1.).unwrap();
+        // z up becomes y up, and y becomes -z
+        assert_eq!(grid.dims, [2, 4, 3]);
+        assert_eq!(grid.get(1, 3, 2), 2);
+        assert_eq!(grid.len(), 1);
+        assert_eq!(palette[2], Color::new(1., 0., 0.));
+        assert!(parse_vox(b"PNG ", Vector3::zeros(), 1.).is_err());
+    }
+}
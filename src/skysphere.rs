@@ -43,6 +43,7 @@ use crate::material::model::{MaterialModel, ScatteredRay};
 use crate::shapes::geometry::Geometry;
 use serde_json::{Value};
 use crate::scenefile::SceneFile;
+use crate::lights::directional::DirectionalLight;
 
 // Constants for atmospheric scattering
 const DEFAULT_NUM_SAMPLES: usize = 16;
@@ -175,6 +176,41 @@ impl SkyMaterial {
         (-height / thickness).exp() * segment_length
     }
     
+    /// Fraction of sunlight reaching a point, per RGB channel, after Rayleigh and Mie
+    /// extinction along the path to the sun. Black if the earth is in the way.
+    ///
+    /// This is the same Beer-Lambert attenuation the sky uses for its light rays, so a low
+    /// sun that turns the sky orange also gives the sunlight the same warm colour.
+    ///
+    /// # Arguments
+    /// * `position` - Point to measure from, in sky coordinates
+    fn sun_transmittance(&self, position: Vector3<f64>) -> Vector3<f64> {
+        let light_ray = Ray::new(position, self.sun_direction);
+        if self.earth.intersects(&light_ray).is_some_and(|i| i.dist > 1.) {
+            return Vector3::zeros();
+        }
+        let light_len = match self.atmosphere.intersects(&light_ray) {
+            Some(i) => i.dist,
+            None => return Vector3::new(1., 1., 1.),
+        };
+
+        let num_samples = DEFAULT_NUM_SAMPLES_LIGHT * 8;
+        let segment_length = light_len / num_samples as f64;
+        let mut optical_depth_r = 0.0;
+        let mut optical_depth_m = 0.0;
+        for j in 0..num_samples {
+            // Sample the middle of each segment
+            let sample_position = position + ((j as f64 + 0.5) * segment_length) * self.sun_direction;
+            let height = (sample_position - self.atmosphere.center).norm() - self.earth.radius;
+            optical_depth_r += self.optical_depth(height, self.rayleigh_thickness, segment_length);
+            optical_depth_m += self.optical_depth(height, self.mie_thickness, segment_length);
+        }
+
+        let tau = self.rayleigh_coefficients * optical_depth_r +
+                Vector3::new(1., 1., 1.) * self.mie_coefficient * 1.1 * optical_depth_m;
+        Vector3::new((-tau.x).exp(), (-tau.y).exp(), (-tau.z).exp())
+    }
+
     /// Apply a tone mapping function to convert HDR values to displayable values
     ///
     /// # Arguments
@@ -209,8 +245,9 @@ impl MaterialModel for SkyMaterial {
 
         // Check if the ray intersects the earth (if we're above the surface)
         let earth_intersection = self.earth.intersects(r);
-        if r.ro.y > self.earth.radius && earth_intersection.is_some() {
-            ray_max = earth_intersection.unwrap().dist; 
+        if let Some(ei) = earth_intersection
+            && r.ro.y > self.earth.radius {
+            ray_max = ei.dist; 
         }
         
         // Setup for numerical integration
@@ -249,7 +286,7 @@ impl MaterialModel for SkyMaterial {
             optical_depth_m += mie_depth;
             
             // Check if we can see the sun from this position
-            let light_ray = Ray { ro: sample_position, rd: self.sun_direction };
+            let light_ray = Ray { ro: sample_position, rd: self.sun_direction, time: r.time };
             let atmosphere_intersection = self.atmosphere.intersects(&light_ray);
             
             if atmosphere_intersection.is_none() {
@@ -318,14 +355,8 @@ fn get_preset_by_name(name: &str) -> &SkyPreset {
     &SKY_PRESETS[0]
 }
 
-/// Creates a sky sphere object from JSON configuration
-///
-/// # Arguments
-/// * `o` - JSON value containing the sky configuration
-///
-/// # Returns
-/// A SceneObject representing the sky sphere
-pub fn create_sky_sphere(o: &Value) -> SceneObject {
+/// Creates the sky material from JSON configuration
+fn parse_sky_material(o: &Value) -> SkyMaterial {
     let earth_location = SceneFile::parse_vec3_def(o, "earth_location", Vector3::new(0., -6360e3, 0.));
     
     // Handle preset or custom scattering parameters
@@ -350,28 +381,64 @@ pub fn create_sky_sphere(o: &Value) -> SceneObject {
     let rayleigh_thickness = SceneFile::parse_number(&o["rayleigh_thickness"], preset.rayleigh_thickness);
     let mie_thickness = SceneFile::parse_number(&o["mie_thickness"], preset.mie_thickness);
     
+    SkyMaterial {
+        earth: Sphere::new(
+            earth_location,
+            SceneFile::parse_number(&o["earth_radius"], 6360e3),
+        ),
+        atmosphere: Sphere::new(
+            earth_location,
+            SceneFile::parse_number(&o["atmosphere_radius"], 6420e3)
+        ),
+        rayleigh_thickness,
+        mie_thickness,
+        sun_direction: SceneFile::parse_vec3_def(o, "sun_direction", Vector3::new(0., 0.5, 2.)).normalize(),
+        brightness: SceneFile::parse_number(&o["brightness"], 20.),
+        rayleigh_coefficients,
+        mie_coefficient,
+        mie_anisotropy,
+    }
+}
+
+/// Creates a sky sphere object from JSON configuration
+///
+/// # Arguments
+/// * `o` - JSON value containing the sky configuration
+///
+/// # Returns
+/// A SceneObject representing the sky sphere
+pub fn create_sky_sphere(o: &Value) -> SceneObject {
     SceneObject {
         geometry: Box::new(Infinite {}),
-        medium: Box::new(Solid { m: Box::new(SkyMaterial {
-            earth: Sphere::new(
-                earth_location,
-                SceneFile::parse_number(&o["earth_radius"], 6360e3),
-            ),
-            atmosphere: Sphere::new(
-                earth_location,
-                SceneFile::parse_number(&o["atmosphere_radius"], 6420e3)
-            ),
-            rayleigh_thickness,
-            mie_thickness,
-            sun_direction: SceneFile::parse_vec3_def(o, "sun_direction", Vector3::new(0., 0.5, 2.)).normalize(),
-            brightness: SceneFile::parse_number(&o["brightness"], 20.),
-            rayleigh_coefficients,
-            mie_coefficient,
-            mie_anisotropy,
-        }) })
+        medium: Box::new(Solid { m: Box::new(parse_sky_material(o)) })
     }
 }
 
+/// Creates a distant light for the sun of a sky sphere, so that objects are lit and shadowed
+/// from the same direction the sky is, in the colour of the sunlight that makes it through
+/// the atmosphere.
+///
+/// # Arguments
+/// * `o` - JSON value containing the sky configuration. `sun_intensity` scales the light and
+///   `sun_angular_diameter` (degrees) softens its shadows.
+///
+/// # Returns
+/// The sun light, or None if the sun is below the horizon
+pub fn create_sky_sun(o: &Value) -> Option<DirectionalLight> {
+    let sky = parse_sky_material(o);
+    // The scene's origin sits on the earth's surface
+    let transmittance = sky.sun_transmittance(Vector3::zeros());
+    if transmittance.max() <= 0. {
+        return None;
+    }
+    Some(DirectionalLight {
+        direction: -sky.sun_direction,
+        angular_diameter: SceneFile::parse_number(&o["sun_angular_diameter"], 0.53).to_radians(),
+        color: Color::new(transmittance.x, transmittance.y, transmittance.z),
+        intensity: SceneFile::parse_number(&o["sun_intensity"], 1.),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -470,17 +537,35 @@ mod tests {
         // Just verify that a sky sphere was created successfully
         // We test for "infinite" bounds as a characteristic of Infinite geometry
         let bounds = sky_obj.geometry.bounds();
-        assert!(bounds.min.x == std::f64::MIN);
-        assert!(bounds.max.x == std::f64::MAX);
+        assert!(bounds.min.x == f64::MIN);
+        assert!(bounds.max.x == f64::MAX);
         
         // Test basic ray intersection - Infinite should always return Some intersection
-        let test_ray = Ray {
-            ro: Vector3::new(0.0, 0.0, 0.0),
-            rd: Vector3::new(0.0, 0.0, 1.0)
-        };
+        let test_ray = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
         assert!(sky_obj.geometry.intersects(&test_ray).is_some());
     }
     
+    #[test]
+    fn test_sky_sun() {
+        let sun = |direction: &str| {
+            let value: Value = serde_json::from_str(&format!(r#"{{ "sun_direction": {} }}"#, direction)).unwrap();
+            create_sky_sun(&value)
+        };
+
+        // Light travels away from the sun
+        let noon = sun("[0, 1, 0]").unwrap();
+        assert_relative_eq!(noon.direction, Vector3::new(0., -1., 0.), epsilon = 1e-10);
+
+        // Overhead sunlight is barely attenuated, a low sun is dimmer and redder
+        let low = sun("[0, 0.05, 1]").unwrap();
+        assert!(noon.color.rgb.z > 0.7);
+        assert!(low.color.rgb.x < noon.color.rgb.x);
+        assert!(low.color.rgb.x / low.color.rgb.z > noon.color.rgb.x / noon.color.rgb.z);
+
+        // No sunlight at night
+        assert!(sun("[0, -0.5, 1]").is_none());
+    }
+
     #[test]
     fn test_sky_presets() {
         // Test with default preset
@@ -509,8 +594,8 @@ mod tests {
         let sky_obj_custom = create_sky_sphere(&value);
         
         // Both should create valid objects
-        assert!(sky_obj.geometry.intersects(&Ray { ro: Vector3::new(0.0, 0.0, 0.0), rd: Vector3::new(0.0, 0.0, 1.0) }).is_some());
-        assert!(sky_obj_custom.geometry.intersects(&Ray { ro: Vector3::new(0.0, 0.0, 0.0), rd: Vector3::new(0.0, 0.0, 1.0) }).is_some());
+        assert!(sky_obj.geometry.intersects(&Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0))).is_some());
+        assert!(sky_obj_custom.geometry.intersects(&Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0))).is_some());
     }
     
     #[test]
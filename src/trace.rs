@@ -1,40 +1,70 @@
 use crate::color::Color;
 use crate::na::{Vector3};
-use crate::ray::Ray;
+use crate::ray::{Ray, RayDifferential};
 use crate::scene::Scene;
 use crate::intersection::Intersection;
 use std::f64;
 
 // Returns num rays cast, Color
 pub fn trace (r: &Ray, depth: u64, s: &Scene) -> (u64, Color) {
+    trace_differential(r, None, depth, s)
+}
+
+/// Trace a ray with its differentials, which textures use to filter out detail smaller than
+/// the ray's footprint.
+pub fn trace_differential(r: &Ray, d: Option<&RayDifferential>, depth: u64, s: &Scene) -> (u64, Color) {
+    trace_path(r, d, depth, s, false)
+}
+
+/// `lights_sampled` is whether the ray was scattered by a material that sampled the lights rays
+/// can't hit, so emitters that are among those lights have already been counted.
+///
+/// ## References
+/// 1. PBRT - 14.5 Path Tracing
+fn trace_path(r: &Ray, d: Option<&RayDifferential>, depth: u64, s: &Scene, lights_sampled: bool) -> (u64, Color) {
     let closest = s.objects.nearest_intersection(r, f64::INFINITY, 0f64);
 
     match closest {
-        Some(x) => trace_intersection(r, x, depth, s),
-        None => (1, s.render.background),
+        Some(x) => {
+            let x = match d {
+                Some(d) => x.with_differential(d),
+                None => x,
+            };
+            trace_intersection(r, d, x, depth, s, lights_sampled)
+        },
+        None => (1, s.background(&r.rd)),
     }
 }
 
-fn trace_sample(r: &Ray, intersection: &Intersection, depth: u64, s: &Scene) -> (u64, Color){
+fn trace_sample(r: &Ray, d: Option<&RayDifferential>, intersection: &Intersection, depth: u64, s: &Scene, lights_sampled: bool) -> (u64, Color){
     let mut cast = 1;
-    let material = intersection.object.medium.material_at(intersection.point);
+    let material = intersection.object.medium.material_for(intersection);
+    if lights_sampled && material.sampled_emitter() {
+        return (cast, Color::black());
+    }
     let interaction = material.scatter(r, intersection, s);
+    let direct = material.direct(r, intersection, s);
 
     if depth < s.render.max_depth as u64 && interaction.attenuate.as_vec().norm() > s.black_threshold {
         if let Some(ray) = interaction.ray {
-            let (c, col) = trace(&ray, depth + 1, s);
+            let differential = d.and_then(|d| material.scatter_differential(r, d, intersection, &ray));
+            let (c, col) = trace_path(&ray, differential.as_ref(), depth + 1, s, material.samples_lights());
             cast += c;
-            return (cast, interaction.attenuate * col); //.clamp(2.)); // TODO - use emission
+            return (cast, direct + interaction.attenuate * col); //.clamp(2.)); // TODO - use emission
         } else {
-			return (cast, interaction.attenuate);
+			return (cast, direct + interaction.attenuate);
 		}
     }
 	
 	// Too many bounce, fallback to color
-    (cast, (interaction.attenuate * s.render.background))
+    let background = match interaction.ray {
+        Some(ray) => s.background(&ray.rd),
+        None => s.render.background,
+    };
+    (cast, direct + (interaction.attenuate * background))
 }
 
-fn trace_intersection(r: &Ray, intersection: Intersection, depth: u64, scene: &Scene) -> (u64, Color) {
+fn trace_intersection(r: &Ray, d: Option<&RayDifferential>, intersection: Intersection, depth: u64, scene: &Scene, lights_sampled: bool) -> (u64, Color) {
     // Shadow bias -> Move the origin of the intersection point along the normal, in case a
     // floating point error puts it slightly below the surface which would cause a sign flip
     // leading to shadow acne.
@@ -42,7 +72,7 @@ fn trace_intersection(r: &Ray, intersection: Intersection, depth: u64, scene: &S
     biased_intersection.point = intersection.point + (intersection.normal * scene.render.shadow_bias);
 
     let mut cast = 1;
-    let (c, o) = trace_sample(r, &biased_intersection, depth, scene);
+    let (c, o) = trace_sample(r, d, &biased_intersection, depth, scene, lights_sampled);
     cast += c;
     (cast, o) 
 }
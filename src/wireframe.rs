@@ -1,28 +1,30 @@
-use na::Vector3;
-use scene::Scene;
-use rendercontext::RenderContext;
-use color::Color;
-use std::mem;
-use bbox::BBox;
-
+use crate::na::Vector3;
+use crate::scene::Scene;
+use crate::rendercontext::RenderContext;
+use crate::color::Color;
+use crate::shapes::bbox::BBox;
+use crate::shapes::geometry::Geometry;
+
+// Longest line drawn in one piece, in pixels. Longer lines are split in two, so that they
+// follow cameras that bend straight lines (eg. fisheye) and break where the image wraps
+// around (eg. equirectangular).
+const MAX_SEGMENT: f64 = 16.;
+const MAX_SPLITS: usize = 12;
 
 pub fn wireframe(s: &Scene, ctx: &mut RenderContext){
     // Draw wireframe of scene / bounding boxes
-
-    for obj in s.objects.items() { 
-        let b = obj.bounds();
-        draw_bbox(b, obj.get_material(b.min).pigment, s, ctx);
-    }
-
     for b in s.objects.partitions() {
-        draw_bbox(b, Color::white(), s, ctx)
+        draw_bbox(b, Color::new(0.4, 0.4, 0.4), s, ctx)
     }
 
+    for obj in s.objects.items() {
+        draw_bbox(obj.bounds(), Color::new(0., 1., 0.), s, ctx);
+    }
 }
 
 pub fn draw_bbox(b:BBox, c:Color,  s: &Scene, rc:&mut RenderContext) {
-    let trf = b.max;
-    let blc = b.min;
+    let trf = b.max.coords;
+    let blc = b.min.coords;
     draw_line(blc, Vector3::new(blc.x, blc.y, trf.z) , c, s, rc);
     draw_line(blc, Vector3::new(blc.x, trf.y, blc.z) , c, s, rc);
     draw_line(blc, Vector3::new(trf.x, blc.y, blc.z) , c, s, rc);
@@ -41,60 +43,73 @@ pub fn draw_bbox(b:BBox, c:Color,  s: &Scene, rc:&mut RenderContext) {
     draw_line(Vector3::new(trf.x, blc.y, blc.z), Vector3::new(trf.x, trf.y, blc.z), c, s, rc);
 }
 
-pub fn draw_line(pt0: Vector3<f64>, pt1: Vector3<f64>, c:Color, s: &Scene, rc: &mut RenderContext) {
-    // Bresenham’s Algorithm
-    let mut steep = false; 
-
-    let (x0s, y0s) = s.camera.get_coord_for_point(pt0);
-    let (x1s, y1s) = s.camera.get_coord_for_point(pt1);
-    let mut x0 = (x0s * s.width as f64) as i32;
-    let mut y0 = (y0s * s.height as f64) as i32; 
-    let mut x1 = (x1s * s.width as f64) as i32;
-    let mut y1 = (y1s * s.height as f64) as i32;
-
-    if (x0 - x1).abs() < (y0 - y1).abs() { 
-        mem::swap(&mut x0, &mut y0); 
-        mem::swap(&mut x1, &mut y1); 
-        steep = true; 
-    } 
-    if x0 > x1 { 
-        mem::swap(&mut x0, &mut x1); 
-        mem::swap(&mut y0, &mut y1); 
-    } 
+/// Where a point appears on the image, in pixels
+fn project(pt: Vector3<f64>, s: &Scene) -> Option<(f64, f64)> {
+    let (x, y) = s.camera.get_coord_for_point(pt)?;
+    Some((x * s.image.width as f64, y * s.image.height as f64))
+}
 
-    let dx = x1 - x0; 
-    let dy = y1 - y0; 
-    let derror = (dy as f64 /dx as f64).abs(); 
-    let mut error = 0.; 
-    let mut y = y0; 
-    for x in x0..x1 { 
-        if steep { 
-            rc.set_pixel(y as u32, x as u32,c);
-        } else { 
-            rc.set_pixel(x as u32, y as u32,c);
-        } 
+pub fn draw_line(pt0: Vector3<f64>, pt1: Vector3<f64>, c:Color, s: &Scene, rc: &mut RenderContext) {
+    draw_segment(pt0, pt1, project(pt0, s), project(pt1, s), 0, c, s, rc);
+}
 
-        error += derror; 
-        if error > 0.5 { 
-            if y1 > y0 {
-                y = y + 1
-            } else {
-                y = y - 1
+#[allow(clippy::too_many_arguments)]
+fn draw_segment(
+        pt0: Vector3<f64>,
+        pt1: Vector3<f64>,
+        px0: Option<(f64, f64)>,
+        px1: Option<(f64, f64)>,
+        splits: usize,
+        c: Color,
+        s: &Scene,
+        rc: &mut RenderContext,
+    ) {
+    if let (Some((x0, y0)), Some((x1, y1))) = (px0, px1) {
+        let (w, h) = (s.image.width as f64, s.image.height as f64);
+        // Off one side of the image
+        if (x0 < 0. && x1 < 0.) || (x0 > w && x1 > w) || (y0 < 0. && y1 < 0.) || (y0 > h && y1 > h) {
+            return;
+        }
+        let len = (x1 - x0).abs().max((y1 - y0).abs());
+        if len <= MAX_SEGMENT {
+            // Step a pixel at a time along the longer axis
+            let steps = len.ceil().max(1.) as usize;
+            for i in 0 ..= steps {
+                let t = i as f64 / steps as f64;
+                let (x, y) = (x0 + (x1 - x0) * t, y0 + (y1 - y0) * t);
+                if x >= 0. && y >= 0. {
+                    rc.overlay_pixel(x as usize, y as usize, c);
+                }
             }
-            error = error - 1.; 
-        } 
-    } 
-
+            return;
+        }
+    }
+    if px0.is_none() && px1.is_none() {
+        return;
+    }
+    // Split pieces that are too long or partly out of view. Pieces still long after many
+    // splits cross a seam in the image, or pass by the camera, and aren't drawn.
+    if splits >= MAX_SPLITS {
+        return;
+    }
+    let mid = (pt0 + pt1) / 2.;
+    let pxm = project(mid, s);
+    draw_segment(pt0, mid, px0, pxm, splits + 1, c, s, rc);
+    draw_segment(mid, pt1, pxm, px1, splits + 1, c, s, rc);
 }
 
 pub fn draw_point(pt:Vector3<f64>, c:Color, s: &Scene, rc: &mut RenderContext) {
-    let (x, y) = s.camera.get_coord_for_point(pt);
-    draw_coord(x,y,c,s,rc);
+    if let Some((x, y)) = s.camera.get_coord_for_point(pt) {
+        draw_coord(x,y,c,s,rc);
+    }
 }
 
 pub fn draw_coord(x:f64, y:f64, c:Color, s: &Scene, rc: &mut RenderContext){
-    rc.set_pixel(
-        (x * s.width as f64) as u32, 
-        (y * s.height as f64) as u32,
+    if x < 0. || y < 0. {
+        return;
+    }
+    rc.overlay_pixel(
+        (x * s.image.width as f64) as usize,
+        (y * s.image.height as f64) as usize,
         c);
-} 
+}